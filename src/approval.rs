@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use log::info;
+use serde::Deserialize;
+
+/// Outcome of evaluating the decisions on a task against the approval rules of its group
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The rules may still be satisfied by further decisions
+    Pending,
+    Approved,
+    /// The rules can no longer be satisfied
+    Declined,
+}
+
+impl Verdict {
+    /// Combine with another verdict which has to be satisfied as well
+    pub fn and(self, other: Verdict) -> Verdict {
+        match (self, other) {
+            (Verdict::Declined, _) | (_, Verdict::Declined) => Verdict::Declined,
+            (Verdict::Pending, _) | (_, Verdict::Pending) => Verdict::Pending,
+            _ => Verdict::Approved,
+        }
+    }
+}
+
+/// Constraint on the decisions of the devices of a role
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Rule {
+    role: String,
+    /// Devices of the role which need to accept
+    min_accept: Option<u32>,
+    /// Devices of the role which may reject; 0 gives each of them a veto
+    max_reject: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct GroupRules {
+    /// Hex-encoded identifier of the group
+    group_id: String,
+    /// Hex-encoded identifiers of the devices of each role
+    roles: HashMap<String, Vec<String>>,
+    #[serde(rename = "rule")]
+    rules: Vec<Rule>,
+}
+
+impl GroupRules {
+    /// Evaluate `decisions` of the group members; devices which are not members of the group
+    /// cannot accept
+    fn evaluate(&self, decisions: &HashMap<Vec<u8>, Option<bool>>) -> Verdict {
+        let mut verdict = Verdict::Approved;
+        for rule in &self.rules {
+            let (mut accepts, mut rejects, mut undecided) = (0, 0, 0);
+            for device in &self.roles[&rule.role] {
+                match hex::decode(device).ok().and_then(|id| decisions.get(&id)) {
+                    Some(Some(true)) => accepts += 1,
+                    Some(Some(false)) => rejects += 1,
+                    Some(None) => undecided += 1,
+                    None => {}
+                }
+            }
+            if rule.max_reject.is_some_and(|max| rejects > max)
+                || rule.min_accept.is_some_and(|min| accepts + undecided < min)
+            {
+                return Verdict::Declined;
+            }
+            if rule.min_accept.is_some_and(|min| accepts < min) {
+                verdict = Verdict::Pending;
+            }
+        }
+        verdict
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApprovalFile {
+    #[serde(default, rename = "group")]
+    groups: Vec<GroupRules>,
+}
+
+/// Organisational rules for approving the tasks of groups, applied on top of the group
+/// thresholds
+pub struct ApprovalPolicy {
+    /// Rules keyed by group identifiers
+    groups: HashMap<Vec<u8>, GroupRules>,
+}
+
+impl ApprovalPolicy {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let policy = Self::parse(&content)
+            .map_err(|e| format!("Invalid approval policy {}: {}", path.display(), e))?;
+        info!("Loaded approval rules of {} groups", policy.groups.len());
+        Ok(policy)
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let file: ApprovalFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        let mut groups = HashMap::new();
+        for rules in file.groups {
+            let group_id = hex::decode(&rules.group_id)
+                .map_err(|_| format!("Invalid group identifier {}", rules.group_id))?;
+            for (role, devices) in &rules.roles {
+                if let Some(device) = devices.iter().find(|id| hex::decode(id).is_err()) {
+                    return Err(format!("Role {} has an invalid device {}", role, device));
+                }
+            }
+            for rule in &rules.rules {
+                let Some(devices) = rules.roles.get(&rule.role) else {
+                    return Err(format!("Unknown role {}", rule.role));
+                };
+                if rule.min_accept.is_none() && rule.max_reject.is_none() {
+                    return Err(format!("Rule of role {} has no constraint", rule.role));
+                }
+                if rule
+                    .min_accept
+                    .is_some_and(|min| min as usize > devices.len())
+                {
+                    return Err(format!(
+                        "Role {} has fewer devices than it needs to accept",
+                        rule.role
+                    ));
+                }
+            }
+            if groups.insert(group_id, rules).is_some() {
+                return Err(String::from("Duplicate rules of a group"));
+            }
+        }
+        Ok(ApprovalPolicy { groups })
+    }
+
+    /// Evaluate `decisions` of the members of the group with `group_id`; approved if the group
+    /// has no rules
+    pub fn evaluate(&self, group_id: &[u8], decisions: &HashMap<Vec<u8>, Option<bool>>) -> Verdict {
+        self.groups
+            .get(group_id)
+            .map_or(Verdict::Approved, |rules| rules.evaluate(decisions))
+    }
+}
+
+static APPROVAL_POLICY: OnceLock<ApprovalPolicy> = OnceLock::new();
+
+/// Apply the approval rules loaded from `path`
+pub fn init(path: &Path) -> Result<(), String> {
+    let policy = ApprovalPolicy::load(path)?;
+    if APPROVAL_POLICY.set(policy).is_err() {
+        panic!("Approval policy already initialized");
+    }
+    Ok(())
+}
+
+/// Evaluate `decisions` of the members of the group with `group_id` against its approval rules,
+/// if any are configured
+pub fn evaluate(group_id: &[u8], decisions: &HashMap<Vec<u8>, Option<bool>>) -> Verdict {
+    APPROVAL_POLICY.get().map_or(Verdict::Approved, |policy| {
+        policy.evaluate(group_id, decisions)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+        [[group]]
+        group-id = "aa"
+        roles = { officers = ["01", "02"], clerks = ["03", "04", "05"], board = ["06"] }
+
+        [[group.rule]]
+        role = "officers"
+        min-accept = 1
+
+        [[group.rule]]
+        role = "clerks"
+        min-accept = 2
+
+        [[group.rule]]
+        role = "board"
+        max-reject = 0
+    "#;
+
+    fn decisions(decided: &[(u8, bool)]) -> HashMap<Vec<u8>, Option<bool>> {
+        let mut decisions: HashMap<_, _> = (1..=6u8).map(|i| (vec![i], None)).collect();
+        for (device, decision) in decided {
+            decisions.insert(vec![*device], Some(*decision));
+        }
+        decisions
+    }
+
+    #[test]
+    fn roles() {
+        let policy = ApprovalPolicy::parse(POLICY).unwrap();
+        let verdict = |decided: &[(u8, bool)]| policy.evaluate(&[0xaa], &decisions(decided));
+
+        assert_eq!(verdict(&[]), Verdict::Pending);
+        assert_eq!(verdict(&[(3, true), (4, true)]), Verdict::Pending);
+        assert_eq!(
+            verdict(&[(1, false), (2, true), (3, true), (4, true)]),
+            Verdict::Approved
+        );
+        assert_eq!(verdict(&[(1, false), (2, false)]), Verdict::Declined);
+        assert_eq!(verdict(&[(3, false), (4, false)]), Verdict::Declined);
+        assert_eq!(verdict(&[(6, false)]), Verdict::Declined);
+
+        // other groups and devices outside the group are not constrained
+        assert_eq!(
+            policy.evaluate(&[0xbb], &decisions(&[(6, false)])),
+            Verdict::Approved
+        );
+        let mut outside = decisions(&[(1, true), (3, true)]);
+        outside.remove(&vec![0x04]);
+        outside.remove(&vec![0x05]);
+        assert_eq!(policy.evaluate(&[0xaa], &outside), Verdict::Declined);
+    }
+
+    #[test]
+    fn invalid_policies() {
+        let parse = |rules: &str| {
+            ApprovalPolicy::parse(&format!(
+                "[[group]]\ngroup-id = \"aa\"\nroles = {{ a = [\"01\"] }}\n{}",
+                rules
+            ))
+        };
+        assert!(parse("[[group.rule]]\nrole = \"a\"\nmin-accept = 1").is_ok());
+        assert!(parse("[[group.rule]]\nrole = \"b\"\nmin-accept = 1").is_err());
+        assert!(parse("[[group.rule]]\nrole = \"a\"\nmin-accept = 2").is_err());
+        assert!(parse("[[group.rule]]\nrole = \"a\"").is_err());
+        assert!(
+            ApprovalPolicy::parse("[[group]]\ngroup-id = \"xx\"\nroles = {}\nrule = []").is_err()
+        );
+    }
+}
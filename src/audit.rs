@@ -0,0 +1,263 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use log::{error, info};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Digest;
+
+use crate::get_timestamp;
+
+/// Hash preceding the first record of the log
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Security-relevant event; identifiers and digests are hex-encoded
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    DeviceRegistered {
+        device_id: String,
+        name: String,
+    },
+    CertificateRenewed {
+        device_id: String,
+    },
+    DeviceRevoked {
+        device_id: String,
+        requester: Option<String>,
+    },
+    DeviceRemoved {
+        device_id: String,
+        requester: Option<String>,
+    },
+    GroupRemoved {
+        group_id: String,
+        requester: Option<String>,
+    },
+    TaskCancelled {
+        task_id: String,
+        requester: Option<String>,
+    },
+    TaskRestarted {
+        task_id: String,
+        requester: Option<String>,
+    },
+    TaskRequested {
+        task_id: String,
+        task_type: String,
+        name: String,
+        requester: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        group_id: Option<String>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        devices: Vec<String>,
+    },
+    TaskDecided {
+        task_id: String,
+        device_id: String,
+        accept: bool,
+    },
+    /// Protocol (re)started with the devices chosen as active
+    ProtocolStarted {
+        task_id: String,
+        attempt: u32,
+        active_devices: Vec<String>,
+    },
+    TaskFinished {
+        task_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        group_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+        result_sha256: String,
+    },
+    /// Problem in a protocol run and the device held responsible for it, if known
+    DeviceFault {
+        task_id: String,
+        device_id: Option<String>,
+        attempt: u32,
+        round: u16,
+        kind: String,
+    },
+    /// Task failed, expired or was cancelled
+    TaskFailed {
+        task_id: String,
+        reason: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    seq: u64,
+    timestamp: u64,
+    prev_hash: &'a str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Hash of a record, computed over its canonical JSON without the `hash` field
+fn record_hash(record: &Value) -> String {
+    hex::encode(sha2::Sha256::digest(record.to_string()))
+}
+
+/// Append-only log of hash-chained JSON records
+pub struct AuditLog {
+    file: File,
+    seq: u64,
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Open the log at `path`, continuing its chain if it exists and is intact
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let (seq, last_hash) = if path.exists() {
+            verify_chain(path)?
+        } else {
+            (0, GENESIS_HASH.to_string())
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+        Ok(AuditLog {
+            file,
+            seq,
+            last_hash,
+        })
+    }
+
+    pub fn append(&mut self, event: &Event) -> Result<(), String> {
+        let record = Record {
+            seq: self.seq + 1,
+            timestamp: get_timestamp(),
+            prev_hash: &self.last_hash,
+            event,
+        };
+        // converting to a Value sorts the keys, which makes the encoding canonical
+        let mut record = serde_json::to_value(record).map_err(|e| e.to_string())?;
+        let hash = record_hash(&record);
+        record["hash"] = Value::String(hash.clone());
+
+        let mut line = record.to_string();
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| e.to_string())?;
+        self.seq += 1;
+        self.last_hash = hash;
+        Ok(())
+    }
+}
+
+/// Check the chain of the log at `path` and return the number of its records
+pub fn verify(path: &Path) -> Result<u64, String> {
+    verify_chain(path).map(|(seq, _)| seq)
+}
+
+fn verify_chain(path: &Path) -> Result<(u64, String), String> {
+    let file = File::open(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let mut seq = 0;
+    let mut last_hash = GENESIS_HASH.to_string();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let invalid = |reason: &str| format!("Record on line {} {}", index + 1, reason);
+
+        let mut record: Value =
+            serde_json::from_str(&line).map_err(|_| invalid("is not valid JSON"))?;
+        let hash = match record
+            .as_object_mut()
+            .and_then(|record| record.remove("hash"))
+        {
+            Some(Value::String(hash)) => hash,
+            _ => return Err(invalid("has no hash")),
+        };
+        if record["seq"].as_u64() != Some(seq + 1) {
+            return Err(invalid("is out of sequence"));
+        }
+        if record["prev_hash"].as_str() != Some(last_hash.as_str()) {
+            return Err(invalid("does not continue the chain"));
+        }
+        if record_hash(&record) != hash {
+            return Err(invalid("has been modified"));
+        }
+        seq += 1;
+        last_hash = hash;
+    }
+    Ok((seq, last_hash))
+}
+
+static AUDIT_LOG: OnceLock<Mutex<AuditLog>> = OnceLock::new();
+
+/// Start recording events to the log at `path`
+pub fn init(path: &Path) -> Result<(), String> {
+    let log = AuditLog::open(path)?;
+    info!(
+        "Audit log {} opened with {} records",
+        path.display(),
+        log.seq
+    );
+    if AUDIT_LOG.set(Mutex::new(log)).is_err() {
+        panic!("Audit log already initialized");
+    }
+    Ok(())
+}
+
+/// Append `event` to the audit log, if one is configured
+pub fn record(event: Event) {
+    if let Some(log) = AUDIT_LOG.get() {
+        if let Err(e) = log.lock().unwrap().append(&event) {
+            error!("Could not write audit record event={:?} error={}", event, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_event(accept: bool) -> Event {
+        Event::TaskDecided {
+            task_id: String::from("aa"),
+            device_id: String::from("01"),
+            accept,
+        }
+    }
+
+    #[test]
+    fn chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        {
+            let mut log = AuditLog::open(&path).unwrap();
+            log.append(&sample_event(true)).unwrap();
+            log.append(&sample_event(false)).unwrap();
+        }
+        assert_eq!(verify(&path).unwrap(), 2);
+
+        // reopening continues the chain
+        AuditLog::open(&path)
+            .unwrap()
+            .append(&Event::DeviceRevoked {
+                device_id: String::from("01"),
+                requester: Some(String::from("portal")),
+            })
+            .unwrap();
+        assert_eq!(verify(&path).unwrap(), 3);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert!(lines[1].contains("\"event\":\"task_decided\""));
+
+        let tampered = content.replacen("\"accept\":false", "\"accept\":true", 1);
+        std::fs::write(&path, tampered).unwrap();
+        assert!(verify(&path).unwrap_err().contains("line 2"));
+        assert!(AuditLog::open(&path).is_err());
+
+        std::fs::write(&path, [lines[0], lines[2]].join("\n")).unwrap();
+        assert!(verify(&path).is_err());
+    }
+}
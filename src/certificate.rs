@@ -0,0 +1,257 @@
+use openssl::asn1::{Asn1Integer, Asn1Time};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, PKeyRef, Private, Public};
+use openssl::x509::extension::{
+    AuthorityKeyIdentifier, BasicConstraints, KeyUsage, SubjectKeyIdentifier,
+};
+use openssl::x509::{X509Builder, X509NameBuilder, X509Ref};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::config;
+use crate::{CA_CERT, CA_KEY};
+
+/// Curves of GG18 group keys, tried in this order
+const GG18_CURVES: [Nid; 2] = [Nid::X9_62_PRIME256V1, Nid::SECP256K1];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyUsageFlag {
+    DigitalSignature,
+    NonRepudiation,
+    KeyEncipherment,
+    DataEncipherment,
+    KeyAgreement,
+}
+
+/// Contents of certificates issued for group public keys
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupCertificateConfig {
+    /// Country (C) of the subject
+    pub country: Option<String>,
+    /// Organization (O) of the subject
+    pub organization: Option<String>,
+    /// Organizational unit (OU) of the subject
+    pub organizational_unit: Option<String>,
+    pub validity_days: u32,
+    pub key_usage: Vec<KeyUsageFlag>,
+}
+
+impl Default for GroupCertificateConfig {
+    fn default() -> Self {
+        GroupCertificateConfig {
+            country: None,
+            organization: None,
+            organizational_unit: None,
+            validity_days: 365 * 4 + 1,
+            key_usage: vec![KeyUsageFlag::DigitalSignature, KeyUsageFlag::NonRepudiation],
+        }
+    }
+}
+
+/// Issue a certificate signed by the MeeSign CA for a GG18 group public key
+pub fn issue_group_certificate(name: &str, public_key: &[u8]) -> Result<Vec<u8>, String> {
+    let config = &config::get().group_certificate;
+    let public_key = ecdsa_public_key(public_key)?;
+    build_certificate(config, name, &public_key, &CA_CERT, &CA_KEY).map_err(|e| e.to_string())
+}
+
+/// Decode an uncompressed or compressed ECDSA point on one of the GG18 curves
+fn ecdsa_public_key(public_key: &[u8]) -> Result<PKey<Public>, String> {
+    let mut context = BigNumContext::new().map_err(|e| e.to_string())?;
+    for curve in GG18_CURVES {
+        let group = EcGroup::from_curve_name(curve).map_err(|e| e.to_string())?;
+        let key = EcPoint::from_bytes(&group, public_key, &mut context)
+            .and_then(|point| EcKey::from_public_key(&group, &point))
+            .and_then(|key| key.check_key().map(|_| key));
+        if let Ok(key) = key {
+            return PKey::from_ec_key(key).map_err(|e| e.to_string());
+        }
+    }
+    Err("Group key is not a valid ECDSA public key".into())
+}
+
+fn build_certificate(
+    config: &GroupCertificateConfig,
+    name: &str,
+    public_key: &PKeyRef<Public>,
+    ca_cert: &X509Ref,
+    ca_key: &PKeyRef<Private>,
+) -> Result<Vec<u8>, ErrorStack> {
+    let mut cert_builder = X509Builder::new()?;
+    cert_builder.set_version(2)?;
+
+    let sn: [u8; 16] = rand::thread_rng().gen();
+    let sn = BigNum::from_slice(&sn)?;
+    let sn = Asn1Integer::from_bn(&sn)?;
+    cert_builder.set_serial_number(&sn)?;
+
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(config.validity_days)?;
+    cert_builder.set_not_before(&not_before)?;
+    cert_builder.set_not_after(&not_after)?;
+
+    cert_builder.set_issuer_name(ca_cert.subject_name())?;
+    cert_builder.set_pubkey(public_key)?;
+
+    let mut subject = X509NameBuilder::new()?;
+    if let Some(country) = &config.country {
+        subject.append_entry_by_nid(Nid::COUNTRYNAME, country)?;
+    }
+    if let Some(organization) = &config.organization {
+        subject.append_entry_by_nid(Nid::ORGANIZATIONNAME, organization)?;
+    }
+    if let Some(organizational_unit) = &config.organizational_unit {
+        subject.append_entry_by_nid(Nid::ORGANIZATIONALUNITNAME, organizational_unit)?;
+    }
+    subject.append_entry_by_nid(Nid::COMMONNAME, name)?;
+    cert_builder.set_subject_name(&subject.build())?;
+
+    let context = cert_builder.x509v3_context(Some(ca_cert), None);
+    let subject_key_identifier = SubjectKeyIdentifier::new().build(&context)?;
+    let authority_key_identifier = AuthorityKeyIdentifier::new()
+        .keyid(false)
+        .issuer(false)
+        .build(&context)?;
+
+    if !config.key_usage.is_empty() {
+        let mut key_usage = KeyUsage::new();
+        key_usage.critical();
+        for flag in &config.key_usage {
+            match flag {
+                KeyUsageFlag::DigitalSignature => key_usage.digital_signature(),
+                KeyUsageFlag::NonRepudiation => key_usage.non_repudiation(),
+                KeyUsageFlag::KeyEncipherment => key_usage.key_encipherment(),
+                KeyUsageFlag::DataEncipherment => key_usage.data_encipherment(),
+                KeyUsageFlag::KeyAgreement => key_usage.key_agreement(),
+            };
+        }
+        cert_builder.append_extension(key_usage.build()?)?;
+    }
+    cert_builder.append_extension(BasicConstraints::new().critical().build()?)?;
+    cert_builder.append_extension(subject_key_identifier)?;
+    cert_builder.append_extension(authority_key_identifier)?;
+
+    cert_builder.sign(ca_key, MessageDigest::sha256())?;
+    cert_builder.build().to_der()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use openssl::ec::PointConversionForm;
+    use openssl::x509::X509;
+
+    pub(crate) fn sample_ca() -> (X509, PKey<Private>) {
+        sample_certificate(0x1234)
+    }
+
+    /// Self-signed certificate with the serial number `serial`
+    pub(crate) fn sample_certificate(serial: u32) -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "MeeSign CA")
+            .unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(serial).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    fn group_key(curve: Nid) -> Vec<u8> {
+        let group = EcGroup::from_curve_name(curve).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let mut context = BigNumContext::new().unwrap();
+        key.public_key()
+            .to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut context)
+            .unwrap()
+    }
+
+    fn entry(certificate: &X509, nid: Nid) -> Option<String> {
+        certificate
+            .subject_name()
+            .entries_by_nid(nid)
+            .next()
+            .map(|entry| entry.data().as_utf8().unwrap().to_string())
+    }
+
+    #[test]
+    fn issue_p256() {
+        let (ca_cert, ca_key) = sample_ca();
+        let config = GroupCertificateConfig {
+            country: Some(String::from("CZ")),
+            organization: Some(String::from("MeeSign")),
+            validity_days: 30,
+            ..Default::default()
+        };
+        let public_key = ecdsa_public_key(&group_key(Nid::X9_62_PRIME256V1)).unwrap();
+        let certificate =
+            build_certificate(&config, "Sample Group", &public_key, &ca_cert, &ca_key).unwrap();
+        let certificate = X509::from_der(&certificate).unwrap();
+
+        assert!(certificate.verify(&ca_key).unwrap());
+        assert!(certificate.public_key().unwrap().public_eq(&public_key));
+        assert_eq!(
+            certificate.issuer_name().to_der().unwrap(),
+            ca_cert.subject_name().to_der().unwrap()
+        );
+        assert_eq!(
+            entry(&certificate, Nid::COMMONNAME).as_deref(),
+            Some("Sample Group")
+        );
+        assert_eq!(entry(&certificate, Nid::COUNTRYNAME).as_deref(), Some("CZ"));
+        assert_eq!(
+            entry(&certificate, Nid::ORGANIZATIONNAME).as_deref(),
+            Some("MeeSign")
+        );
+        assert_eq!(entry(&certificate, Nid::ORGANIZATIONALUNITNAME), None);
+        let validity = certificate
+            .not_before()
+            .diff(certificate.not_after())
+            .unwrap();
+        assert_eq!(validity.days, 30);
+    }
+
+    #[test]
+    fn issue_secp256k1() {
+        let (ca_cert, ca_key) = sample_ca();
+        let public_key = ecdsa_public_key(&group_key(Nid::SECP256K1)).unwrap();
+        let certificate = build_certificate(
+            &GroupCertificateConfig::default(),
+            "Sample Group",
+            &public_key,
+            &ca_cert,
+            &ca_key,
+        )
+        .unwrap();
+        let certificate = X509::from_der(&certificate).unwrap();
+        let key = certificate.public_key().unwrap().ec_key().unwrap();
+        assert_eq!(key.group().curve_name(), Some(Nid::SECP256K1));
+    }
+
+    #[test]
+    fn invalid_key() {
+        let mut public_key = group_key(Nid::X9_62_PRIME256V1);
+        public_key[64] ^= 0x01;
+        assert!(ecdsa_public_key(&public_key).is_err());
+        assert!(ecdsa_public_key(&[0x04; 33]).is_err());
+    }
+}
@@ -1,20 +1,23 @@
+use crate::approval::Verdict;
+use crate::config;
 use crate::device::Device;
-use crate::get_timestamp;
-use crate::proto::ProtocolType;
+use crate::proto::{ParticipantSelection, ProtocolType};
+use crate::{get_timestamp, utils};
 use meesign_crypto::proto::{Message, ProtocolMessage};
 use rand::prelude::SliceRandom;
 use rand::thread_rng;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::ops::Deref;
 use tonic::codegen::Arc;
 
 /// Communication state of a Task
 pub struct Communicator {
     /// The minimal number of parties needed to successfully complete the task
     threshold: u32,
-    /// Ordered list of devices
+    /// Ordered list of devices, a device is listed once per key share it holds
     device_list: Vec<Arc<Device>>,
-    /// Ordered list of active devices (participating in the protocol)
+    /// Ordered list of active devices (participating in the protocol), a device is listed
+    /// once per active key share
     active_devices: Option<Vec<Vec<u8>>>,
     /// A mapping of device identifiers to their Task decision
     decisions: HashMap<Vec<u8>, Option<bool>>,
@@ -26,14 +29,44 @@ pub struct Communicator {
     output: Vec<Vec<u8>>,
     /// Relayed protocol type
     protocol_type: ProtocolType,
+    /// Choice of the active devices among the agreeing ones
+    selection: Selection,
+    /// Identifiers of the agreeing devices in the order of their decisions
+    approvals: Vec<Vec<u8>>,
+    /// Devices which stalled an earlier protocol run; left out of further runs while enough
+    /// other devices agreed
+    stalled: Vec<Vec<u8>>,
+}
+
+/// Requested choice of the devices participating in the protocol
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Selection {
+    /// Devices whose key shares have to be active
+    pub pinned: Vec<Vec<u8>>,
+    /// Choice of the remaining active key shares
+    pub mode: ParticipantSelection,
+}
+
+/// Serializable state of a Communicator
+///
+/// Decisions and acknowledgements are ordered in the same way as the device list.
+#[derive(Serialize, Deserialize)]
+pub struct CommunicatorSnapshot {
+    active_devices: Option<Vec<Vec<u8>>>,
+    decisions: Vec<Option<bool>>,
+    acknowledgements: Vec<bool>,
+    input: Vec<Vec<Option<Vec<u8>>>>,
+    output: Vec<Vec<u8>>,
+    approvals: Vec<Vec<u8>>,
+    stalled: Vec<Vec<u8>>,
 }
 
 impl Communicator {
     /// Constructs a new Communicator instance with given Devices, threshold, and request message
     ///
     /// # Arguments
-    /// * `devices` - Sorted list of devices; items of the list need to be unique
-    /// * `threshold` - The minimal number of devices to successfully complete the task
+    /// * `devices` - Sorted list of devices; a device is repeated for each key share it holds
+    /// * `threshold` - The minimal number of key shares to successfully complete the task
     pub fn new(devices: &[Arc<Device>], threshold: u32, protocol_type: ProtocolType) -> Self {
         assert!(devices.len() > 1);
         assert!(threshold <= devices.len() as u32);
@@ -55,11 +88,85 @@ impl Communicator {
             input: Vec::new(),
             output: Vec::new(),
             protocol_type,
+            selection: Selection::default(),
+            approvals: Vec::new(),
+            stalled: Vec::new(),
         };
         communicator.clear_input();
         communicator
     }
 
+    /// Set the choice of the active devices; the key shares of the pinned devices must not
+    /// exceed the threshold
+    pub fn set_selection(&mut self, selection: Selection) -> Result<(), String> {
+        for (idx, device) in selection.pinned.iter().enumerate() {
+            if !self.decisions.contains_key(device) {
+                return Err(format!(
+                    "Pinned device {} is not a member of the group",
+                    utils::hextrunc(device)
+                ));
+            }
+            if selection.pinned[..idx].contains(device) {
+                return Err("Duplicate pinned device".into());
+            }
+        }
+        let pinned_shares = self
+            .device_list
+            .iter()
+            .filter(|device| selection.pinned.iter().any(|id| id == device.identifier()))
+            .count();
+        if pinned_shares > self.threshold as usize {
+            return Err("Pinned devices hold more key shares than the threshold".into());
+        }
+        self.selection = selection;
+        Ok(())
+    }
+
+    /// Evaluate the decisions of the pinned devices; declined once any of them rejects
+    pub fn pinned_verdict(&self) -> Verdict {
+        let decisions = self.selection.pinned.iter().map(|id| self.decisions[id]);
+        if decisions.clone().any(|decision| decision == Some(false)) {
+            Verdict::Declined
+        } else if decisions.clone().any(|decision| decision.is_none()) {
+            Verdict::Pending
+        } else {
+            Verdict::Approved
+        }
+    }
+
+    /// Mark active devices which have not sent their messages of the current round as stalled,
+    /// so that the next protocol run avoids them; pinned devices are never avoided
+    ///
+    /// # Returns
+    /// The devices which stalled for the first time
+    pub fn mark_stalled(&mut self) -> Vec<Vec<u8>> {
+        let mut stalled: Vec<Vec<u8>> = Vec::new();
+        for device in self.active_devices.iter().flatten() {
+            if self.waiting_for(device)
+                && !self.selection.pinned.contains(device)
+                && !self.stalled.contains(device)
+                && !stalled.contains(device)
+            {
+                stalled.push(device.clone());
+            }
+        }
+        self.stalled.extend(stalled.iter().cloned());
+        stalled
+    }
+
+    /// Get stalled devices which are left out of the current protocol run
+    pub fn get_excluded_devices(&self) -> Vec<Vec<u8>> {
+        self.stalled
+            .iter()
+            .filter(|device| {
+                self.active_devices
+                    .as_ref()
+                    .is_none_or(|active| !active.contains(device))
+            })
+            .cloned()
+            .collect()
+    }
+
     /// Clears incoming message buffers
     pub fn clear_input(&mut self) {
         self.input.clear();
@@ -73,33 +180,44 @@ impl Communicator {
     /// # Arguments
     ///
     /// * `from_identifier` - identifier of device from which is this broadcast received
-    /// * `message` - vector of length (threshold - 1) containing messages for other parties, sending party is excluded
-    pub fn receive_messages(&mut self, from_identifier: &[u8], message: Vec<Vec<u8>>) -> bool {
-        assert_eq!(message.len(), (self.threshold - 1) as usize);
-
-        let from_index = self.identifier_to_index(from_identifier);
-        if from_index.is_none() {
+    /// * `messages` - one item per active share of the device, in the order of their protocol
+    ///   indices; each is a vector of length (threshold - 1) containing messages for other
+    ///   parties, sending party is excluded
+    ///
+    /// # Returns
+    /// `false` if the device is not active or `messages` do not have the expected lengths
+    pub fn receive_messages(
+        &mut self,
+        from_identifier: &[u8],
+        messages: Vec<Vec<Vec<u8>>>,
+    ) -> bool {
+        let from_indices = self.identifier_to_indices(from_identifier);
+        if from_indices.is_empty()
+            || from_indices.len() != messages.len()
+            || messages
+                .iter()
+                .any(|message| message.len() != (self.threshold - 1) as usize)
+        {
             return false;
         }
-        let from_index = from_index.unwrap();
 
-        self.input[from_index] = message.into_iter().map(Some).collect();
-        self.input[from_index].insert(from_index, None);
+        for (from_index, message) in from_indices.into_iter().zip(messages) {
+            self.input[from_index] = message.into_iter().map(Some).collect();
+            self.input[from_index].insert(from_index, None);
+        }
         true
     }
 
     /// Is waiting for a message from the given device identifier
     pub fn waiting_for(&self, device_identifier: &[u8]) -> bool {
-        let device_index = self.identifier_to_index(device_identifier);
-        if device_index.is_none() {
-            return false;
-        }
-        let device_index = device_index.unwrap();
-
-        self.input
-            .get(device_index)
-            .map(|messages| !messages.iter().any(Option::is_some))
-            .unwrap_or(true)
+        self.identifier_to_indices(device_identifier)
+            .into_iter()
+            .any(|device_index| {
+                self.input
+                    .get(device_index)
+                    .map(|messages| !messages.iter().any(Option::is_some))
+                    .unwrap_or(true)
+            })
     }
 
     /// Moves messages from incoming buffers to outgoing buffers
@@ -154,11 +272,13 @@ impl Communicator {
             == 0
     }
 
-    /// Get message for given device identifier
-    pub fn get_message(&self, device_identifier: &[u8]) -> Option<Vec<u8>> {
-        self.identifier_to_index(device_identifier)
-            .and_then(|device_identifier| self.output.get(device_identifier))
-            .map(Vec::clone)
+    /// Get messages for given device identifier, one per its active share
+    pub fn get_messages(&self, device_identifier: &[u8]) -> Vec<Vec<u8>> {
+        self.identifier_to_indices(device_identifier)
+            .into_iter()
+            .filter_map(|index| self.output.get(index))
+            .cloned()
+            .collect()
     }
 
     /// Get final message
@@ -166,40 +286,83 @@ impl Communicator {
         self.input[0][1].clone()
     }
 
-    /// Set active devices
+    /// Set active devices, choosing `threshold` key shares of the agreeing devices
+    ///
+    /// Shares of the pinned devices are chosen first, the remaining ones according to the
+    /// selection mode. Stalled devices are chosen only if the other agreeing devices do not
+    /// hold enough shares.
     pub fn set_active_devices(&mut self) -> Vec<Vec<u8>> {
         assert!(self.accept_count() >= self.threshold);
-        let agreeing_devices = self
-            .device_list
-            .iter()
-            .filter(|device| self.decisions.get(device.identifier()) == Some(&Some(true)))
+        let threshold = self.threshold as usize;
+        // positions in the device list of the shares of the agreeing devices
+        let agreeing = (0..self.device_list.len())
+            .filter(|idx| {
+                self.decisions.get(self.device_list[*idx].identifier()) == Some(&Some(true))
+            })
             .collect::<Vec<_>>();
-
-        let timestamp = get_timestamp();
-        let connected_devices = agreeing_devices
+        let (mut chosen, candidates): (Vec<usize>, Vec<usize>) =
+            agreeing.into_iter().partition(|idx| {
+                self.selection
+                    .pinned
+                    .iter()
+                    .any(|id| id == self.device_list[*idx].identifier())
+            });
+        chosen.truncate(threshold);
+        let remaining = threshold - chosen.len();
+        let responsive = candidates
             .iter()
-            .filter(|device| device.last_active() > timestamp - 5)
-            .map(Deref::deref)
-            .collect::<Vec<_>>();
-
-        let (devices, indices): (&Vec<&Arc<Device>>, Vec<_>) =
-            if connected_devices.len() >= self.threshold as usize {
-                (&connected_devices, (0..connected_devices.len()).collect())
-            } else {
-                (&agreeing_devices, (0..agreeing_devices.len()).collect())
-            };
-        let mut indices = indices
-            .choose_multiple(&mut thread_rng(), self.threshold as usize)
+            .filter(|idx| {
+                !self
+                    .stalled
+                    .iter()
+                    .any(|id| id == self.device_list[**idx].identifier())
+            })
             .cloned()
             .collect::<Vec<_>>();
-        indices.sort();
+        let candidates = if responsive.len() >= remaining {
+            responsive
+        } else {
+            candidates
+        };
+
+        match self.selection.mode {
+            ParticipantSelection::Random => {
+                let timestamp = get_timestamp();
+                let connected = candidates
+                    .iter()
+                    .filter(|idx| {
+                        self.device_list[**idx].last_active()
+                            > timestamp.saturating_sub(config::get().liveness_window)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let candidates = if connected.len() >= remaining {
+                    connected
+                } else {
+                    candidates
+                };
+                chosen.extend(candidates.choose_multiple(&mut thread_rng(), remaining));
+            }
+            ParticipantSelection::FirstApproved => {
+                let mut candidates = candidates;
+                // the sort is stable, so the shares of a device keep their order
+                candidates.sort_by_key(|idx| {
+                    self.approvals
+                        .iter()
+                        .position(|id| id == self.device_list[*idx].identifier())
+                        .unwrap_or(usize::MAX)
+                });
+                chosen.extend(candidates.into_iter().take(remaining));
+            }
+        }
+        // shares of the same device are listed next to each other, so the chosen shares
+        // stay grouped by their devices
+        chosen.sort();
 
         self.active_devices = Some(
-            devices
-                .iter()
-                .enumerate()
-                .filter(|(idx, _)| indices.contains(idx))
-                .map(|(_, device)| device.identifier().to_vec())
+            chosen
+                .into_iter()
+                .map(|idx| self.device_list[idx].identifier().to_vec())
                 .collect(),
         );
         assert_eq!(
@@ -221,25 +384,33 @@ impl Communicator {
             return false;
         }
         self.decisions.insert(device.to_vec(), Some(decision));
+        if decision {
+            self.approvals.push(device.to_vec());
+        }
         true
     }
 
-    /// Get the number of Task accepts
+    /// Get the number of Task accepts, weighted by the key shares of the devices
     pub fn accept_count(&self) -> u32 {
-        self.decisions
+        self.device_list
             .iter()
-            .map(|x| u32::from(x.1.unwrap_or(false)))
+            .map(|device| u32::from(self.decisions[device.identifier()].unwrap_or(false)))
             .sum()
     }
 
-    /// Get the number of Task rejects
+    /// Get the number of Task rejects, weighted by the key shares of the devices
     pub fn reject_count(&self) -> u32 {
-        self.decisions
+        self.device_list
             .iter()
-            .map(|x| u32::from(!x.1.unwrap_or(true)))
+            .map(|device| u32::from(!self.decisions[device.identifier()].unwrap_or(true)))
             .sum()
     }
 
+    /// Decisions of the devices, `None` for devices which have not decided yet
+    pub fn decisions(&self) -> &HashMap<Vec<u8>, Option<bool>> {
+        &self.decisions
+    }
+
     /// Check whether a device submitted its decision
     pub fn device_decided(&self, device: &[u8]) -> bool {
         matches!(self.decisions.get(device), Some(Some(_)))
@@ -261,28 +432,95 @@ impl Communicator {
 
     /// Get indices of active devices in the corresponding group
     ///
-    /// Indices returned by this command correspond to key share indices of `active_devices`.
+    /// Indices returned by this command correspond to key share indices of `active_devices`;
+    /// the n-th occurrence of a device uses its n-th key share.
     pub fn get_protocol_indices(&mut self) -> Vec<u32> {
         assert!(self.active_devices.is_some());
 
         let active_devices = self.get_active_devices().unwrap();
         let mut indices: Vec<u32> = Vec::new();
-        for device in &active_devices {
+        for (idx, device) in active_devices.iter().enumerate() {
+            let occurrence = active_devices[..idx]
+                .iter()
+                .filter(|x| *x == device)
+                .count();
             indices.push(
                 self.device_list
                     .iter()
-                    .position(|x| x.identifier() == device)
-                    .unwrap() as u32,
+                    .enumerate()
+                    .filter(|(_, x)| x.identifier() == device)
+                    .nth(occurrence)
+                    .unwrap()
+                    .0 as u32,
             );
         }
         indices
     }
 
-    /// Translate device identifier to `active_devices` index
-    fn identifier_to_index(&self, device_id: &[u8]) -> Option<usize> {
+    /// Capture the communication state so that it can be restored later
+    pub fn snapshot(&self) -> CommunicatorSnapshot {
+        CommunicatorSnapshot {
+            active_devices: self.active_devices.clone(),
+            decisions: self
+                .device_list
+                .iter()
+                .map(|device| self.decisions[device.identifier()])
+                .collect(),
+            acknowledgements: self
+                .device_list
+                .iter()
+                .map(|device| self.acknowledgements[device.identifier()])
+                .collect(),
+            input: self.input.clone(),
+            output: self.output.clone(),
+            approvals: self.approvals.clone(),
+            stalled: self.stalled.clone(),
+        }
+    }
+
+    /// Restore the communication state from a snapshot taken with the same devices and threshold
+    pub fn restore(&mut self, snapshot: CommunicatorSnapshot) -> Result<(), String> {
+        if snapshot.decisions.len() != self.device_list.len()
+            || snapshot.acknowledgements.len() != self.device_list.len()
+            || snapshot.input.len() != self.threshold as usize
+        {
+            return Err("Communicator snapshot does not match the device list".into());
+        }
+        if let Some(active_devices) = &snapshot.active_devices {
+            if active_devices.len() != self.threshold as usize
+                || active_devices
+                    .iter()
+                    .any(|id| !self.decisions.contains_key(id))
+            {
+                return Err("Communicator snapshot contains unknown active devices".into());
+            }
+        }
+
+        for (device, decision) in self.device_list.iter().zip(snapshot.decisions) {
+            self.decisions
+                .insert(device.identifier().to_vec(), decision);
+        }
+        for (device, acknowledgement) in self.device_list.iter().zip(snapshot.acknowledgements) {
+            self.acknowledgements
+                .insert(device.identifier().to_vec(), acknowledgement);
+        }
+        self.active_devices = snapshot.active_devices;
+        self.input = snapshot.input;
+        self.output = snapshot.output;
+        self.approvals = snapshot.approvals;
+        self.stalled = snapshot.stalled;
+        Ok(())
+    }
+
+    /// Translate device identifier to the `active_devices` indices of its active shares
+    fn identifier_to_indices(&self, device_id: &[u8]) -> Vec<usize> {
         self.active_devices
-            .as_ref()
-            .and_then(|list| list.iter().position(|id| id == device_id))
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, id)| *id == device_id)
+            .map(|(index, _)| index)
+            .collect()
     }
 }
 
@@ -310,8 +548,8 @@ mod tests {
         assert_eq!(communicator.accept_count(), 0);
         assert_eq!(communicator.reject_count(), 0);
         assert_eq!(communicator.round_received(), false);
-        assert_eq!(communicator.get_message(d0), None);
-        assert_eq!(communicator.get_message(&[0x00, 0x00]), None);
+        assert!(communicator.get_messages(d0).is_empty());
+        assert!(communicator.get_messages(&[0x00, 0x00]).is_empty());
         assert_eq!(communicator.device_decided(d0), false);
         assert_eq!(communicator.device_decided(&[0x00, 0x00]), false);
         assert_eq!(communicator.waiting_for(d0), false);
@@ -378,7 +616,7 @@ mod tests {
             assert_eq!(
                 communicator.receive_messages(
                     devices[idx].identifier(),
-                    vec![vec![]; active_indices.len() - 1]
+                    vec![vec![vec![]; active_indices.len() - 1]]
                 ),
                 active_indices.contains(&idx)
             );
@@ -390,15 +628,15 @@ mod tests {
         assert_eq!(communicator.round_received(), true);
 
         for idx in 0..devices.len() {
-            assert_eq!(communicator.get_message(devices[idx].identifier()), None);
+            assert!(communicator
+                .get_messages(devices[idx].identifier())
+                .is_empty());
         }
         communicator.relay();
         for idx in 0..devices.len() {
             assert_eq!(
-                communicator
-                    .get_message(devices[idx].identifier())
-                    .is_some(),
-                active_indices.contains(&idx)
+                communicator.get_messages(devices[idx].identifier()).len(),
+                usize::from(active_indices.contains(&idx))
             );
         }
         assert_eq!(communicator.round_received(), false);
@@ -425,23 +663,27 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn not_enough_messages() {
         let devices = prepare_devices(3);
         let mut communicator = Communicator::new(&devices, 3, ProtocolType::Gg18);
-        communicator.decide(devices[0].identifier(), true);
-        communicator.decide(devices[1].identifier(), true);
-        communicator.receive_messages(devices[0].identifier(), vec![vec![]; 1]);
+        for device in &devices {
+            communicator.decide(device.identifier(), true);
+        }
+        communicator.set_active_devices();
+        assert!(!communicator.receive_messages(devices[0].identifier(), vec![vec![vec![]; 1]]));
+        assert!(communicator.waiting_for(devices[0].identifier()));
     }
 
     #[test]
-    #[should_panic]
     fn too_many_messages() {
         let devices = prepare_devices(3);
         let mut communicator = Communicator::new(&devices, 3, ProtocolType::Gg18);
-        communicator.decide(devices[0].identifier(), true);
-        communicator.decide(devices[1].identifier(), true);
-        communicator.receive_messages(devices[0].identifier(), vec![vec![]; 3]);
+        for device in &devices {
+            communicator.decide(device.identifier(), true);
+        }
+        communicator.set_active_devices();
+        assert!(!communicator.receive_messages(devices[0].identifier(), vec![vec![vec![]; 3]]));
+        assert!(communicator.waiting_for(devices[0].identifier()));
     }
 
     #[test]
@@ -486,16 +728,168 @@ mod tests {
         );
         communicator.send_all(|idx| vec![idx as u8]);
         assert_eq!(
-            communicator.get_message(devices[0].identifier()),
-            Some(vec![0])
+            communicator.get_messages(devices[0].identifier()),
+            vec![vec![0]]
         );
-        assert_eq!(communicator.get_message(devices[1].identifier()), None);
+        assert!(communicator
+            .get_messages(devices[1].identifier())
+            .is_empty());
         assert_eq!(
-            communicator.get_message(devices[2].identifier()),
-            Some(vec![2])
+            communicator.get_messages(devices[2].identifier()),
+            vec![vec![2]]
         );
     }
 
+    #[test]
+    fn multiple_shares() {
+        let devices = prepare_devices(3);
+        // the second device holds two of four shares
+        let shares = vec![
+            devices[0].clone(),
+            devices[1].clone(),
+            devices[1].clone(),
+            devices[2].clone(),
+        ];
+        let mut communicator = Communicator::new(&shares, 3, ProtocolType::Gg18);
+        communicator.decide(devices[1].identifier(), true);
+        assert_eq!(communicator.accept_count(), 2);
+        communicator.decide(devices[0].identifier(), false);
+        assert_eq!(communicator.reject_count(), 1);
+        communicator.decide(devices[2].identifier(), true);
+        assert_eq!(communicator.accept_count(), 3);
+
+        communicator.set_active_devices();
+        assert_eq!(
+            communicator.get_active_devices(),
+            Some(vec![
+                devices[1].identifier().to_vec(),
+                devices[1].identifier().to_vec(),
+                devices[2].identifier().to_vec()
+            ])
+        );
+        assert_eq!(communicator.get_protocol_indices(), vec![1, 2, 3]);
+
+        communicator.send_all(|idx| vec![idx as u8]);
+        assert_eq!(
+            communicator.get_messages(devices[1].identifier()),
+            vec![vec![1], vec![2]]
+        );
+        assert_eq!(
+            communicator.get_messages(devices[2].identifier()),
+            vec![vec![3]]
+        );
+
+        // a device sends the messages of all its active shares at once
+        assert!(!communicator.receive_messages(devices[1].identifier(), vec![vec![vec![]; 2]]));
+        assert!(communicator.receive_messages(
+            devices[1].identifier(),
+            vec![vec![vec![0x10], vec![0x12]], vec![vec![0x20], vec![0x22]]]
+        ));
+        assert!(!communicator.waiting_for(devices[1].identifier()));
+        assert!(!communicator.round_received());
+        assert!(communicator
+            .receive_messages(devices[2].identifier(), vec![vec![vec![0x30], vec![0x31]]]));
+        assert!(communicator.round_received());
+
+        communicator.relay();
+        let decode = |message: &Vec<u8>| ProtocolMessage::decode(message.as_slice()).unwrap();
+        let messages = communicator.get_messages(devices[1].identifier());
+        assert_eq!(decode(&messages[0]).message, vec![vec![0x20], vec![0x30]]);
+        assert_eq!(decode(&messages[1]).message, vec![vec![0x10], vec![0x31]]);
+        let messages = communicator.get_messages(devices[2].identifier());
+        assert_eq!(decode(&messages[0]).message, vec![vec![0x12], vec![0x22]]);
+    }
+
+    #[test]
+    fn selection() {
+        let devices = prepare_devices(5);
+        let mut communicator = Communicator::new(&devices, 3, ProtocolType::Gg18);
+        communicator
+            .set_selection(Selection {
+                pinned: vec![devices[3].identifier().to_vec()],
+                mode: ParticipantSelection::FirstApproved,
+            })
+            .unwrap();
+        assert_eq!(communicator.pinned_verdict(), Verdict::Pending);
+        communicator.decide(devices[4].identifier(), true);
+        communicator.decide(devices[1].identifier(), true);
+        communicator.decide(devices[0].identifier(), true);
+        assert_eq!(communicator.pinned_verdict(), Verdict::Pending);
+        communicator.decide(devices[3].identifier(), true);
+        assert_eq!(communicator.pinned_verdict(), Verdict::Approved);
+
+        let mut restored = Communicator::new(&devices, 3, ProtocolType::Gg18);
+        restored
+            .set_selection(communicator.selection.clone())
+            .unwrap();
+        restored.restore(communicator.snapshot()).unwrap();
+        for communicator in [&mut communicator, &mut restored] {
+            communicator.set_active_devices();
+            assert_eq!(
+                communicator.get_active_devices(),
+                Some(
+                    [1, 3, 4]
+                        .iter()
+                        .map(|idx| devices[*idx].identifier().to_vec())
+                        .collect()
+                )
+            );
+        }
+
+        let mut communicator = Communicator::new(&devices, 3, ProtocolType::Gg18);
+        let pin = |devices: &[Arc<Device>]| Selection {
+            pinned: devices.iter().map(|d| d.identifier().to_vec()).collect(),
+            mode: ParticipantSelection::Random,
+        };
+        assert!(communicator.set_selection(pin(&devices[..4])).is_err());
+        assert!(communicator
+            .set_selection(pin(&[devices[2].clone(), devices[2].clone()]))
+            .is_err());
+        assert!(communicator
+            .set_selection(pin(&prepare_devices(6)[5..]))
+            .is_err());
+        communicator.set_selection(pin(&devices[2..3])).unwrap();
+        communicator.decide(devices[2].identifier(), false);
+        assert_eq!(communicator.pinned_verdict(), Verdict::Declined);
+    }
+
+    #[test]
+    fn stalled_devices() {
+        let devices = prepare_devices(5);
+        let mut communicator = Communicator::new(&devices, 3, ProtocolType::Gg18);
+        for device in &devices[..4] {
+            communicator.decide(device.identifier(), true);
+        }
+        communicator.set_active_devices();
+        communicator.send_all(|idx| vec![idx as u8]);
+        let active = communicator.get_active_devices().unwrap();
+        for device in &active[..2] {
+            assert!(communicator.receive_messages(device, vec![vec![vec![0x01], vec![0x02]]]));
+        }
+
+        // the device which did not respond is left out of the next run
+        assert_eq!(communicator.mark_stalled(), vec![active[2].clone()]);
+        assert!(communicator.mark_stalled().is_empty());
+        communicator.set_active_devices();
+        assert!(!communicator
+            .get_active_devices()
+            .unwrap()
+            .contains(&active[2]));
+        assert_eq!(communicator.get_excluded_devices(), vec![active[2].clone()]);
+
+        // unless the other devices do not suffice
+        communicator.send_all(|idx| vec![idx as u8]);
+        let snapshot = communicator.snapshot();
+        assert_eq!(communicator.mark_stalled().len(), 3);
+        communicator.set_active_devices();
+        assert_eq!(communicator.get_active_devices().unwrap().len(), 3);
+        assert_eq!(communicator.get_excluded_devices().len(), 1);
+
+        let mut restored = Communicator::new(&devices, 3, ProtocolType::Gg18);
+        restored.restore(snapshot).unwrap();
+        assert_eq!(restored.get_excluded_devices(), vec![active[2].clone()]);
+    }
+
     #[test]
     fn unknown_device_acknowledgement() {
         let devices = prepare_devices(3);
@@ -511,6 +905,46 @@ mod tests {
         assert_eq!(communicator.acknowledge(devices[0].identifier()), false);
     }
 
+    #[test]
+    fn snapshot_restore() {
+        let devices = prepare_devices(4);
+        let mut communicator = Communicator::new(&devices, 3, ProtocolType::Gg18);
+        communicator.decide(devices[0].identifier(), true);
+        communicator.decide(devices[1].identifier(), false);
+        communicator.decide(devices[2].identifier(), true);
+        communicator.decide(devices[3].identifier(), true);
+        communicator.set_active_devices();
+        communicator.send_all(|idx| vec![idx as u8]);
+        communicator.receive_messages(devices[2].identifier(), vec![vec![vec![0x01], vec![0x02]]]);
+        communicator.acknowledge(devices[3].identifier());
+
+        let mut restored = Communicator::new(&devices, 3, ProtocolType::Gg18);
+        restored.restore(communicator.snapshot()).unwrap();
+        assert_eq!(restored.accept_count(), 3);
+        assert_eq!(restored.reject_count(), 1);
+        assert_eq!(
+            restored.get_active_devices(),
+            communicator.get_active_devices()
+        );
+        for device in &devices {
+            assert_eq!(
+                restored.get_messages(device.identifier()),
+                communicator.get_messages(device.identifier())
+            );
+            assert_eq!(
+                restored.waiting_for(device.identifier()),
+                communicator.waiting_for(device.identifier())
+            );
+            assert_eq!(
+                restored.device_acknowledged(device.identifier()),
+                communicator.device_acknowledged(device.identifier())
+            );
+        }
+
+        let mut mismatched = Communicator::new(&devices[..3], 3, ProtocolType::Gg18);
+        assert!(mismatched.restore(communicator.snapshot()).is_err());
+    }
+
     fn prepare_devices(n: usize) -> Vec<Arc<Device>> {
         assert!(n < u8::MAX as usize);
         (0..n)
@@ -0,0 +1,756 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use log::info;
+use serde::Deserialize;
+
+use crate::certificate::{GroupCertificateConfig, KeyUsageFlag};
+use crate::interfaces::timer::{TaskCleanup, TaskLimits};
+use crate::pdf;
+
+/// Operational limits and key paths of the server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Seconds without progress after which an approved task is restarted
+    pub stale_task_timeout: u64,
+    /// Seconds since the last request for which a device is considered connected
+    pub liveness_window: u64,
+    /// Maximal size of a PDF document to be signed in bytes
+    pub max_pdf_size: usize,
+    /// Maximal number of characters of device and group names
+    pub max_name_length: usize,
+    /// Maximal length of names of signed documents in bytes
+    pub max_document_name_length: usize,
+    /// Maximal number of key shares of a group
+    pub max_group_shares: u32,
+    /// Validity of issued device certificates in days
+    pub device_cert_validity: u32,
+    pub ca_cert: PathBuf,
+    pub ca_key: PathBuf,
+    pub server_cert: PathBuf,
+    pub server_key: PathBuf,
+    /// Requester authorization policy; requests are not restricted if missing
+    pub policy: Option<PathBuf>,
+    /// Approval rules of groups; only the group thresholds apply if missing
+    pub approval_policy: Option<PathBuf>,
+    /// Path where the CRL of revoked device certificates is published
+    pub crl: Option<PathBuf>,
+    /// Address of the HTTP endpoint exposing Prometheus metrics; disabled if missing
+    pub metrics_addr: Option<SocketAddr>,
+    /// Path of the hash-chained audit log; no audit records are written if missing
+    pub audit_log: Option<PathBuf>,
+    /// Address of the admin gRPC service; disabled if missing
+    pub admin_addr: Option<SocketAddr>,
+    /// Webhooks notified of task lifecycle events; none if missing
+    pub webhooks: Option<PathBuf>,
+    /// Address and port of the gRPC service
+    pub addr: String,
+    pub port: u16,
+    /// Path of the SQLite database
+    pub database: PathBuf,
+    pub pdf_backend: pdf::Backend,
+    pub group_certificate: GroupCertificateConfig,
+    pub task_limits: TaskLimits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            stale_task_timeout: 30,
+            liveness_window: 5,
+            max_pdf_size: 8 * 1024 * 1024,
+            max_name_length: 64,
+            max_document_name_length: 256,
+            max_group_shares: 256,
+            device_cert_validity: 365 * 4 + 1,
+            ca_cert: PathBuf::from("keys/meesign-ca-cert.pem"),
+            ca_key: PathBuf::from("keys/meesign-ca-key.pem"),
+            server_cert: PathBuf::from("keys/meesign-server-cert.pem"),
+            server_key: PathBuf::from("keys/meesign-server-key.pem"),
+            policy: None,
+            approval_policy: None,
+            crl: None,
+            metrics_addr: None,
+            audit_log: None,
+            admin_addr: None,
+            webhooks: None,
+            addr: String::from("127.0.0.1"),
+            port: 1337,
+            database: PathBuf::from("meesign.db"),
+            pdf_backend: pdf::Backend::Native,
+            group_certificate: GroupCertificateConfig::default(),
+            task_limits: TaskLimits::default(),
+        }
+    }
+}
+
+/// Values overriding the defaults of `Config`
+///
+/// The same structure is read from command-line options (or their environment
+/// variables) and from the TOML configuration file; options take precedence.
+#[derive(Clone, Debug, Default, Deserialize, clap::Args)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigOverrides {
+    #[clap(
+        long,
+        env = "MEESIGN_STALE_TASK_TIMEOUT",
+        help = "Seconds without progress after which an approved task is restarted [default: 30]"
+    )]
+    pub stale_task_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_LIVENESS_WINDOW",
+        help = "Seconds since the last request for which a device is considered connected [default: 5]"
+    )]
+    pub liveness_window: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_MAX_PDF_SIZE",
+        help = "Maximal size of a PDF document in bytes [default: 8388608]"
+    )]
+    pub max_pdf_size: Option<usize>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_MAX_NAME_LENGTH",
+        help = "Maximal length of device and group names [default: 64]"
+    )]
+    pub max_name_length: Option<usize>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_MAX_DOCUMENT_NAME_LENGTH",
+        help = "Maximal length of names of signed PDF documents in bytes [default: 256]"
+    )]
+    pub max_document_name_length: Option<usize>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_MAX_GROUP_SHARES",
+        help = "Maximal number of key shares of a group [default: 256]"
+    )]
+    pub max_group_shares: Option<u32>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_DEVICE_CERT_VALIDITY",
+        help = "Validity of device certificates in days [default: 1461]"
+    )]
+    pub device_cert_validity: Option<u32>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_CA_CERT",
+        help = "Path to the CA certificate [default: keys/meesign-ca-cert.pem]"
+    )]
+    pub ca_cert: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_CA_KEY",
+        help = "Path to the CA private key [default: keys/meesign-ca-key.pem]"
+    )]
+    pub ca_key: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_SERVER_CERT",
+        help = "Path to the server certificate [default: keys/meesign-server-cert.pem]"
+    )]
+    pub server_cert: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_SERVER_KEY",
+        help = "Path to the server private key [default: keys/meesign-server-key.pem]"
+    )]
+    pub server_key: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_POLICY",
+        help = "Path to the requester authorization policy"
+    )]
+    pub policy: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_APPROVAL_POLICY",
+        help = "Path to the approval rules of groups"
+    )]
+    pub approval_policy: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_CRL",
+        help = "Path where the CRL of revoked device certificates is published"
+    )]
+    pub crl: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_METRICS_ADDR",
+        help = "Address of the Prometheus metrics endpoint, e.g. 127.0.0.1:9100"
+    )]
+    pub metrics_addr: Option<SocketAddr>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_AUDIT_LOG",
+        help = "Path of the hash-chained audit log"
+    )]
+    pub audit_log: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_ADMIN_ADDR",
+        help = "Address of the admin gRPC service, e.g. 127.0.0.1:1338"
+    )]
+    pub admin_addr: Option<SocketAddr>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_WEBHOOKS",
+        help = "Path to the configuration of webhooks notified of task events"
+    )]
+    pub webhooks: Option<PathBuf>,
+
+    #[clap(
+        short,
+        long,
+        env = "MEESIGN_ADDR",
+        help = "Address of the gRPC service [default: 127.0.0.1]"
+    )]
+    pub addr: Option<String>,
+
+    #[clap(
+        short,
+        long,
+        env = "MEESIGN_PORT",
+        help = "Port of the gRPC service [default: 1337]"
+    )]
+    pub port: Option<u16>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_DATABASE",
+        help = "Path to the SQLite database [default: meesign.db]"
+    )]
+    pub database: Option<PathBuf>,
+
+    #[clap(
+        long,
+        arg_enum,
+        env = "MEESIGN_PDF_BACKEND",
+        help = "PDF signing backend [default: native]"
+    )]
+    pub pdf_backend: Option<pdf::Backend>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_GROUP_CERT_COUNTRY",
+        help = "Country (C) of group certificates"
+    )]
+    pub group_cert_country: Option<String>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_GROUP_CERT_ORGANIZATION",
+        help = "Organization (O) of group certificates"
+    )]
+    pub group_cert_organization: Option<String>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_GROUP_CERT_ORGANIZATIONAL_UNIT",
+        help = "Organizational unit (OU) of group certificates"
+    )]
+    pub group_cert_organizational_unit: Option<String>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_GROUP_CERT_VALIDITY",
+        help = "Validity of group certificates in days [default: 1461]"
+    )]
+    pub group_cert_validity: Option<u32>,
+
+    #[clap(
+        long,
+        arg_enum,
+        use_value_delimiter = true,
+        env = "MEESIGN_GROUP_CERT_KEY_USAGE",
+        help = "Key usage of group certificates [default: digital-signature,non-repudiation]"
+    )]
+    pub group_cert_key_usage: Option<Vec<KeyUsageFlag>>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_GROUP_APPROVAL_TIMEOUT",
+        help = "Approval deadline of group tasks in seconds, 0 disables it [default: 86400]"
+    )]
+    pub group_approval_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_GROUP_COMPLETION_TIMEOUT",
+        help = "Completion deadline of group tasks after their approval in seconds, 0 disables it [default: 3600]"
+    )]
+    pub group_completion_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_SIGN_PDF_APPROVAL_TIMEOUT",
+        help = "Approval deadline of PDF signing tasks in seconds, 0 disables it [default: 86400]"
+    )]
+    pub sign_pdf_approval_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_SIGN_PDF_COMPLETION_TIMEOUT",
+        help = "Completion deadline of PDF signing tasks after their approval in seconds, 0 disables it [default: 3600]"
+    )]
+    pub sign_pdf_completion_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_SIGN_CHALLENGE_APPROVAL_TIMEOUT",
+        help = "Approval deadline of challenge signing tasks in seconds, 0 disables it [default: 86400]"
+    )]
+    pub sign_challenge_approval_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_SIGN_CHALLENGE_COMPLETION_TIMEOUT",
+        help = "Completion deadline of challenge signing tasks after their approval in seconds, 0 disables it [default: 3600]"
+    )]
+    pub sign_challenge_completion_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_DECRYPT_APPROVAL_TIMEOUT",
+        help = "Approval deadline of decryption tasks in seconds, 0 disables it [default: 86400]"
+    )]
+    pub decrypt_approval_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_DECRYPT_COMPLETION_TIMEOUT",
+        help = "Completion deadline of decryption tasks after their approval in seconds, 0 disables it [default: 3600]"
+    )]
+    pub decrypt_completion_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_TASK_RETENTION",
+        help = "Seconds to keep ended tasks after all devices acknowledged them, 0 keeps them forever [default: 86400]"
+    )]
+    pub task_retention: Option<u64>,
+
+    #[clap(
+        long,
+        env = "MEESIGN_TASK_HARD_RETENTION",
+        help = "Seconds to keep ended tasks which were not acknowledged by all devices, 0 keeps them forever [default: 2592000]"
+    )]
+    pub task_hard_retention: Option<u64>,
+
+    #[clap(
+        long,
+        arg_enum,
+        env = "MEESIGN_TASK_CLEANUP",
+        help = "What to do with ended tasks after the retention period [default: archive]"
+    )]
+    pub task_cleanup: Option<TaskCleanup>,
+}
+
+impl ConfigOverrides {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    }
+
+    /// Fill in the values missing in `self` from `other`
+    fn or(self, other: ConfigOverrides) -> Self {
+        ConfigOverrides {
+            stale_task_timeout: self.stale_task_timeout.or(other.stale_task_timeout),
+            liveness_window: self.liveness_window.or(other.liveness_window),
+            max_pdf_size: self.max_pdf_size.or(other.max_pdf_size),
+            max_name_length: self.max_name_length.or(other.max_name_length),
+            max_document_name_length: self
+                .max_document_name_length
+                .or(other.max_document_name_length),
+            max_group_shares: self.max_group_shares.or(other.max_group_shares),
+            device_cert_validity: self.device_cert_validity.or(other.device_cert_validity),
+            ca_cert: self.ca_cert.or(other.ca_cert),
+            ca_key: self.ca_key.or(other.ca_key),
+            server_cert: self.server_cert.or(other.server_cert),
+            server_key: self.server_key.or(other.server_key),
+            policy: self.policy.or(other.policy),
+            approval_policy: self.approval_policy.or(other.approval_policy),
+            crl: self.crl.or(other.crl),
+            metrics_addr: self.metrics_addr.or(other.metrics_addr),
+            audit_log: self.audit_log.or(other.audit_log),
+            admin_addr: self.admin_addr.or(other.admin_addr),
+            webhooks: self.webhooks.or(other.webhooks),
+            addr: self.addr.or(other.addr),
+            port: self.port.or(other.port),
+            database: self.database.or(other.database),
+            pdf_backend: self.pdf_backend.or(other.pdf_backend),
+            group_cert_country: self.group_cert_country.or(other.group_cert_country),
+            group_cert_organization: self
+                .group_cert_organization
+                .or(other.group_cert_organization),
+            group_cert_organizational_unit: self
+                .group_cert_organizational_unit
+                .or(other.group_cert_organizational_unit),
+            group_cert_validity: self.group_cert_validity.or(other.group_cert_validity),
+            group_cert_key_usage: self.group_cert_key_usage.or(other.group_cert_key_usage),
+            group_approval_timeout: self.group_approval_timeout.or(other.group_approval_timeout),
+            group_completion_timeout: self
+                .group_completion_timeout
+                .or(other.group_completion_timeout),
+            sign_pdf_approval_timeout: self
+                .sign_pdf_approval_timeout
+                .or(other.sign_pdf_approval_timeout),
+            sign_pdf_completion_timeout: self
+                .sign_pdf_completion_timeout
+                .or(other.sign_pdf_completion_timeout),
+            sign_challenge_approval_timeout: self
+                .sign_challenge_approval_timeout
+                .or(other.sign_challenge_approval_timeout),
+            sign_challenge_completion_timeout: self
+                .sign_challenge_completion_timeout
+                .or(other.sign_challenge_completion_timeout),
+            decrypt_approval_timeout: self
+                .decrypt_approval_timeout
+                .or(other.decrypt_approval_timeout),
+            decrypt_completion_timeout: self
+                .decrypt_completion_timeout
+                .or(other.decrypt_completion_timeout),
+            task_retention: self.task_retention.or(other.task_retention),
+            task_hard_retention: self.task_hard_retention.or(other.task_hard_retention),
+            task_cleanup: self.task_cleanup.or(other.task_cleanup),
+        }
+    }
+}
+
+impl Config {
+    /// Apply `overrides` and then the contents of the configuration `file` over the
+    /// defaults and check that the result is usable
+    pub fn resolve(overrides: ConfigOverrides, file: Option<&Path>) -> Result<Self, String> {
+        let overrides = match file {
+            Some(path) => {
+                info!("Loading configuration from {}", path.display());
+                overrides.or(ConfigOverrides::from_file(path)?)
+            }
+            None => overrides,
+        };
+        let default = Config::default();
+        let certificate = default.group_certificate;
+        let limits = default.task_limits;
+        let config = Config {
+            stale_task_timeout: overrides
+                .stale_task_timeout
+                .unwrap_or(default.stale_task_timeout),
+            liveness_window: overrides.liveness_window.unwrap_or(default.liveness_window),
+            max_pdf_size: overrides.max_pdf_size.unwrap_or(default.max_pdf_size),
+            max_name_length: overrides.max_name_length.unwrap_or(default.max_name_length),
+            max_document_name_length: overrides
+                .max_document_name_length
+                .unwrap_or(default.max_document_name_length),
+            max_group_shares: overrides
+                .max_group_shares
+                .unwrap_or(default.max_group_shares),
+            device_cert_validity: overrides
+                .device_cert_validity
+                .unwrap_or(default.device_cert_validity),
+            ca_cert: overrides.ca_cert.unwrap_or(default.ca_cert),
+            ca_key: overrides.ca_key.unwrap_or(default.ca_key),
+            server_cert: overrides.server_cert.unwrap_or(default.server_cert),
+            server_key: overrides.server_key.unwrap_or(default.server_key),
+            policy: overrides.policy,
+            approval_policy: overrides.approval_policy,
+            crl: overrides.crl,
+            metrics_addr: overrides.metrics_addr,
+            audit_log: overrides.audit_log,
+            admin_addr: overrides.admin_addr,
+            webhooks: overrides.webhooks,
+            addr: overrides.addr.unwrap_or(default.addr),
+            port: overrides.port.unwrap_or(default.port),
+            database: overrides.database.unwrap_or(default.database),
+            pdf_backend: overrides.pdf_backend.unwrap_or(default.pdf_backend),
+            group_certificate: GroupCertificateConfig {
+                country: overrides.group_cert_country,
+                organization: overrides.group_cert_organization,
+                organizational_unit: overrides.group_cert_organizational_unit,
+                validity_days: overrides
+                    .group_cert_validity
+                    .unwrap_or(certificate.validity_days),
+                key_usage: overrides
+                    .group_cert_key_usage
+                    .unwrap_or(certificate.key_usage),
+            },
+            task_limits: TaskLimits {
+                group_approval_timeout: overrides
+                    .group_approval_timeout
+                    .unwrap_or(limits.group_approval_timeout),
+                group_completion_timeout: overrides
+                    .group_completion_timeout
+                    .unwrap_or(limits.group_completion_timeout),
+                sign_pdf_approval_timeout: overrides
+                    .sign_pdf_approval_timeout
+                    .unwrap_or(limits.sign_pdf_approval_timeout),
+                sign_pdf_completion_timeout: overrides
+                    .sign_pdf_completion_timeout
+                    .unwrap_or(limits.sign_pdf_completion_timeout),
+                sign_challenge_approval_timeout: overrides
+                    .sign_challenge_approval_timeout
+                    .unwrap_or(limits.sign_challenge_approval_timeout),
+                sign_challenge_completion_timeout: overrides
+                    .sign_challenge_completion_timeout
+                    .unwrap_or(limits.sign_challenge_completion_timeout),
+                decrypt_approval_timeout: overrides
+                    .decrypt_approval_timeout
+                    .unwrap_or(limits.decrypt_approval_timeout),
+                decrypt_completion_timeout: overrides
+                    .decrypt_completion_timeout
+                    .unwrap_or(limits.decrypt_completion_timeout),
+                task_retention: overrides.task_retention.unwrap_or(limits.task_retention),
+                task_hard_retention: overrides
+                    .task_hard_retention
+                    .unwrap_or(limits.task_hard_retention),
+                task_cleanup: overrides.task_cleanup.unwrap_or(limits.task_cleanup),
+            },
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.stale_task_timeout == 0 {
+            return Err("stale-task-timeout has to be positive".into());
+        }
+        if self.liveness_window == 0 {
+            return Err("liveness-window has to be positive".into());
+        }
+        if self.max_pdf_size == 0 {
+            return Err("max-pdf-size has to be positive".into());
+        }
+        // names are used as common names of certificates, which are limited to 64 characters
+        if !(1..=64).contains(&self.max_name_length) {
+            return Err("max-name-length has to be between 1 and 64".into());
+        }
+        if self.max_document_name_length == 0 {
+            return Err("max-document-name-length has to be positive".into());
+        }
+        if self.max_group_shares == 0 {
+            return Err("max-group-shares has to be positive".into());
+        }
+        if self.device_cert_validity == 0 {
+            return Err("device-cert-validity has to be positive".into());
+        }
+        if self.group_certificate.validity_days == 0 {
+            return Err("group-cert-validity has to be positive".into());
+        }
+        let limits = &self.task_limits;
+        if limits.task_hard_retention > 0
+            && (limits.task_retention == 0 || limits.task_hard_retention < limits.task_retention)
+        {
+            return Err("task-hard-retention has to be at least task-retention".into());
+        }
+        if let Some(path) = &self.policy {
+            if !path.is_file() {
+                return Err(format!("Policy file {} does not exist", path.display()));
+            }
+        }
+        if let Some(path) = &self.approval_policy {
+            if !path.is_file() {
+                return Err(format!(
+                    "Approval policy file {} does not exist",
+                    path.display()
+                ));
+            }
+        }
+        if let Some(path) = &self.webhooks {
+            if !path.is_file() {
+                return Err(format!("Webhook file {} does not exist", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the keys and certificates used by the server exist; offline
+    /// subcommands do not need them
+    pub fn check_keys(&self) -> Result<(), String> {
+        for path in [
+            &self.ca_cert,
+            &self.ca_key,
+            &self.server_cert,
+            &self.server_key,
+        ] {
+            if !path.is_file() {
+                return Err(format!("Key file {} does not exist", path.display()));
+            }
+        }
+        Ok(())
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Set the server configuration; has to be called before any key is loaded
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("Configuration already initialized");
+    }
+}
+
+/// The server configuration, the default one if none was set
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn key_files(dir: &Path) -> ConfigOverrides {
+        let mut paths = Vec::new();
+        for name in [
+            "ca-cert.pem",
+            "ca-key.pem",
+            "server-cert.pem",
+            "server-key.pem",
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, b"").unwrap();
+            paths.push(Some(path));
+        }
+        ConfigOverrides {
+            ca_cert: paths[0].take(),
+            ca_key: paths[1].take(),
+            server_cert: paths[2].take(),
+            server_key: paths[3].take(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::resolve(key_files(dir.path()), None).unwrap();
+        assert_eq!(config.stale_task_timeout, 30);
+        assert_eq!(config.liveness_window, 5);
+        assert_eq!(config.max_pdf_size, 8 * 1024 * 1024);
+        assert_eq!(config.max_name_length, 64);
+        assert_eq!(config.max_document_name_length, 256);
+        assert_eq!(config.max_group_shares, 256);
+        assert_eq!(config.device_cert_validity, 365 * 4 + 1);
+        assert_eq!(config.server_key, dir.path().join("server-key.pem"));
+    }
+
+    #[test]
+    fn file_and_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "stale-task-timeout = 60\nliveness-window = 10\nmax-name-length = 32\nmetrics-addr = \"127.0.0.1:9100\"\nca-key = \"{}\"",
+            dir.path().join("ca-key.pem").display()
+        )
+        .unwrap();
+
+        let overrides = ConfigOverrides {
+            stale_task_timeout: Some(45),
+            ..key_files(dir.path())
+        };
+        let config = Config::resolve(overrides, Some(file.path())).unwrap();
+        assert_eq!(config.stale_task_timeout, 45);
+        assert_eq!(config.liveness_window, 10);
+        assert_eq!(config.max_name_length, 32);
+        assert_eq!(config.max_pdf_size, 8 * 1024 * 1024);
+        assert_eq!(config.metrics_addr, Some("127.0.0.1:9100".parse().unwrap()));
+    }
+
+    #[test]
+    fn server_settings() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "addr = \"0.0.0.0\"\nport = 1400\ndatabase = \"data/meesign.db\"\npdf-backend = \"java\"\ngroup-cert-organization = \"MeeSign\"\ngroup-cert-key-usage = [\"digital-signature\"]\ndecrypt-completion-timeout = 600\ntask-retention = 0\ntask-hard-retention = 0\ntask-cleanup = \"purge\""
+        )
+        .unwrap();
+
+        let overrides = ConfigOverrides {
+            port: Some(1500),
+            ..Default::default()
+        };
+        let config = Config::resolve(overrides, Some(file.path())).unwrap();
+        assert_eq!(config.addr, "0.0.0.0");
+        assert_eq!(config.port, 1500);
+        assert_eq!(config.database, PathBuf::from("data/meesign.db"));
+        assert_eq!(config.pdf_backend, pdf::Backend::Java);
+        assert_eq!(
+            config.group_certificate.organization.as_deref(),
+            Some("MeeSign")
+        );
+        assert_eq!(
+            config.group_certificate.key_usage,
+            vec![KeyUsageFlag::DigitalSignature]
+        );
+        assert_eq!(config.group_certificate.validity_days, 365 * 4 + 1);
+        assert_eq!(config.task_limits.decrypt_completion_timeout, 600);
+        assert_eq!(config.task_limits.decrypt_approval_timeout, 86400);
+        assert_eq!(config.task_limits.task_retention, 0);
+        assert_eq!(config.task_limits.task_hard_retention, 0);
+        assert_eq!(config.task_limits.task_cleanup, TaskCleanup::Purge);
+    }
+
+    #[test]
+    fn invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let resolve =
+            |overrides: ConfigOverrides| Config::resolve(overrides.or(key_files(dir.path())), None);
+        assert!(resolve(ConfigOverrides {
+            stale_task_timeout: Some(0),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(resolve(ConfigOverrides {
+            max_name_length: Some(65),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(resolve(ConfigOverrides {
+            group_cert_validity: Some(0),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(resolve(ConfigOverrides {
+            task_retention: Some(3600),
+            task_hard_retention: Some(60),
+            ..Default::default()
+        })
+        .is_err());
+
+        // keys are only needed when the server is started
+        let config = resolve(ConfigOverrides {
+            server_cert: Some(dir.path().join("missing.pem")),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(config.check_keys().is_err());
+        assert!(Config::resolve(key_files(dir.path()), None)
+            .unwrap()
+            .check_keys()
+            .is_ok());
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "max-pdf-sise = 1024").unwrap();
+        assert!(Config::resolve(key_files(dir.path()), Some(file.path())).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use time::OffsetDateTime;
+
+pub fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut result = vec![tag];
+    if content.len() < 0x80 {
+        result.push(content.len() as u8);
+    } else {
+        let length = content.len().to_be_bytes();
+        let skip = length.iter().take_while(|byte| **byte == 0).count();
+        result.push(0x80 | (length.len() - skip) as u8);
+        result.extend_from_slice(&length[skip..]);
+    }
+    result.extend_from_slice(content);
+    result
+}
+
+pub fn oid(encoded: &[u8]) -> Vec<u8> {
+    der(0x06, encoded)
+}
+
+/// Encode an unsigned big-endian integer
+pub fn integer(value: &[u8]) -> Vec<u8> {
+    let skip = value.iter().take_while(|byte| **byte == 0).count();
+    let mut content = value[skip..].to_vec();
+    if content.first().map(|byte| byte & 0x80 != 0).unwrap_or(true) {
+        content.insert(0, 0);
+    }
+    der(0x02, &content)
+}
+
+pub fn sequence(items: &[Vec<u8>]) -> Vec<u8> {
+    der(0x30, &items.concat())
+}
+
+/// Encode DER SET OF, which orders its elements by their encoding
+pub fn set(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut items = items.to_vec();
+    items.sort();
+    der(0x31, &items.concat())
+}
+
+/// Encode UTCTime (dates before 2050) or GeneralizedTime as required by RFC 5280
+pub fn time(timestamp: u64) -> Vec<u8> {
+    let time =
+        OffsetDateTime::from_unix_timestamp(timestamp as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    let (tag, year) = if time.year() < 2050 {
+        (0x17, format!("{:02}", time.year() % 100))
+    } else {
+        (0x18, format!("{:04}", time.year()))
+    };
+    let encoded = format!(
+        "{}{:02}{:02}{:02}{:02}{:02}Z",
+        year,
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    );
+    der(tag, encoded.as_bytes())
+}
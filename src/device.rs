@@ -1,11 +1,13 @@
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub struct Device {
     identifier: Vec<u8>,
     name: String,
-    certificate: Vec<u8>,
+    /// Current certificate; replaced when the device renews it
+    certificate: RwLock<Vec<u8>>,
     last_active: AtomicU64,
 }
 
@@ -16,7 +18,7 @@ impl Device {
         Device {
             identifier,
             name,
-            certificate,
+            certificate: RwLock::new(certificate),
             last_active: AtomicU64::new(
                 SystemTime::now()
                     .duration_since(UNIX_EPOCH)
@@ -34,8 +36,13 @@ impl Device {
         &self.name
     }
 
-    pub fn certificate(&self) -> &[u8] {
-        &self.certificate
+    pub fn certificate(&self) -> Vec<u8> {
+        self.certificate.read().unwrap().clone()
+    }
+
+    pub fn set_certificate(&self, certificate: Vec<u8>) {
+        assert!(!certificate.is_empty());
+        *self.certificate.write().unwrap() = certificate;
     }
 
     pub fn last_active(&self) -> u64 {
@@ -59,7 +66,7 @@ impl From<&Device> for crate::proto::Device {
         crate::proto::Device {
             identifier: device.identifier().to_vec(),
             name: device.name().to_string(),
-            certificate: device.certificate().to_vec(),
+            certificate: device.certificate(),
             last_active: device.last_active(),
         }
     }
@@ -103,10 +110,13 @@ mod tests {
         let device = Device::new(identifier.clone(), name.clone(), certificate.clone());
         assert_eq!(device.identifier(), &identifier);
         assert_eq!(device.name(), &name);
-        assert_eq!(device.certificate(), &certificate);
+        assert_eq!(device.certificate(), certificate);
         let previous_active = device.last_active();
         let activated = device.activated();
         assert!(previous_active <= device.last_active());
         assert_eq!(device.last_active(), activated);
+
+        device.set_certificate(vec![0x12, 0x34]);
+        assert_eq!(device.certificate(), vec![0x12, 0x34]);
     }
 }
@@ -6,7 +6,10 @@ use tonic::codegen::Arc;
 pub struct Group {
     identifier: Vec<u8>,
     name: String,
+    /// Member devices, each listed once
     devices: Vec<Arc<Device>>,
+    /// Number of key shares held by each of `devices`
+    shares: Vec<u32>,
     threshold: u32,
     protocol: ProtocolType,
     key_type: KeyType,
@@ -14,6 +17,8 @@ pub struct Group {
 }
 
 impl Group {
+    /// Constructs a group whose key shares are held by `devices`; a device holding
+    /// several shares is listed once per share
     pub fn new(
         identifier: Vec<u8>,
         name: String,
@@ -26,10 +31,25 @@ impl Group {
         assert!(!identifier.is_empty());
         assert!(threshold >= 1);
         assert!(threshold as usize <= devices.len());
+        let mut members: Vec<Arc<Device>> = Vec::new();
+        let mut shares = Vec::new();
+        for device in devices {
+            match members
+                .iter()
+                .position(|member| member.identifier() == device.identifier())
+            {
+                Some(idx) => shares[idx] += 1,
+                None => {
+                    members.push(device);
+                    shares.push(1);
+                }
+            }
+        }
         Group {
             identifier,
             name,
-            devices,
+            devices: members,
+            shares,
             threshold,
             protocol,
             key_type,
@@ -50,13 +70,35 @@ impl Group {
     }
 
     pub fn reject_threshold(&self) -> u32 {
-        self.devices.len() as u32 - self.threshold + 1 // rejects >= threshold_reject => fail
+        self.share_count() - self.threshold + 1 // rejects >= threshold_reject => fail
     }
 
     pub fn devices(&self) -> &[Arc<Device>] {
         &self.devices
     }
 
+    /// Number of key shares held by each device of `devices()`
+    pub fn shares(&self) -> &[u32] {
+        &self.shares
+    }
+
+    /// Total number of key shares
+    pub fn share_count(&self) -> u32 {
+        self.shares.iter().sum()
+    }
+
+    /// Devices listed once per key share they hold, sorted by their identifiers
+    pub fn share_holders(&self) -> Vec<Arc<Device>> {
+        let mut holders: Vec<_> = self
+            .devices
+            .iter()
+            .zip(&self.shares)
+            .flat_map(|(device, shares)| (0..*shares).map(|_| device.clone()))
+            .collect();
+        holders.sort_by_key(|device| device.identifier().to_vec());
+        holders
+    }
+
     pub fn contains(&self, device_id: &[u8]) -> bool {
         self.devices
             .iter()
@@ -90,6 +132,7 @@ impl From<&Group> for crate::proto::Group {
                 .collect(),
             protocol: group.protocol().into(),
             key_type: group.key_type().into(),
+            shares: group.shares().to_vec(),
         }
     }
 }
@@ -138,6 +181,35 @@ mod tests {
         );
         assert_eq!(protobuf.protocol, group.protocol() as i32);
         assert_eq!(protobuf.key_type, group.key_type() as i32);
+        assert_eq!(protobuf.shares, vec![1; 3]);
+    }
+
+    #[test]
+    fn weighted_group() {
+        let devices = prepare_devices(4);
+        let mut holders = devices.clone();
+        holders.insert(1, devices[3].clone());
+        let group = Group::new(
+            vec![0x00],
+            String::from("Weighted Group"),
+            holders,
+            3,
+            ProtocolType::Frost,
+            KeyType::SignChallenge,
+            None,
+        );
+        assert_eq!(group.devices().len(), 4);
+        assert_eq!(group.shares(), &[1, 2, 1, 1]);
+        assert_eq!(group.share_count(), 5);
+        assert_eq!(group.reject_threshold(), 3);
+        assert_eq!(
+            group
+                .share_holders()
+                .iter()
+                .map(|device| device.identifier()[0])
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 3]
+        );
     }
 
     #[test]
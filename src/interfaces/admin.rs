@@ -0,0 +1,382 @@
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+
+use log::{info, warn};
+use tonic::codegen::Arc;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::audit::{self, Event};
+use crate::interfaces::grpc::{cert_fingerprint, format_task, server_tls};
+use crate::interfaces::listing;
+use crate::metrics;
+use crate::policy::{Action, Policy};
+use crate::proto::admin_server::{Admin, AdminServer};
+use crate::state::State;
+use crate::tasks::transcript::FaultKind;
+use crate::{proto as msg, utils};
+
+/// Administrative service, served separately from the device-facing `Mpc` service
+#[derive(Clone)]
+pub struct AdminService {
+    state: Arc<State>,
+    policy: Arc<Policy>,
+}
+
+impl AdminService {
+    pub fn new(state: Arc<State>, policy: Arc<Policy>) -> Self {
+        AdminService { state, policy }
+    }
+
+    /// Run `f` outside of the async runtime, as it may wait for task locks or storage
+    #[allow(clippy::result_large_err)]
+    async fn blocking<R, F>(&self, f: F) -> Result<R, Status>
+    where
+        R: Send + 'static,
+        F: FnOnce(&AdminService) -> Result<R, Status> + Send + 'static,
+    {
+        let service = self.clone();
+        tokio::task::spawn_blocking(move || f(&service))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+    }
+
+    /// Check that the client certificate belongs to an admin requester
+    ///
+    /// # Returns
+    /// The name of the requester.
+    #[allow(clippy::result_large_err)]
+    fn authorize<T>(&self, request: &Request<T>) -> Result<String, Status> {
+        let certificate = request
+            .peer_certs()
+            .and_then(|certs| certs.first().cloned())
+            .ok_or_else(|| Status::unauthenticated("Admin certificate required"))?;
+        if self.state.is_revoked(certificate.as_ref()) {
+            return Err(Status::unauthenticated("Certificate revoked"));
+        }
+        let requester = self
+            .policy
+            .identify(Some(&cert_fingerprint(&certificate)), None)
+            .filter(|requester| requester.permits(&Action::Administer))
+            .ok_or_else(|| {
+                warn!(
+                    "Admin request denied certificate_id={}",
+                    utils::hextrunc(cert_fingerprint(&certificate))
+                );
+                Status::permission_denied("Admin certificate required")
+            })?;
+        Ok(requester.name.clone())
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_task_id(task_id: &[u8]) -> Result<Uuid, Status> {
+    Uuid::from_slice(task_id).map_err(|_| Status::invalid_argument("Invalid task identifier"))
+}
+
+fn format_faults(faults: &HashMap<FaultKind, u64>) -> msg::DeviceFaults {
+    let count = |kind| faults.get(&kind).copied().unwrap_or(0);
+    msg::DeviceFaults {
+        malformed_messages: count(FaultKind::MalformedMessage),
+        stalls: count(FaultKind::Stalled),
+        missing_outputs: count(FaultKind::MissingOutput),
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn get_devices(
+        &self,
+        request: Request<msg::DevicesRequest>,
+    ) -> Result<Response<msg::Devices>, Status> {
+        let _timer = metrics::rpc_timer("Admin.GetDevices");
+        self.authorize(&request)?;
+
+        let devices = self
+            .state
+            .get_devices()
+            .iter()
+            .map(|device| device.as_ref().into())
+            .collect();
+        Ok(Response::new(msg::Devices { devices }))
+    }
+
+    #[allow(clippy::result_large_err)]
+    async fn get_device(
+        &self,
+        request: Request<msg::DeviceRequest>,
+    ) -> Result<Response<msg::DeviceDetail>, Status> {
+        let _timer = metrics::rpc_timer("Admin.GetDevice");
+        self.authorize(&request)?;
+        let device_id = request.into_inner().device_id;
+
+        self.blocking(move |service| {
+            let state = &service.state;
+            let device = state
+                .get_device(&device_id)
+                .ok_or_else(|| Status::not_found("Unknown device"))?;
+            Ok(Response::new(msg::DeviceDetail {
+                device: Some(device.as_ref().into()),
+                group_ids: state
+                    .get_device_groups(&device_id)
+                    .iter()
+                    .map(|group| group.identifier().to_vec())
+                    .collect(),
+                task_ids: state
+                    .get_device_tasks(&device_id)
+                    .iter()
+                    .map(|(task_id, _)| task_id.as_bytes().to_vec())
+                    .collect(),
+                revoked: state.is_device_revoked(&device_id),
+                subscribed: state.is_subscribed(&device_id),
+                faults: Some(format_faults(&state.get_device_faults(&device_id))),
+            }))
+        })
+        .await
+    }
+
+    async fn remove_device(
+        &self,
+        request: Request<msg::DeviceRemoval>,
+    ) -> Result<Response<msg::Resp>, Status> {
+        let _timer = metrics::rpc_timer("Admin.RemoveDevice");
+        let requester = self.authorize(&request)?;
+        let device_id = request.into_inner().device_id;
+        info!(
+            "DeviceRemoval device_id={} requester={:?}",
+            utils::hextrunc(&device_id),
+            requester
+        );
+
+        let state = self.state.clone();
+        let id = device_id.clone();
+        tokio::task::spawn_blocking(move || state.remove_device(&id))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::failed_precondition)?;
+        audit::record(Event::DeviceRemoved {
+            device_id: hex::encode(device_id),
+            requester: Some(requester),
+        });
+
+        Ok(Response::new(msg::Resp {
+            message: "OK".into(),
+        }))
+    }
+
+    async fn revoke_device(
+        &self,
+        request: Request<msg::DeviceRevocation>,
+    ) -> Result<Response<msg::Resp>, Status> {
+        let _timer = metrics::rpc_timer("Admin.RevokeDevice");
+        let requester = self.authorize(&request)?;
+        let device_id = request.into_inner().device_id;
+        info!(
+            "DeviceRevocation device_id={} requester={:?}",
+            utils::hextrunc(&device_id),
+            requester
+        );
+
+        let state = self.state.clone();
+        let id = device_id.clone();
+        tokio::task::spawn_blocking(move || state.revoke_device(&id))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::failed_precondition)?;
+        audit::record(Event::DeviceRevoked {
+            device_id: hex::encode(device_id),
+            requester: Some(requester),
+        });
+
+        Ok(Response::new(msg::Resp {
+            message: "OK".into(),
+        }))
+    }
+
+    async fn get_groups(
+        &self,
+        request: Request<msg::GroupsRequest>,
+    ) -> Result<Response<msg::Groups>, Status> {
+        let _timer = metrics::rpc_timer("Admin.GetGroups");
+        self.authorize(&request)?;
+
+        let request = request.into_inner();
+        let groups = match &request.device_id {
+            Some(device_id) => self.state.get_device_groups(device_id),
+            None => self.state.get_groups(),
+        };
+        Ok(Response::new(listing::groups_page(groups, &request)?))
+    }
+
+    async fn delete_group(
+        &self,
+        request: Request<msg::GroupDeletion>,
+    ) -> Result<Response<msg::Resp>, Status> {
+        let _timer = metrics::rpc_timer("Admin.DeleteGroup");
+        let requester = self.authorize(&request)?;
+        let group_id = request.into_inner().group_id;
+        info!(
+            "GroupDeletion group_id={} requester={:?}",
+            utils::hextrunc(&group_id),
+            requester
+        );
+
+        let state = self.state.clone();
+        let id = group_id.clone();
+        tokio::task::spawn_blocking(move || state.remove_group(&id))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::failed_precondition)?;
+        audit::record(Event::GroupRemoved {
+            group_id: hex::encode(group_id),
+            requester: Some(requester),
+        });
+
+        Ok(Response::new(msg::Resp {
+            message: "OK".into(),
+        }))
+    }
+
+    #[allow(clippy::result_large_err)]
+    async fn get_tasks(
+        &self,
+        request: Request<msg::TasksRequest>,
+    ) -> Result<Response<msg::Tasks>, Status> {
+        let _timer = metrics::rpc_timer("Admin.GetTasks");
+        self.authorize(&request)?;
+
+        let request = request.into_inner();
+        self.blocking(move |service| {
+            let tasks = match &request.device_id {
+                Some(device_id) => service.state.get_device_tasks(device_id),
+                None => service.state.get_tasks(),
+            };
+            Ok(Response::new(listing::tasks_page(
+                tasks, &request, None, true,
+            )?))
+        })
+        .await
+    }
+
+    async fn cancel_task(
+        &self,
+        request: Request<msg::TaskCancellation>,
+    ) -> Result<Response<msg::Resp>, Status> {
+        let _timer = metrics::rpc_timer("Admin.CancelTask");
+        let requester = self.authorize(&request)?;
+        let task_id = parse_task_id(&request.get_ref().task_id)?;
+        info!(
+            "TaskCancellation task_id={} requester={:?}",
+            utils::hextrunc(task_id.as_bytes()),
+            requester
+        );
+
+        let state = self.state.clone();
+        tokio::task::spawn_blocking(move || state.cancel_task(&task_id))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::failed_precondition)?;
+        audit::record(Event::TaskCancelled {
+            task_id: hex::encode(task_id.as_bytes()),
+            requester: Some(requester),
+        });
+
+        Ok(Response::new(msg::Resp {
+            message: "OK".into(),
+        }))
+    }
+
+    async fn restart_task(
+        &self,
+        request: Request<msg::TaskRestart>,
+    ) -> Result<Response<msg::Resp>, Status> {
+        let _timer = metrics::rpc_timer("Admin.RestartTask");
+        let requester = self.authorize(&request)?;
+        let task_id = parse_task_id(&request.get_ref().task_id)?;
+        info!(
+            "TaskRestart task_id={} requester={:?}",
+            utils::hextrunc(task_id.as_bytes()),
+            requester
+        );
+
+        let state = self.state.clone();
+        let restarted = tokio::task::spawn_blocking(move || state.restart_task(&task_id))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        if !restarted {
+            return Err(Status::failed_precondition(
+                "Only approved unfinished tasks can be restarted",
+            ));
+        }
+        audit::record(Event::TaskRestarted {
+            task_id: hex::encode(task_id.as_bytes()),
+            requester: Some(requester),
+        });
+
+        Ok(Response::new(msg::Resp {
+            message: "OK".into(),
+        }))
+    }
+
+    #[allow(clippy::result_large_err)]
+    async fn dump_state(
+        &self,
+        request: Request<msg::StateDumpRequest>,
+    ) -> Result<Response<msg::StateDump>, Status> {
+        let _timer = metrics::rpc_timer("Admin.DumpState");
+        self.authorize(&request)?;
+
+        self.blocking(|service| {
+            let state = &service.state;
+            // devices may be subscribed more than once
+            let subscribers: BTreeSet<_> = state
+                .get_subscribers()
+                .into_iter()
+                .map(|(device_id, _)| device_id)
+                .collect();
+            Ok(Response::new(msg::StateDump {
+                devices: state
+                    .get_devices()
+                    .iter()
+                    .map(|device| device.as_ref().into())
+                    .collect(),
+                groups: state
+                    .get_groups()
+                    .iter()
+                    .map(|group| group.into())
+                    .collect(),
+                tasks: state
+                    .get_tasks()
+                    .iter()
+                    .map(|(task_id, task)| {
+                        let task = task.lock().unwrap();
+                        format_task(task_id, task.as_ref(), None, Some(task.get_request()))
+                    })
+                    .collect(),
+                revoked_devices: state.get_revoked_devices(),
+                subscribers: subscribers.into_iter().collect(),
+            }))
+        })
+        .await
+    }
+}
+
+pub async fn run_admin(
+    state: Arc<State>,
+    policy: Arc<Policy>,
+    addr: SocketAddr,
+) -> Result<(), String> {
+    if !policy.is_restricted() {
+        warn!("No requester policy configured, all admin requests will be denied");
+    }
+    info!("Admin service listening on {}", addr);
+
+    Server::builder()
+        .tls_config(server_tls(false).await?)
+        .map_err(|_| "Unable to setup TLS for admin server")?
+        .add_service(AdminServer::new(AdminService::new(state, policy)))
+        .serve(addr)
+        .await
+        .map_err(|_| String::from("Unable to run admin server"))
+}
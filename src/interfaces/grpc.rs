@@ -1,15 +1,15 @@
-use log::{debug, info};
+use log::{debug, info, warn};
 use openssl::asn1::{Asn1Integer, Asn1Time};
 use openssl::bn::BigNum;
 use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
 use openssl::pkey::PKey;
 use openssl::x509::extension::{
     AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectKeyIdentifier,
 };
-use openssl::x509::{X509Builder, X509NameBuilder, X509Req};
+use openssl::x509::{X509Builder, X509NameBuilder, X509Req, X509};
 use rand::Rng;
 use tokio::sync::mpsc;
-use tokio::sync::Mutex;
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::Stream;
 use tonic::codegen::Arc;
@@ -17,21 +17,342 @@ use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
 use tonic::{Request, Response, Status};
 use uuid::Uuid;
 
+use crate::audit::{self, Event};
+use crate::communicator::Selection;
+use crate::config;
+use crate::interfaces::listing;
+use crate::metrics;
+use crate::policy::{Action, Policy, Requester};
 use crate::proto::mpc_server::{Mpc, MpcServer};
 use crate::proto::{KeyType, ProtocolType};
 use crate::state::State;
-use crate::tasks::{Task, TaskStatus};
+use crate::tasks::group::check_shares;
+use crate::tasks::{Task, TaskResult, TaskStatus};
 use crate::{proto as msg, utils, CA_CERT, CA_KEY};
 
 use std::pin::Pin;
 
+/// Authenticated caller of a listing or subscription RPC
+enum Caller<'a> {
+    Device(Vec<u8>),
+    Requester(&'a Requester),
+}
+
+#[derive(Clone)]
 pub struct MPCService {
-    state: Arc<Mutex<State>>,
+    state: Arc<State>,
+    policy: Arc<Policy>,
 }
 
 impl MPCService {
-    pub fn new(state: Arc<Mutex<State>>) -> Self {
-        MPCService { state }
+    pub fn new(state: Arc<State>, policy: Arc<Policy>) -> Self {
+        MPCService { state, policy }
+    }
+
+    /// Run `f` outside of the async runtime, as it may wait for task locks or storage
+    #[allow(clippy::result_large_err)]
+    async fn blocking<R, F>(&self, f: F) -> Result<R, Status>
+    where
+        R: Send + 'static,
+        F: FnOnce(&MPCService) -> Result<R, Status> + Send + 'static,
+    {
+        let service = self.clone();
+        tokio::task::spawn_blocking(move || f(&service))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+    }
+
+    /// Check that the requester identified by its client certificate or API token
+    /// is allowed to perform `action`
+    ///
+    /// # Returns
+    /// The name of the requester; `None` if requests are not restricted.
+    #[allow(clippy::result_large_err)]
+    fn authorize<T>(&self, request: &Request<T>, action: Action) -> Result<Option<String>, Status> {
+        if !self.policy.is_restricted() {
+            return Ok(None);
+        }
+        let certificate = request
+            .peer_certs()
+            .and_then(|certs| certs.first().cloned());
+        if let Some(certificate) = &certificate {
+            if self.state.is_revoked(certificate.as_ref()) {
+                return Err(Status::unauthenticated("Certificate revoked"));
+            }
+        }
+        let certificate_id = certificate.map(cert_fingerprint);
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        let requester = self
+            .policy
+            .identify(certificate_id.as_deref(), token)
+            .ok_or_else(|| Status::unauthenticated("Unknown requester"))?;
+        if !requester.permits(&action) {
+            warn!("Requester not authorized requester={:?}", requester.name);
+            return Err(Status::permission_denied(
+                "Request not permitted by the policy",
+            ));
+        }
+        Ok(Some(requester.name.clone()))
+    }
+
+    /// Identify the caller by its client certificate or API token
+    #[allow(clippy::result_large_err)]
+    fn identify_caller<T>(&self, request: &Request<T>) -> Result<Caller<'_>, Status> {
+        let certificate = request
+            .peer_certs()
+            .and_then(|certs| certs.first().map(|cert| cert.as_ref().to_vec()));
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        self.caller(certificate.as_deref(), token)
+    }
+
+    /// Registered devices are identified by their certificate, other callers have to be
+    /// requesters known to the policy
+    #[allow(clippy::result_large_err)]
+    fn caller(
+        &self,
+        certificate: Option<&[u8]>,
+        token: Option<&str>,
+    ) -> Result<Caller<'_>, Status> {
+        if let Some(certificate) = certificate {
+            if self.state.is_revoked(certificate) {
+                return Err(Status::unauthenticated("Certificate revoked"));
+            }
+            let device_id = cert_to_id(certificate);
+            if self.state.get_device(&device_id).is_some() {
+                return Ok(Caller::Device(device_id));
+            }
+        }
+        self.policy
+            .identify(certificate.map(cert_fingerprint).as_deref(), token)
+            .map(Caller::Requester)
+            .ok_or_else(|| Status::unauthenticated("Authentication required"))
+    }
+
+    /// Page of tasks of the calling device, or of tasks the calling requester created
+    #[allow(clippy::result_large_err)]
+    fn caller_tasks(
+        &self,
+        caller: &Caller,
+        request: &msg::TasksRequest,
+    ) -> Result<msg::Tasks, Status> {
+        match caller {
+            Caller::Device(caller_id) => {
+                if request
+                    .device_id
+                    .as_ref()
+                    .is_some_and(|device_id| device_id != caller_id)
+                {
+                    warn!(
+                        "Tasks of another device requested device_id={}",
+                        utils::hextrunc(caller_id)
+                    );
+                    return Err(Status::permission_denied(
+                        "Tasks of other devices are not accessible",
+                    ));
+                }
+                self.state.device_activated(caller_id);
+                let tasks = self.state.get_device_tasks(caller_id);
+                listing::tasks_page(tasks, request, Some(caller_id), false)
+            }
+            Caller::Requester(requester) => {
+                if request.device_id.is_some() {
+                    return Err(Status::permission_denied(
+                        "Tasks of devices are only accessible to the devices",
+                    ));
+                }
+                let tasks = self
+                    .state
+                    .get_tasks()
+                    .into_iter()
+                    .filter(|(task_id, _)| {
+                        self.state.get_task_requester(task_id).as_deref()
+                            == Some(requester.name.as_str())
+                    })
+                    .collect();
+                listing::tasks_page(tasks, request, None, false)
+            }
+        }
+    }
+
+    /// Task of the calling device, or a task the calling requester may create
+    #[allow(clippy::result_large_err)]
+    fn caller_task(
+        &self,
+        caller: &Caller,
+        request: &msg::TaskRequest,
+    ) -> Result<msg::Task, Status> {
+        let task_id = Uuid::from_slice(&request.task_id)
+            .map_err(|_| Status::invalid_argument("Invalid task identifier"))?;
+        match caller {
+            Caller::Device(caller_id) => {
+                if request
+                    .device_id
+                    .as_ref()
+                    .is_some_and(|device_id| device_id != caller_id)
+                {
+                    warn!(
+                        "Task of another device requested device_id={}",
+                        utils::hextrunc(caller_id)
+                    );
+                    return Err(Status::permission_denied(
+                        "Tasks of other devices are not accessible",
+                    ));
+                }
+                self.state.device_activated(caller_id);
+            }
+            Caller::Requester(_) => {
+                if request.device_id.is_some() {
+                    return Err(Status::permission_denied(
+                        "Tasks of devices are only accessible to the devices",
+                    ));
+                }
+            }
+        }
+
+        let task = self
+            .state
+            .get_task(&task_id)
+            .ok_or_else(|| Status::not_found("Unknown task"))?;
+        let task = task.lock().unwrap();
+        let device_id = match caller {
+            Caller::Device(caller_id) => {
+                if !task.has_device(caller_id) {
+                    warn!(
+                        "Task of another device requested device_id={}",
+                        utils::hextrunc(caller_id)
+                    );
+                    return Err(Status::permission_denied(
+                        "Tasks of other devices are not accessible",
+                    ));
+                }
+                Some(caller_id.as_slice())
+            }
+            Caller::Requester(requester) => {
+                if !task_action(task.as_ref()).is_some_and(|action| requester.permits(&action)) {
+                    warn!("Requester not authorized requester={:?}", requester.name);
+                    return Err(Status::permission_denied(
+                        "Request not permitted by the policy",
+                    ));
+                }
+                None
+            }
+        };
+        Ok(format_task(
+            &task_id,
+            task.as_ref(),
+            device_id,
+            Some(task.get_request()),
+        ))
+    }
+
+    /// Page of groups of the calling device, or of groups the calling requester may use
+    #[allow(clippy::result_large_err)]
+    fn caller_groups(
+        &self,
+        caller: &Caller,
+        request: &msg::GroupsRequest,
+    ) -> Result<msg::Groups, Status> {
+        match caller {
+            Caller::Device(caller_id) => {
+                if request
+                    .device_id
+                    .as_ref()
+                    .is_some_and(|device_id| device_id != caller_id)
+                {
+                    warn!(
+                        "Groups of another device requested device_id={}",
+                        utils::hextrunc(caller_id)
+                    );
+                    return Err(Status::permission_denied(
+                        "Groups of other devices are not accessible",
+                    ));
+                }
+                self.state.device_activated(caller_id);
+                listing::groups_page(self.state.get_device_groups(caller_id), request)
+            }
+            Caller::Requester(requester) => {
+                if request.device_id.is_some() {
+                    return Err(Status::permission_denied(
+                        "Groups of devices are only accessible to the devices",
+                    ));
+                }
+                let groups = self
+                    .state
+                    .get_groups()
+                    .into_iter()
+                    .filter(|group| {
+                        requester.permits(&Action::UseGroup(group.identifier().to_vec()))
+                    })
+                    .collect();
+                listing::groups_page(groups, request)
+            }
+        }
+    }
+
+    /// All devices for a registered device, devices the calling requester may group otherwise
+    fn caller_devices(&self, caller: &Caller) -> Vec<msg::Device> {
+        self.state
+            .get_devices()
+            .iter()
+            .filter(|device| match caller {
+                Caller::Device(_) => true,
+                Caller::Requester(requester) => {
+                    requester.permits(&Action::CreateGroup(vec![device.identifier().to_vec()]))
+                }
+            })
+            .map(|device| device.as_ref().into())
+            .collect()
+    }
+
+    /// Check that the requester created the task and may still create it, which lets
+    /// it cancel the task and fetch its result
+    #[allow(clippy::result_large_err)]
+    fn authorize_task<T>(&self, request: &Request<T>, task_id: &Uuid) -> Result<(), Status> {
+        if !self.policy.is_restricted() {
+            return Ok(());
+        }
+        let action = self
+            .state
+            .get_task(task_id)
+            .and_then(|task| task_action(task.lock().unwrap().as_ref()))
+            .ok_or_else(|| Status::failed_precondition("Unknown task"))?;
+        let requester = self.authorize(request, action)?;
+        if self.state.get_task_requester(task_id) != requester {
+            warn!(
+                "Task of another requester requested requester={:?}",
+                requester
+            );
+            return Err(Status::permission_denied(
+                "Tasks of other requesters are not accessible",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Identify the device by its client certificate, which must not be revoked
+    #[allow(clippy::result_large_err)]
+    fn authenticate_device<T>(&self, request: &Request<T>) -> Result<Vec<u8>, Status> {
+        let certificate = request
+            .peer_certs()
+            .and_then(|certs| certs.first().cloned())
+            .ok_or_else(|| Status::unauthenticated("Authentication required"))?;
+        if self.state.is_revoked(certificate.as_ref()) {
+            warn!(
+                "Revoked certificate presented device_id={}",
+                utils::hextrunc(cert_to_id(&certificate))
+            );
+            return Err(Status::unauthenticated("Certificate revoked"));
+        }
+        Ok(cert_to_id(certificate))
     }
 }
 
@@ -39,11 +360,14 @@ impl MPCService {
 impl Mpc for MPCService {
     type SubscribeUpdatesStream =
         Pin<Box<dyn Stream<Item = Result<msg::Task, Status>> + Send + 'static>>;
+    type WatchTaskStream =
+        Pin<Box<dyn Stream<Item = Result<msg::TaskResult, Status>> + Send + 'static>>;
 
     async fn get_server_info(
         &self,
         _request: Request<msg::ServerInfoRequest>,
     ) -> Result<Response<msg::ServerInfo>, Status> {
+        let _timer = metrics::rpc_timer("GetServerInfo");
         debug!("ServerInfoRequest");
         Ok(Response::new(msg::ServerInfo {
             version: crate::VERSION.unwrap_or("unknown").to_string(),
@@ -54,16 +378,15 @@ impl Mpc for MPCService {
         &self,
         request: Request<msg::RegistrationRequest>,
     ) -> Result<Response<msg::RegistrationResponse>, Status> {
+        let _timer = metrics::rpc_timer("Register");
         let request = request.into_inner();
         let name = request.name;
         let csr = request.csr;
         info!("RegistrationRequest name={:?}", name);
 
-        let mut state = self.state.lock().await;
-
-        if let Ok(certificate) = issue_certificate(&name, &csr) {
+        if let Ok(certificate) = issue_certificate(&name, &csr, None) {
             let device_id = cert_to_id(&certificate);
-            if state.add_device(&device_id, &name, &certificate) {
+            if self.state.add_device(&device_id, &name, &certificate) {
                 Ok(Response::new(msg::RegistrationResponse {
                     device_id,
                     certificate,
@@ -80,89 +403,209 @@ impl Mpc for MPCService {
         }
     }
 
+    #[allow(clippy::result_large_err)]
     async fn sign(
         &self,
         request: Request<msg::SignRequest>,
     ) -> Result<Response<msg::Task>, Status> {
+        let _timer = metrics::rpc_timer("Sign");
+        let requester = self.authorize(
+            &request,
+            Action::UseGroup(request.get_ref().group_id.clone()),
+        )?;
         let request = request.into_inner();
+        let selection = Selection {
+            mode: request.selection(),
+            pinned: request.participants,
+        };
         let group_id = request.group_id;
         let name = request.name;
         let data = request.data;
-        info!("SignRequest group_id={}", utils::hextrunc(&group_id));
+        info!(
+            "SignRequest group_id={} pinned={} selection={:?}",
+            utils::hextrunc(&group_id),
+            selection.pinned.len(),
+            selection.mode
+        );
 
-        let mut state = self.state.lock().await;
-        if let Some(task_id) = state.add_sign_task(&group_id, &name, &data) {
-            let task = state.get_task(&task_id).unwrap();
-            Ok(Response::new(format_task(&task_id, task, None, None)))
-        } else {
-            Err(Status::failed_precondition("Request failed"))
-        }
+        self.blocking(move |service| {
+            if let Some(task_id) = service.state.add_sign_task(
+                &group_id,
+                &name,
+                &data,
+                selection,
+                requester.as_deref(),
+            ) {
+                let task = service
+                    .state
+                    .get_task(&task_id)
+                    .ok_or_else(|| Status::not_found("Unknown task"))?;
+                let task = task.lock().unwrap();
+                audit::record(Event::TaskRequested {
+                    task_id: hex::encode(task_id.as_bytes()),
+                    task_type: task.get_type().as_str_name().into(),
+                    name,
+                    requester,
+                    group_id: Some(hex::encode(&group_id)),
+                    devices: Vec::new(),
+                });
+                Ok(Response::new(format_task(
+                    &task_id,
+                    task.as_ref(),
+                    None,
+                    None,
+                )))
+            } else {
+                Err(Status::failed_precondition("Request failed"))
+            }
+        })
+        .await
     }
 
+    #[allow(clippy::result_large_err)]
     async fn decrypt(
         &self,
         request: Request<msg::DecryptRequest>,
     ) -> Result<Response<msg::Task>, Status> {
+        let _timer = metrics::rpc_timer("Decrypt");
+        let requester = self.authorize(
+            &request,
+            Action::UseGroup(request.get_ref().group_id.clone()),
+        )?;
         let request = request.into_inner();
+        let selection = Selection {
+            mode: request.selection(),
+            pinned: request.participants,
+        };
         let group_id = request.group_id;
         let name = request.name;
         let data = request.data;
         let data_type = request.data_type;
-        info!("DecryptRequest group_id={}", utils::hextrunc(&group_id));
+        info!(
+            "DecryptRequest group_id={} pinned={} selection={:?}",
+            utils::hextrunc(&group_id),
+            selection.pinned.len(),
+            selection.mode
+        );
 
-        let mut state = self.state.lock().await;
-        if let Some(task_id) = state.add_decrypt_task(&group_id, &name, &data, &data_type) {
-            let task = state.get_task(&task_id).unwrap();
-            Ok(Response::new(format_task(&task_id, task, None, None)))
-        } else {
-            Err(Status::failed_precondition("Request failed"))
-        }
+        self.blocking(move |service| {
+            if let Some(task_id) = service.state.add_decrypt_task(
+                &group_id,
+                &name,
+                &data,
+                &data_type,
+                selection,
+                requester.as_deref(),
+            ) {
+                let task = service
+                    .state
+                    .get_task(&task_id)
+                    .ok_or_else(|| Status::not_found("Unknown task"))?;
+                let task = task.lock().unwrap();
+                audit::record(Event::TaskRequested {
+                    task_id: hex::encode(task_id.as_bytes()),
+                    task_type: task.get_type().as_str_name().into(),
+                    name,
+                    requester,
+                    group_id: Some(hex::encode(&group_id)),
+                    devices: Vec::new(),
+                });
+                Ok(Response::new(format_task(
+                    &task_id,
+                    task.as_ref(),
+                    None,
+                    None,
+                )))
+            } else {
+                Err(Status::failed_precondition("Request failed"))
+            }
+        })
+        .await
     }
 
+    #[allow(clippy::result_large_err)]
     async fn get_task(
         &self,
         request: Request<msg::TaskRequest>,
     ) -> Result<Response<msg::Task>, Status> {
-        let request = request.into_inner();
-        let task_id = Uuid::from_slice(&request.task_id).unwrap();
-        let device_id = request.device_id;
-        let device_id = if device_id.is_none() {
-            None
-        } else {
-            Some(device_id.as_ref().unwrap().as_slice())
-        };
+        let _timer = metrics::rpc_timer("GetTask");
+        self.blocking(move |service| {
+            let caller = service.identify_caller(&request)?;
+            let request = request.into_inner();
+            debug!(
+                "TaskRequest task_id={} device_id={}",
+                utils::hextrunc(&request.task_id),
+                utils::hextrunc(request.device_id.as_deref().unwrap_or(&[]))
+            );
+
+            Ok(Response::new(service.caller_task(&caller, &request)?))
+        })
+        .await
+    }
+
+    #[allow(clippy::result_large_err)]
+    async fn get_task_result(
+        &self,
+        request: Request<msg::TaskResultRequest>,
+    ) -> Result<Response<msg::TaskResult>, Status> {
+        let _timer = metrics::rpc_timer("GetTaskResult");
+        let task_id = Uuid::from_slice(&request.get_ref().task_id)
+            .map_err(|_| Status::invalid_argument("Invalid task identifier"))?;
         debug!(
-            "TaskRequest task_id={} device_id={}",
-            utils::hextrunc(task_id.as_bytes()),
-            utils::hextrunc(device_id.unwrap_or(&[]))
+            "TaskResultRequest task_id={}",
+            utils::hextrunc(task_id.as_bytes())
         );
+        self.blocking(move |service| {
+            service.authorize_task(&request, &task_id)?;
 
-        let state = self.state.lock().await;
-        if device_id.is_some() {
-            state.device_activated(device_id.as_ref().unwrap());
-        }
-        let task = state.get_task(&task_id).unwrap();
-        let request = Some(task.get_request());
+            let task = service
+                .state
+                .get_task(&task_id)
+                .ok_or_else(|| Status::not_found("Unknown task"))?;
+            let task = task.lock().unwrap();
+            Ok(Response::new(format_result(&task_id, task.as_ref())))
+        })
+        .await
+    }
 
-        let resp = format_task(&task_id, task, device_id, request);
-        Ok(Response::new(resp))
+    #[allow(clippy::result_large_err)]
+    async fn watch_task(
+        &self,
+        request: Request<msg::TaskResultRequest>,
+    ) -> Result<Response<Self::WatchTaskStream>, Status> {
+        let _timer = metrics::rpc_timer("WatchTask");
+        let task_id = Uuid::from_slice(&request.get_ref().task_id)
+            .map_err(|_| Status::invalid_argument("Invalid task identifier"))?;
+        debug!("WatchTask task_id={}", utils::hextrunc(task_id.as_bytes()));
+        let rx = self
+            .blocking(move |service| {
+                service.authorize_task(&request, &task_id)?;
+
+                let (tx, rx) = mpsc::channel(1);
+                if !service.state.watch_task(&task_id, tx) {
+                    return Err(Status::not_found("Unknown task"));
+                }
+                Ok(rx)
+            })
+            .await?;
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
     }
 
     async fn update_task(
         &self,
         request: Request<msg::TaskUpdate>,
     ) -> Result<Response<msg::Resp>, Status> {
-        if request.peer_certs().is_none() {
-            return Err(Status::unauthenticated("Authentication required"));
-        }
-        let device_id = request
-            .peer_certs()
-            .and_then(|certs| certs.get(0).map(cert_to_id))
-            .unwrap();
+        let _timer = metrics::rpc_timer("UpdateTask");
+        let device_id = self.authenticate_device(&request)?;
 
         let request = request.into_inner();
-        let task_id = Uuid::from_slice(&request.task).unwrap();
-        let data = request.data;
+        let task_id = Uuid::from_slice(&request.task)
+            .map_err(|_| Status::invalid_argument("Invalid task identifier"))?;
+        let data = if request.share_data.is_empty() {
+            vec![request.data]
+        } else {
+            request.share_data
+        };
         let attempt = request.attempt;
         debug!(
             "TaskUpdate task_id={} device_id={} attempt={}",
@@ -171,9 +614,14 @@ impl Mpc for MPCService {
             attempt
         );
 
-        let mut state = self.state.lock().await;
-        state.device_activated(&device_id);
-        let result = state.update_task(&task_id, &device_id, &data, attempt);
+        self.state.device_activated(&device_id);
+        // finishing a round may block, e.g. on the PDF helper
+        let state = self.state.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            state.update_task(&task_id, &device_id, &data, attempt)
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?;
 
         match result {
             Ok(_) => Ok(Response::new(msg::Resp {
@@ -183,123 +631,154 @@ impl Mpc for MPCService {
         }
     }
 
+    #[allow(clippy::result_large_err)]
     async fn get_tasks(
         &self,
         request: Request<msg::TasksRequest>,
     ) -> Result<Response<msg::Tasks>, Status> {
-        let request = request.into_inner();
-        let device_id = request.device_id;
-        let device_str = device_id
-            .as_ref()
-            .map(utils::hextrunc)
-            .unwrap_or_else(|| "unknown".to_string());
-        debug!("TasksRequest device_id={}", device_str);
-
-        let state = self.state.lock().await;
-        let tasks = if let Some(device_id) = device_id {
-            state.device_activated(&device_id);
-            state
-                .get_device_tasks(&device_id)
-                .iter()
-                .map(|(task_id, task)| format_task(task_id, *task, Some(&device_id), None))
-                .collect()
-        } else {
-            state
-                .get_tasks()
-                .iter()
-                .map(|(task_id, task)| format_task(task_id, task.as_ref(), None, None))
-                .collect()
-        };
-
-        Ok(Response::new(msg::Tasks { tasks }))
+        let _timer = metrics::rpc_timer("GetTasks");
+        self.blocking(move |service| {
+            let caller = service.identify_caller(&request)?;
+            let request = request.into_inner();
+            debug!(
+                "TasksRequest device_id={}",
+                request
+                    .device_id
+                    .as_ref()
+                    .map(utils::hextrunc)
+                    .unwrap_or_else(|| "unknown".to_string())
+            );
+
+            Ok(Response::new(service.caller_tasks(&caller, &request)?))
+        })
+        .await
     }
 
     async fn get_groups(
         &self,
         request: Request<msg::GroupsRequest>,
     ) -> Result<Response<msg::Groups>, Status> {
+        let _timer = metrics::rpc_timer("GetGroups");
+        let caller = self.identify_caller(&request)?;
         let request = request.into_inner();
-        let device_id = request.device_id;
-        let device_str = device_id
-            .as_ref()
-            .map(utils::hextrunc)
-            .unwrap_or_else(|| "unknown".to_string());
-        debug!("GroupsRequest device_id={}", device_str);
-
-        let state = self.state.lock().await;
-        let groups = if let Some(device_id) = device_id {
-            state.device_activated(&device_id);
-            state
-                .get_device_groups(&device_id)
-                .iter()
-                .map(|group| group.into())
-                .collect()
-        } else {
-            state
-                .get_groups()
-                .values()
-                .map(|group| group.into())
-                .collect()
-        };
+        debug!(
+            "GroupsRequest device_id={}",
+            request
+                .device_id
+                .as_ref()
+                .map(utils::hextrunc)
+                .unwrap_or_else(|| "unknown".to_string())
+        );
 
-        Ok(Response::new(msg::Groups { groups }))
+        Ok(Response::new(self.caller_groups(&caller, &request)?))
     }
 
+    #[allow(clippy::result_large_err)]
     async fn group(
         &self,
         request: Request<msg::GroupRequest>,
     ) -> Result<Response<msg::Task>, Status> {
+        let _timer = metrics::rpc_timer("Group");
+        let requester = self.authorize(
+            &request,
+            Action::CreateGroup(request.get_ref().device_ids.clone()),
+        )?;
         let request = request.into_inner();
         let name = request.name;
         let device_ids = request.device_ids;
+        let shares = request.shares;
         let threshold = request.threshold;
-        let protocol = ProtocolType::try_from(request.protocol).unwrap();
-        let key_type = KeyType::try_from(request.key_type).unwrap();
+        check_shares(
+            device_ids.len(),
+            &shares,
+            threshold,
+            Some(config::get().max_group_shares),
+        )
+        .map_err(Status::invalid_argument)?;
+        if device_ids
+            .iter()
+            .enumerate()
+            .any(|(idx, id)| device_ids[..idx].contains(id))
+        {
+            return Err(Status::invalid_argument("Devices must not repeat"));
+        }
+        let protocol = ProtocolType::try_from(request.protocol)
+            .map_err(|_| Status::invalid_argument("Unknown protocol"))?;
+        let key_type = KeyType::try_from(request.key_type)
+            .map_err(|_| Status::invalid_argument("Unknown key type"))?;
 
         info!(
-            "GroupRequest name={:?} device_ids={:?} threshold={}",
+            "GroupRequest name={:?} device_ids={:?} shares={:?} threshold={}",
             &name,
             device_ids
                 .iter()
                 .map(utils::hextrunc)
                 .collect::<Vec<String>>(),
+            shares,
             threshold
         );
 
-        let mut state = self.state.lock().await;
-        if let Some(task_id) =
-            state.add_group_task(&name, &device_ids, threshold, protocol, key_type)
-        {
-            let task = state.get_task(&task_id).unwrap();
-            Ok(Response::new(format_task(&task_id, task, None, None)))
-        } else {
-            Err(Status::failed_precondition("Request failed"))
-        }
+        // each device is listed once per share it is to hold
+        let share_holders: Vec<_> = device_ids
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, id)| vec![id.clone(); shares.get(idx).copied().unwrap_or(1) as usize])
+            .collect();
+        self.blocking(move |service| {
+            if let Some(task_id) = service.state.add_group_task(
+                &name,
+                &share_holders,
+                threshold,
+                protocol,
+                key_type,
+                requester.as_deref(),
+            ) {
+                let task = service
+                    .state
+                    .get_task(&task_id)
+                    .ok_or_else(|| Status::not_found("Unknown task"))?;
+                let task = task.lock().unwrap();
+                audit::record(Event::TaskRequested {
+                    task_id: hex::encode(task_id.as_bytes()),
+                    task_type: task.get_type().as_str_name().into(),
+                    name,
+                    requester,
+                    group_id: None,
+                    devices: device_ids.iter().map(hex::encode).collect(),
+                });
+                Ok(Response::new(format_task(
+                    &task_id,
+                    task.as_ref(),
+                    None,
+                    None,
+                )))
+            } else {
+                Err(Status::failed_precondition("Request failed"))
+            }
+        })
+        .await
     }
 
     async fn get_devices(
         &self,
-        _request: Request<msg::DevicesRequest>,
+        request: Request<msg::DevicesRequest>,
     ) -> Result<Response<msg::Devices>, Status> {
+        let _timer = metrics::rpc_timer("GetDevices");
+        let caller = self.identify_caller(&request)?;
         debug!("DevicesRequest");
 
-        let resp = msg::Devices {
-            devices: self
-                .state
-                .lock()
-                .await
-                .get_devices()
-                .values()
-                .map(|device| device.as_ref().into())
-                .collect(),
-        };
-        Ok(Response::new(resp))
+        Ok(Response::new(msg::Devices {
+            devices: self.caller_devices(&caller),
+        }))
     }
 
     async fn log(&self, request: Request<msg::LogRequest>) -> Result<Response<msg::Resp>, Status> {
+        let _timer = metrics::rpc_timer("Log");
         let device_id = request
             .peer_certs()
-            .and_then(|certs| certs.get(0).map(cert_to_id));
+            .and_then(|certs| certs.first().cloned())
+            .filter(|certificate| !self.state.is_revoked(certificate.as_ref()))
+            .map(cert_to_id);
 
         let device_str = device_id
             .as_ref()
@@ -308,11 +787,8 @@ impl Mpc for MPCService {
         let message = request.into_inner().message;
         debug!("LogRequest device_id={} message={}", device_str, message);
 
-        if device_id.is_some() {
-            self.state
-                .lock()
-                .await
-                .device_activated(device_id.as_ref().unwrap());
+        if let Some(device_id) = &device_id {
+            self.state.device_activated(device_id);
         }
 
         Ok(Response::new(msg::Resp {
@@ -320,20 +796,17 @@ impl Mpc for MPCService {
         }))
     }
 
+    #[allow(clippy::result_large_err)]
     async fn decide_task(
         &self,
         request: Request<msg::TaskDecision>,
     ) -> Result<Response<msg::Resp>, Status> {
-        if request.peer_certs().is_none() {
-            return Err(Status::unauthenticated("Authentication required"));
-        }
-        let device_id = request
-            .peer_certs()
-            .and_then(|certs| certs.get(0).map(cert_to_id))
-            .unwrap();
+        let _timer = metrics::rpc_timer("DecideTask");
+        let device_id = self.authenticate_device(&request)?;
 
         let request = request.into_inner();
-        let task_id = Uuid::from_slice(&request.task).unwrap();
+        let task_id = Uuid::from_slice(&request.task)
+            .map_err(|_| Status::invalid_argument("Invalid task identifier"))?;
         let accept = request.accept;
 
         info!(
@@ -343,62 +816,146 @@ impl Mpc for MPCService {
             accept
         );
 
-        let state = self.state.clone();
-        tokio::task::spawn(async move {
-            let mut state = state.lock().await;
-            state.device_activated(&device_id);
-            state.decide_task(&task_id, &device_id, accept);
-        });
+        self.blocking(move |service| {
+            let task = service
+                .state
+                .get_task(&task_id)
+                .ok_or_else(|| Status::not_found("Unknown task"))?;
+            if !task.lock().unwrap().has_device(&device_id) {
+                warn!(
+                    "Decision on a task of other devices device_id={}",
+                    utils::hextrunc(&device_id)
+                );
+                return Err(Status::permission_denied(
+                    "Tasks of other devices are not accessible",
+                ));
+            }
+            service.state.device_activated(&device_id);
+            service.state.decide_task(&task_id, &device_id, accept);
 
-        Ok(Response::new(msg::Resp {
-            message: "OK".into(),
-        }))
+            Ok(Response::new(msg::Resp {
+                message: "OK".into(),
+            }))
+        })
+        .await
     }
 
+    #[allow(clippy::result_large_err)]
     async fn acknowledge_task(
         &self,
         request: Request<msg::TaskAcknowledgement>,
     ) -> Result<Response<msg::Resp>, Status> {
-        if request.peer_certs().is_none() {
-            return Err(Status::unauthenticated("Authentication required"));
-        }
-        let device_id = request
-            .peer_certs()
-            .and_then(|certs| certs.get(0).map(cert_to_id))
-            .unwrap();
+        let _timer = metrics::rpc_timer("AcknowledgeTask");
+        let device_id = self.authenticate_device(&request)?;
 
-        let task_id = request.into_inner().task_id;
+        let task_id = Uuid::from_slice(&request.into_inner().task_id)
+            .map_err(|_| Status::invalid_argument("Invalid task identifier"))?;
 
         debug!(
             "TaskAcknowledgement task_id={} device_id={}",
-            utils::hextrunc(&task_id),
+            utils::hextrunc(task_id.as_bytes()),
             utils::hextrunc(&device_id)
         );
 
-        let mut state = self.state.lock().await;
-        state.device_activated(&device_id);
-        state.acknowledge_task(&Uuid::from_slice(&task_id).unwrap(), &device_id);
+        self.blocking(move |service| {
+            service.state.device_activated(&device_id);
+            if service.state.get_task(&task_id).is_none() {
+                return Err(Status::not_found("Unknown task"));
+            }
+            service.state.acknowledge_task(&task_id, &device_id);
+
+            Ok(Response::new(msg::Resp {
+                message: "OK".into(),
+            }))
+        })
+        .await
+    }
+
+    async fn cancel_task(
+        &self,
+        request: Request<msg::TaskCancellation>,
+    ) -> Result<Response<msg::Resp>, Status> {
+        let _timer = metrics::rpc_timer("CancelTask");
+        let task_id = Uuid::from_slice(&request.get_ref().task_id)
+            .map_err(|_| Status::invalid_argument("Invalid task identifier"))?;
+
+        info!(
+            "TaskCancellation task_id={}",
+            utils::hextrunc(task_id.as_bytes())
+        );
+
+        self.authorize_task(&request, &task_id)?;
+
+        let state = self.state.clone();
+        tokio::task::spawn_blocking(move || state.cancel_task(&task_id))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::failed_precondition)?;
 
         Ok(Response::new(msg::Resp {
             message: "OK".into(),
         }))
     }
 
+    async fn renew_certificate(
+        &self,
+        request: Request<msg::RenewalRequest>,
+    ) -> Result<Response<msg::RegistrationResponse>, Status> {
+        let _timer = metrics::rpc_timer("RenewCertificate");
+        let device_id = self.authenticate_device(&request)?;
+        let csr = request.into_inner().csr;
+        info!("RenewalRequest device_id={}", utils::hextrunc(&device_id));
+
+        let device = self
+            .state
+            .get_device(&device_id)
+            .ok_or_else(|| Status::failed_precondition("Unknown device"))?;
+        let certificate =
+            issue_certificate(device.name(), &csr, Some(&device_id)).map_err(|_| {
+                Status::failed_precondition("Request failed: certificate was not created")
+            })?;
+
+        let state = self.state.clone();
+        let renewed = certificate.clone();
+        let id = device_id.clone();
+        tokio::task::spawn_blocking(move || state.renew_device(&id, &renewed))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::failed_precondition)?;
+
+        Ok(Response::new(msg::RegistrationResponse {
+            device_id,
+            certificate,
+        }))
+    }
+
     async fn subscribe_updates(
         &self,
         request: Request<msg::SubscribeRequest>,
     ) -> Result<Response<Self::SubscribeUpdatesStream>, Status> {
-        if request.peer_certs().is_none() {
-            return Err(Status::unauthenticated("Authentication required"));
-        }
-        let device_id = request
-            .peer_certs()
-            .and_then(|certs| certs.get(0).map(cert_to_id))
-            .unwrap();
-
-        let (tx, rx) = mpsc::channel(8);
-
-        self.state.lock().await.add_subscriber(device_id, tx);
+        let _timer = metrics::rpc_timer("SubscribeUpdates");
+        let caller = self.identify_caller(&request)?;
+        let resume_token = request.into_inner().resume_token;
+
+        let rx = match caller {
+            Caller::Device(device_id) => {
+                debug!(
+                    "SubscribeRequest device_id={} resume_token={:?}",
+                    utils::hextrunc(&device_id),
+                    resume_token
+                );
+                self.state
+                    .add_subscriber(device_id, resume_token.as_deref())?
+            }
+            Caller::Requester(requester) => {
+                debug!(
+                    "SubscribeRequest requester={:?} resume_token={:?}",
+                    requester.name, resume_token
+                );
+                self.state
+                    .add_requester_subscriber(requester.name.clone(), resume_token.as_deref())?
+            }
+        };
 
         Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
     }
@@ -412,7 +969,7 @@ pub fn format_task(
 ) -> msg::Task {
     let task_status = task.get_status();
 
-    let (task_status, round, data) = match task_status {
+    let (task_status, round, mut share_data) = match task_status {
         TaskStatus::Created => (msg::task::TaskState::Created, 0, task.get_work(device_id)),
         TaskStatus::Running(round) => (
             msg::task::TaskState::Running,
@@ -422,16 +979,25 @@ pub fn format_task(
         TaskStatus::Finished => (
             msg::task::TaskState::Finished,
             u16::MAX,
-            Some(task.get_result().unwrap().as_bytes().to_vec()),
+            vec![task.get_result().unwrap().as_bytes().to_vec()],
         ),
         TaskStatus::Failed(data) => (
             msg::task::TaskState::Failed,
             u16::MAX,
-            Some(data.as_bytes().to_vec()),
+            vec![data.as_bytes().to_vec()],
         ),
+        TaskStatus::Expired => (msg::task::TaskState::Expired, u16::MAX, Vec::new()),
+        TaskStatus::Cancelled => (msg::task::TaskState::Cancelled, u16::MAX, Vec::new()),
     };
+    // devices holding a single active share get their data only in `data`
+    let data = share_data.first().cloned();
+    if share_data.len() == 1 {
+        share_data.clear();
+    }
 
     let (accept, reject) = task.get_decisions();
+    let mut participants = task.get_active_devices().unwrap_or_default();
+    participants.dedup();
 
     msg::Task {
         id: task_id.as_bytes().to_vec(),
@@ -443,13 +1009,79 @@ pub fn format_task(
         data,
         request: request.map(Vec::from),
         attempt: task.get_attempts(),
+        // set by the subscription feeds
+        sequence: 0,
+        resume_token: String::new(),
+        share_data,
+        participants,
+        excluded: task.get_excluded_devices(),
+        faults: task
+            .get_transcript()
+            .faults()
+            .iter()
+            .map(Into::into)
+            .collect(),
+        transcript: match request {
+            Some(_) => task
+                .get_transcript()
+                .messages()
+                .iter()
+                .map(Into::into)
+                .collect(),
+            None => Vec::new(),
+        },
+    }
+}
+
+/// Result of `task` for its requester
+pub fn format_result(task_id: &Uuid, task: &dyn Task) -> msg::TaskResult {
+    use msg::task_result::Result as Outcome;
+    use prost::Message as _;
+
+    let (name, group_id) = listing::task_summary(task);
+    let status = task.get_status();
+    let result = match (&status, task.get_result()) {
+        (TaskStatus::Finished, Some(TaskResult::Signed(signature))) => {
+            Some(Outcome::Signature(signature))
+        }
+        (TaskStatus::Finished, Some(TaskResult::SignedPdf(pdf))) => Some(Outcome::SignedPdf(pdf)),
+        (TaskStatus::Finished, Some(TaskResult::Decrypted(plaintext))) => {
+            Some(Outcome::Plaintext(plaintext))
+        }
+        (TaskStatus::Failed(error), _) => Some(Outcome::Error(error.clone())),
+        _ => None,
+    };
+    let data_type = match task.get_type() {
+        msg::TaskType::Decrypt => msg::DecryptRequest::decode(task.get_request())
+            .map(|request| request.data_type)
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+
+    msg::TaskResult {
+        task_id: task_id.as_bytes().to_vec(),
+        r#type: task.get_type() as i32,
+        state: listing::task_state(&status) as i32,
+        name,
+        group_id,
+        result,
+        data_type,
+        created: task.created_at(),
     }
 }
 
-pub fn issue_certificate(device_name: &str, csr: &[u8]) -> Result<Vec<u8>, String> {
-    let csr = X509Req::from_der(csr).unwrap();
-    let public_key = csr.public_key().unwrap();
-    if !csr.verify(&public_key).unwrap() {
+/// Issue a device certificate for `csr`; renewed certificates carry the `device_id`
+/// of the registered device so that it keeps its identity (see `cert_to_id`)
+pub fn issue_certificate(
+    device_name: &str,
+    csr: &[u8],
+    device_id: Option<&[u8]>,
+) -> Result<Vec<u8>, String> {
+    let csr = X509Req::from_der(csr).map_err(|_| String::from("Invalid CSR."))?;
+    let public_key = csr
+        .public_key()
+        .map_err(|_| String::from("Invalid CSR public key."))?;
+    if !csr.verify(&public_key).unwrap_or(false) {
         return Err(String::from("CSR does not contain a valid signature."));
     }
 
@@ -470,7 +1102,7 @@ pub fn issue_certificate(device_name: &str, csr: &[u8]) -> Result<Vec<u8>, Strin
         .unwrap();
 
     cert_builder
-        .set_not_after(&Asn1Time::days_from_now(365 * 4 + 1).unwrap())
+        .set_not_after(&Asn1Time::days_from_now(config::get().device_cert_validity).unwrap())
         .unwrap();
 
     cert_builder.set_issuer_name(CA_CERT.issuer_name()).unwrap();
@@ -481,6 +1113,11 @@ pub fn issue_certificate(device_name: &str, csr: &[u8]) -> Result<Vec<u8>, Strin
 
     let mut subject = X509NameBuilder::new().unwrap();
     subject.append_entry_by_text("CN", device_name).unwrap();
+    if let Some(device_id) = device_id {
+        subject
+            .append_entry_by_nid(Nid::USERID, &hex::encode(device_id))
+            .unwrap();
+    }
     cert_builder.set_subject_name(&subject.build()).unwrap();
 
     let context = cert_builder.x509v3_context(Some(&CA_CERT), None);
@@ -521,39 +1158,432 @@ pub fn issue_certificate(device_name: &str, csr: &[u8]) -> Result<Vec<u8>, Strin
     Ok(cert_builder.build().to_der().unwrap())
 }
 
+/// Policy action of the request which created `task`
+fn task_action(task: &dyn Task) -> Option<Action> {
+    use prost::Message as _;
+    let request = task.get_request();
+    match task.get_type() {
+        msg::TaskType::Group => msg::GroupRequest::decode(request)
+            .ok()
+            .map(|request| Action::CreateGroup(request.device_ids)),
+        msg::TaskType::SignPdf | msg::TaskType::SignChallenge => msg::SignRequest::decode(request)
+            .ok()
+            .map(|request| Action::UseGroup(request.group_id)),
+        msg::TaskType::Decrypt => msg::DecryptRequest::decode(request)
+            .ok()
+            .map(|request| Action::UseGroup(request.group_id)),
+    }
+}
+
+/// Identifier of the device holding the DER-encoded certificate
+///
+/// Certificates issued on registration are identified by their SHA-256 hash; renewed
+/// certificates carry the identifier of the original one in the subject UID.
 pub fn cert_to_id(cert: impl AsRef<[u8]>) -> Vec<u8> {
+    X509::from_der(cert.as_ref())
+        .ok()
+        .and_then(|cert| {
+            cert.subject_name()
+                .entries_by_nid(Nid::USERID)
+                .next()
+                .and_then(|entry| hex::decode(entry.data().as_slice()).ok())
+        })
+        .unwrap_or_else(|| cert_fingerprint(cert))
+}
+
+/// SHA-256 hash of the DER-encoded certificate
+pub fn cert_fingerprint(cert: impl AsRef<[u8]>) -> Vec<u8> {
     use sha2::Digest;
     sha2::Sha256::digest(cert).to_vec()
 }
 
-pub async fn run_grpc(state: Arc<Mutex<State>>, addr: &str, port: u16) -> Result<(), String> {
-    let addr = format!("{}:{}", addr, port)
-        .parse()
-        .map_err(|_| String::from("Unable to parse server address"))?;
-    let node = MPCService::new(state);
-
+/// TLS configuration of the server accepting client certificates issued by the MeeSign CA
+pub async fn server_tls(client_auth_optional: bool) -> Result<ServerTlsConfig, String> {
     let ca_cert = CA_CERT
         .to_pem()
         .map_err(|_| "Unable to load CA certificate".to_string())?;
-    let cert = tokio::fs::read("keys/meesign-server-cert.pem")
+    let config = config::get();
+    let cert = tokio::fs::read(&config.server_cert)
         .await
         .map_err(|_| "Unable to load server certificate".to_string())?;
-    let key = tokio::fs::read("keys/meesign-server-key.pem")
+    let key = tokio::fs::read(&config.server_key)
         .await
         .map_err(|_| "Unable to load server key".to_string())?;
 
+    Ok(ServerTlsConfig::new()
+        .identity(Identity::from_pem(&cert, &key))
+        .client_ca_root(Certificate::from_pem(ca_cert))
+        .client_auth_optional(client_auth_optional))
+}
+
+pub async fn run_grpc(
+    state: Arc<State>,
+    policy: Arc<Policy>,
+    addr: &str,
+    port: u16,
+) -> Result<(), String> {
+    let addr = format!("{}:{}", addr, port)
+        .parse()
+        .map_err(|_| String::from("Unable to parse server address"))?;
+    let node = MPCService::new(state, policy);
+
     Server::builder()
-        .tls_config(
-            ServerTlsConfig::new()
-                .identity(Identity::from_pem(&cert, &key))
-                .client_ca_root(Certificate::from_pem(ca_cert))
-                .client_auth_optional(true),
-        )
+        .tls_config(server_tls(true).await?)
         .map_err(|_| "Unable to setup TLS for gRPC server")?
-        .add_service(MpcServer::new(node))
+        // leave room for the rest of a SignRequest besides the document
+        .add_service(
+            MpcServer::new(node).max_decoding_message_size(config::get().max_pdf_size + 64 * 1024),
+        )
         .serve(addr)
         .await
         .map_err(|_| String::from("Unable to run gRPC server"))?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::sample_ca;
+    use crate::device::Device;
+    use crate::group::Group;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::Storage;
+    use sha2::Digest;
+    use std::io::Write;
+
+    /// DER certificate with the common name `name` and optionally the subject UID `device_id`
+    fn device_certificate(name: &str, device_id: Option<&[u8]>) -> Vec<u8> {
+        let (_, key) = sample_ca();
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        if let Some(device_id) = device_id {
+            subject
+                .append_entry_by_nid(Nid::USERID, &hex::encode(device_id))
+                .unwrap();
+        }
+        let subject = subject.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_issuer_name(&subject).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        builder.build().to_der().unwrap()
+    }
+
+    #[test]
+    fn renewed_certificate_id() {
+        let (certificate, _) = sample_ca();
+        let certificate = certificate.to_der().unwrap();
+        assert_eq!(cert_to_id(&certificate), cert_fingerprint(&certificate));
+
+        let renewed = device_certificate("Renewed Device", Some(&cert_to_id(&certificate)));
+        assert_eq!(cert_to_id(&renewed), cert_to_id(&certificate));
+        assert_ne!(cert_fingerprint(&renewed), cert_fingerprint(&certificate));
+        assert_eq!(cert_to_id([0x30, 0x00]), cert_fingerprint([0x30, 0x00]));
+    }
+
+    /// Service with devices `d1` and `d2` in separate groups shared with `d3`, and a
+    /// group task for each of the groups
+    fn sample_service(policy: Policy) -> (MPCService, Vec<Vec<u8>>) {
+        let storage = MemoryStorage::new();
+        let mut devices = Vec::new();
+        let mut certificates = Vec::new();
+        for name in ["d1", "d2", "d3"] {
+            let certificate = device_certificate(name, None);
+            let device = Device::new(cert_to_id(&certificate), name.into(), certificate.clone());
+            storage.add_device(&device).unwrap();
+            devices.push(Arc::new(device));
+            certificates.push(certificate);
+        }
+        let members = [[0, 2], [1, 2]];
+        for (i, members) in members.iter().enumerate() {
+            let group = Group::new(
+                vec![i as u8],
+                format!("g{}", i),
+                members.iter().map(|&j| devices[j].clone()).collect(),
+                2,
+                ProtocolType::Gg18,
+                KeyType::SignChallenge,
+                None,
+            );
+            storage.add_group(&group).unwrap();
+        }
+
+        let state = State::new(Box::new(storage)).unwrap();
+        for (i, members) in members.iter().enumerate() {
+            let device_ids: Vec<_> = members
+                .iter()
+                .map(|&j| devices[j].identifier().to_vec())
+                .collect();
+            state
+                .add_group_task(
+                    &format!("t{}", i),
+                    &device_ids,
+                    2,
+                    ProtocolType::Gg18,
+                    KeyType::SignChallenge,
+                    None,
+                )
+                .unwrap();
+        }
+        (
+            MPCService::new(Arc::new(state), Arc::new(policy)),
+            certificates,
+        )
+    }
+
+    fn tasks_request(device_id: Option<&[u8]>) -> msg::TasksRequest {
+        msg::TasksRequest {
+            device_id: device_id.map(Vec::from),
+            ..Default::default()
+        }
+    }
+
+    fn groups_request(device_id: Option<&[u8]>) -> msg::GroupsRequest {
+        msg::GroupsRequest {
+            device_id: device_id.map(Vec::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cross_device_access() {
+        let (service, certificates) = sample_service(Policy::unrestricted());
+        let d1 = cert_to_id(&certificates[0]);
+        let d2 = cert_to_id(&certificates[1]);
+        let caller = service.caller(Some(&certificates[0]), None).unwrap();
+
+        let tasks = service
+            .caller_tasks(&caller, &tasks_request(None))
+            .unwrap()
+            .tasks;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(
+            service
+                .caller_tasks(&caller, &tasks_request(Some(&d1)))
+                .unwrap()
+                .tasks,
+            tasks
+        );
+        assert_eq!(
+            service
+                .caller_tasks(&caller, &tasks_request(Some(&d2)))
+                .unwrap_err()
+                .code(),
+            tonic::Code::PermissionDenied
+        );
+
+        let groups = service
+            .caller_groups(&caller, &groups_request(None))
+            .unwrap()
+            .groups;
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].device_ids.contains(&d1));
+        assert_eq!(
+            service
+                .caller_groups(&caller, &groups_request(Some(&d2)))
+                .unwrap_err()
+                .code(),
+            tonic::Code::PermissionDenied
+        );
+        assert_eq!(service.caller_devices(&caller).len(), 3);
+    }
+
+    #[test]
+    fn cross_device_task() {
+        let (service, certificates) = sample_service(Policy::unrestricted());
+        let d1 = cert_to_id(&certificates[0]);
+        let d2 = cert_to_id(&certificates[1]);
+        let caller = service.caller(Some(&certificates[0]), None).unwrap();
+        let task_request = |task_id: &Uuid, device_id: Option<&[u8]>| msg::TaskRequest {
+            task_id: task_id.as_bytes().to_vec(),
+            device_id: device_id.map(Vec::from),
+        };
+        let (own, _) = service.state.get_device_tasks(&d1)[0].clone();
+        let (other, _) = service.state.get_device_tasks(&d2)[0].clone();
+
+        let task = service
+            .caller_task(&caller, &task_request(&own, None))
+            .unwrap();
+        assert_eq!(task.id, own.as_bytes());
+        assert!(task.request.is_some());
+        assert!(service
+            .caller_task(&caller, &task_request(&own, Some(&d1)))
+            .is_ok());
+        for (task_id, device_id) in [
+            (own, Some(d2.as_slice())),
+            (other, None),
+            (other, Some(&d2)),
+        ] {
+            assert_eq!(
+                service
+                    .caller_task(&caller, &task_request(&task_id, device_id))
+                    .unwrap_err()
+                    .code(),
+                tonic::Code::PermissionDenied
+            );
+        }
+        assert_eq!(
+            service
+                .caller_task(&caller, &task_request(&Uuid::new_v4(), None))
+                .unwrap_err()
+                .code(),
+            tonic::Code::NotFound
+        );
+        let malformed = msg::TaskRequest {
+            task_id: vec![0x01],
+            device_id: None,
+        };
+        assert_eq!(
+            service.caller_task(&caller, &malformed).unwrap_err().code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn unauthenticated_caller() {
+        let (service, _) = sample_service(Policy::unrestricted());
+        let unknown = device_certificate("unknown", None);
+        for certificate in [None, Some(unknown.as_slice()), Some(&[0x30, 0x00][..])] {
+            assert_eq!(
+                service
+                    .caller(certificate, Some("token"))
+                    .err()
+                    .unwrap()
+                    .code(),
+                tonic::Code::Unauthenticated
+            );
+        }
+    }
+
+    #[test]
+    fn requester_caller() {
+        let (service, certificates) = sample_service(Policy::unrestricted());
+        let d1 = cert_to_id(&certificates[0]);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "[[requester]]\nname = \"script\"\ntoken-sha256 = \"{}\"\ngroup-devices = [\"{}\"]\ngroups = [\"01\"]\n",
+            hex::encode(sha2::Sha256::digest(b"secret")),
+            hex::encode(&d1),
+        )
+        .unwrap();
+        let service = MPCService::new(
+            service.state.clone(),
+            Arc::new(Policy::load(file.path()).unwrap()),
+        );
+
+        assert!(service.caller(None, Some("wrong")).is_err());
+        let caller = service.caller(None, Some("secret")).unwrap();
+        let groups = service
+            .caller_groups(&caller, &groups_request(None))
+            .unwrap()
+            .groups;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].identifier, vec![0x01]);
+        assert!(service
+            .caller_groups(&caller, &groups_request(Some(&d1)))
+            .is_err());
+        assert!(service
+            .caller_tasks(&caller, &tasks_request(Some(&d1)))
+            .is_err());
+        assert!(service
+            .caller_tasks(&caller, &tasks_request(None))
+            .unwrap()
+            .tasks
+            .is_empty());
+        let task_id = service
+            .state
+            .add_group_task(
+                "t2",
+                &[d1.clone(), cert_to_id(&certificates[2])],
+                2,
+                ProtocolType::Gg18,
+                KeyType::SignChallenge,
+                Some("script"),
+            )
+            .unwrap();
+        let tasks = service
+            .caller_tasks(&caller, &tasks_request(None))
+            .unwrap()
+            .tasks;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].id, task_id.as_bytes());
+
+        let devices = service.caller_devices(&caller);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].identifier, d1);
+    }
+
+    #[tokio::test]
+    async fn requester_task_ownership() {
+        let (service, certificates) = sample_service(Policy::unrestricted());
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for (name, token) in [("a", b"token-a"), ("b", b"token-b")] {
+            write!(
+                file,
+                "[[requester]]\nname = \"{}\"\ntoken-sha256 = \"{}\"\ngroup-devices = [\"*\"]\n",
+                name,
+                hex::encode(sha2::Sha256::digest(token)),
+            )
+            .unwrap();
+        }
+        let service = MPCService::new(
+            service.state.clone(),
+            Arc::new(Policy::load(file.path()).unwrap()),
+        );
+        let task_id = service
+            .state
+            .add_group_task(
+                "t2",
+                &[cert_to_id(&certificates[0]), cert_to_id(&certificates[2])],
+                2,
+                ProtocolType::Gg18,
+                KeyType::SignChallenge,
+                Some("a"),
+            )
+            .unwrap();
+        fn with_token<T>(message: T, token: &str) -> Request<T> {
+            let mut request = Request::new(message);
+            request.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+            request
+        }
+        let result_request = || msg::TaskResultRequest {
+            task_id: task_id.as_bytes().to_vec(),
+        };
+        let cancellation = || msg::TaskCancellation {
+            task_id: task_id.as_bytes().to_vec(),
+        };
+
+        // another requester permitted to create the task may not access it
+        let denied = service
+            .get_task_result(with_token(result_request(), "token-b"))
+            .await
+            .unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        let denied = service
+            .cancel_task(with_token(cancellation(), "token-b"))
+            .await
+            .unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+
+        assert!(service
+            .get_task_result(with_token(result_request(), "token-a"))
+            .await
+            .is_ok());
+        assert!(service
+            .cancel_task(with_token(cancellation(), "token-a"))
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,371 @@
+use prost::Message as _;
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::group::Group;
+use crate::interfaces::grpc::format_task;
+use crate::proto as msg;
+use crate::tasks::{SharedTask, Task, TaskResult, TaskStatus};
+
+pub fn task_state(status: &TaskStatus) -> msg::task::TaskState {
+    match status {
+        TaskStatus::Created => msg::task::TaskState::Created,
+        TaskStatus::Running(_) => msg::task::TaskState::Running,
+        TaskStatus::Finished => msg::task::TaskState::Finished,
+        TaskStatus::Failed(_) => msg::task::TaskState::Failed,
+        TaskStatus::Expired => msg::task::TaskState::Expired,
+        TaskStatus::Cancelled => msg::task::TaskState::Cancelled,
+    }
+}
+
+/// Name of the task and the group it uses, or the group it has established
+pub fn task_summary(task: &dyn Task) -> (String, Option<Vec<u8>>) {
+    let request = task.get_request();
+    match task.get_type() {
+        msg::TaskType::Group => {
+            let name = msg::GroupRequest::decode(request)
+                .map(|request| request.name)
+                .unwrap_or_default();
+            let group_id = match task.get_result() {
+                Some(TaskResult::GroupEstablished(group)) => Some(group.identifier().to_vec()),
+                _ => None,
+            };
+            (name, group_id)
+        }
+        msg::TaskType::SignPdf | msg::TaskType::SignChallenge => msg::SignRequest::decode(request)
+            .map(|request| (request.name, Some(request.group_id)))
+            .unwrap_or_default(),
+        msg::TaskType::Decrypt => msg::DecryptRequest::decode(request)
+            .map(|request| (request.name, Some(request.group_id)))
+            .unwrap_or_default(),
+    }
+}
+
+fn contains_ignore_case(name: &str, pattern: &str) -> bool {
+    name.to_lowercase().contains(&pattern.to_lowercase())
+}
+
+fn task_matches(task: &dyn Task, request: &msg::TasksRequest) -> bool {
+    if !request.types.is_empty() && !request.types.contains(&(task.get_type() as i32)) {
+        return false;
+    }
+    if !request.states.is_empty()
+        && !request
+            .states
+            .contains(&(task_state(&task.get_status()) as i32))
+    {
+        return false;
+    }
+    let created = task.created_at();
+    if request.created_after.is_some_and(|after| created < after)
+        || request
+            .created_before
+            .is_some_and(|before| created >= before)
+    {
+        return false;
+    }
+    if request.group_id.is_none() && request.name.is_empty() {
+        return true;
+    }
+    let (name, group_id) = task_summary(task);
+    request
+        .group_id
+        .as_ref()
+        .is_none_or(|id| group_id.as_ref() == Some(id))
+        && contains_ignore_case(&name, &request.name)
+}
+
+/// Remove the entries following the first `page_size` ones
+///
+/// # Returns
+/// The last entry of the page if more entries follow.
+fn truncate<T>(entries: &mut Vec<T>, page_size: u32) -> Option<&T> {
+    let page_size = page_size as usize;
+    if page_size == 0 || entries.len() <= page_size {
+        return None;
+    }
+    entries.truncate(page_size);
+    entries.last()
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_task_token(token: &str) -> Result<(u64, Uuid), Status> {
+    let invalid = || Status::invalid_argument("Invalid page token");
+    let token = hex::decode(token).map_err(|_| invalid())?;
+    if token.len() != 24 {
+        return Err(invalid());
+    }
+    let created = u64::from_be_bytes(token[..8].try_into().unwrap());
+    let task_id = Uuid::from_slice(&token[8..]).map_err(|_| invalid())?;
+    Ok((created, task_id))
+}
+
+/// Select the page of `tasks` requested by `request`, newest tasks first
+///
+/// Tasks are formatted for `device_id`, with the serialized request if `with_request` is set.
+#[allow(clippy::result_large_err)]
+pub fn tasks_page(
+    tasks: Vec<(Uuid, SharedTask)>,
+    request: &msg::TasksRequest,
+    device_id: Option<&[u8]>,
+    with_request: bool,
+) -> Result<msg::Tasks, Status> {
+    let after = match request.page_token.as_str() {
+        "" => None,
+        token => Some(parse_task_token(token)?),
+    };
+
+    // tasks ordered by creation are keyed by (created, id), which keeps pages stable
+    let mut selected: Vec<_> = tasks
+        .into_iter()
+        .filter_map(|(task_id, task)| {
+            let guard = task.lock().unwrap();
+            let key = (guard.created_at(), task_id);
+            let matches =
+                after.is_none_or(|after| key < after) && task_matches(guard.as_ref(), request);
+            drop(guard);
+            matches.then_some((key, task))
+        })
+        .collect();
+    selected.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let next_page_token = truncate(&mut selected, request.page_size)
+        .map(|((created, task_id), _)| {
+            hex::encode([&created.to_be_bytes()[..], task_id.as_bytes()].concat())
+        })
+        .unwrap_or_default();
+    let tasks = selected
+        .iter()
+        .map(|((_, task_id), task)| {
+            let task = task.lock().unwrap();
+            let mut task = format_task(
+                task_id,
+                task.as_ref(),
+                device_id,
+                with_request.then(|| task.get_request()),
+            );
+            if request.omit_payloads {
+                task.data = None;
+                task.request = None;
+                task.transcript = Vec::new();
+            }
+            task
+        })
+        .collect();
+    Ok(msg::Tasks {
+        tasks,
+        next_page_token,
+    })
+}
+
+fn group_matches(group: &Group, request: &msg::GroupsRequest) -> bool {
+    request
+        .protocol
+        .is_none_or(|protocol| group.protocol() as i32 == protocol)
+        && request
+            .key_type
+            .is_none_or(|key_type| group.key_type() as i32 == key_type)
+        && contains_ignore_case(group.name(), &request.name)
+}
+
+/// Select the page of `groups` requested by `request`, ordered by group identifiers
+#[allow(clippy::result_large_err)]
+pub fn groups_page(
+    groups: Vec<Group>,
+    request: &msg::GroupsRequest,
+) -> Result<msg::Groups, Status> {
+    let after = hex::decode(&request.page_token)
+        .map_err(|_| Status::invalid_argument("Invalid page token"))?;
+
+    let mut selected: Vec<_> = groups
+        .into_iter()
+        .filter(|group| {
+            (after.is_empty() || group.identifier() > after.as_slice())
+                && group_matches(group, request)
+        })
+        .collect();
+    selected.sort_by(|a, b| a.identifier().cmp(b.identifier()));
+
+    let next_page_token = truncate(&mut selected, request.page_size)
+        .map(|group| hex::encode(group.identifier()))
+        .unwrap_or_default();
+    Ok(msg::Groups {
+        groups: selected.iter().map(|group| group.into()).collect(),
+        next_page_token,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Device;
+    use crate::proto::{KeyType, ProtocolType};
+    use crate::state::State;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::Storage;
+    use std::sync::Arc;
+
+    fn sample_state() -> State {
+        let storage = MemoryStorage::new();
+        let devices: Vec<_> = (0..2u8)
+            .map(|i| Arc::new(Device::new(vec![i], format!("d{}", i), vec![0xf0 + i])))
+            .collect();
+        for device in &devices {
+            storage.add_device(device).unwrap();
+        }
+        for (i, protocol) in [ProtocolType::Gg18, ProtocolType::Frost, ProtocolType::Gg18]
+            .into_iter()
+            .enumerate()
+        {
+            let group = Group::new(
+                vec![i as u8],
+                format!("Group {}", i),
+                devices.clone(),
+                2,
+                protocol,
+                KeyType::SignChallenge,
+                None,
+            );
+            storage.add_group(&group).unwrap();
+        }
+
+        let state = State::new(Box::new(storage)).unwrap();
+        for name in ["Alpha", "Beta", "alphabet"] {
+            state
+                .add_group_task(
+                    name,
+                    &[vec![0], vec![1]],
+                    2,
+                    ProtocolType::Gg18,
+                    KeyType::SignChallenge,
+                    None,
+                )
+                .unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn task_pages() {
+        let state = sample_state();
+        let mut request = msg::TasksRequest {
+            page_size: 2,
+            ..Default::default()
+        };
+        let first = tasks_page(state.get_tasks(), &request, None, true).unwrap();
+        assert_eq!(first.tasks.len(), 2);
+        assert!(first.tasks[0].request.is_some());
+
+        request.page_token = first.next_page_token;
+        let second = tasks_page(state.get_tasks(), &request, None, true).unwrap();
+        assert_eq!(second.tasks.len(), 1);
+        assert!(second.next_page_token.is_empty());
+        let mut ids: Vec<_> = first
+            .tasks
+            .iter()
+            .chain(&second.tasks)
+            .map(|t| &t.id)
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 3);
+
+        request.page_token = String::from("00");
+        assert_eq!(
+            tasks_page(state.get_tasks(), &request, None, true)
+                .unwrap_err()
+                .code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn task_filters() {
+        let state = sample_state();
+        let count = |request: msg::TasksRequest| {
+            tasks_page(state.get_tasks(), &request, None, false)
+                .unwrap()
+                .tasks
+                .len()
+        };
+        assert_eq!(count(Default::default()), 3);
+        assert_eq!(
+            count(msg::TasksRequest {
+                name: String::from("ALPHA"),
+                ..Default::default()
+            }),
+            2
+        );
+        assert_eq!(
+            count(msg::TasksRequest {
+                types: vec![msg::TaskType::SignChallenge as i32],
+                ..Default::default()
+            }),
+            0
+        );
+        assert_eq!(
+            count(msg::TasksRequest {
+                states: vec![
+                    msg::task::TaskState::Created as i32,
+                    msg::task::TaskState::Running as i32
+                ],
+                ..Default::default()
+            }),
+            3
+        );
+        assert_eq!(
+            count(msg::TasksRequest {
+                created_after: Some(crate::get_timestamp() + 60),
+                ..Default::default()
+            }),
+            0
+        );
+        assert_eq!(
+            count(msg::TasksRequest {
+                group_id: Some(vec![0]),
+                ..Default::default()
+            }),
+            0
+        );
+
+        let tasks = tasks_page(
+            state.get_tasks(),
+            &msg::TasksRequest {
+                omit_payloads: true,
+                ..Default::default()
+            },
+            Some(&[0]),
+            true,
+        )
+        .unwrap()
+        .tasks;
+        assert!(tasks
+            .iter()
+            .all(|task| task.data.is_none() && task.request.is_none()));
+    }
+
+    #[test]
+    fn group_pages() {
+        let state = sample_state();
+        let mut request = msg::GroupsRequest {
+            protocol: Some(ProtocolType::Gg18 as i32),
+            page_size: 1,
+            ..Default::default()
+        };
+        let first = groups_page(state.get_groups(), &request).unwrap();
+        assert_eq!(first.groups[0].identifier, vec![0]);
+
+        request.page_token = first.next_page_token;
+        let second = groups_page(state.get_groups(), &request).unwrap();
+        assert_eq!(second.groups[0].identifier, vec![2]);
+        assert!(second.next_page_token.is_empty());
+
+        let request = msg::GroupsRequest {
+            name: String::from("group 1"),
+            ..Default::default()
+        };
+        let groups = groups_page(state.get_groups(), &request).unwrap().groups;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].identifier, vec![1]);
+    }
+}
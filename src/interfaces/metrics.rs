@@ -0,0 +1,68 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::info;
+use tonic::codegen::Arc;
+
+use crate::metrics;
+use crate::state::State;
+
+fn handle(state: &State, request: Request<Body>) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(Body::from(metrics::render(state)))
+            .unwrap(),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+pub async fn run_metrics(state: Arc<State>, addr: SocketAddr) -> Result<(), String> {
+    let service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move {
+                    // gathering the task gauges locks every task
+                    tokio::task::spawn_blocking(move || {
+                        Ok::<_, Infallible>(handle(&state, request))
+                    })
+                    .await
+                    .unwrap()
+                }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr).map_err(|e| e.to_string())?;
+    info!("Metrics endpoint listening on http://{}/metrics", addr);
+    server.serve(service).await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+
+    #[test]
+    fn routes() {
+        let state = State::new(Box::new(MemoryStorage::new())).unwrap();
+        let get = |method, path| {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::empty())
+                .unwrap();
+            handle(&state, request).status()
+        };
+        assert_eq!(get(Method::GET, "/metrics"), StatusCode::OK);
+        assert_eq!(get(Method::POST, "/metrics"), StatusCode::NOT_FOUND);
+        assert_eq!(get(Method::GET, "/"), StatusCode::NOT_FOUND);
+    }
+}
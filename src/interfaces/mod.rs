@@ -1,2 +1,5 @@
+pub mod admin;
 pub mod grpc;
+mod listing;
+pub mod metrics;
 pub mod timer;
@@ -1,53 +1,178 @@
+use crate::config;
+use crate::metrics;
+use crate::proto::TaskType;
 use crate::state::State;
 use crate::tasks::TaskStatus;
 use crate::{get_timestamp, utils};
 
 use log::debug;
-use tokio::sync::MutexGuard;
-use tokio::{sync::Mutex, time};
+use serde::Deserialize;
+use tokio::time;
 use tonic::codegen::Arc;
 
-pub async fn run_timer(state: Arc<Mutex<State>>) -> Result<(), String> {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskCleanup {
+    /// Keep the record in storage but drop the task from memory
+    Archive,
+    /// Delete the task record from storage
+    Purge,
+}
+
+/// Deadlines of unfinished tasks and retention of ended ones, in seconds; 0 disables a limit
+///
+/// Approval deadlines are measured from the task creation, completion deadlines from the
+/// task approval.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaskLimits {
+    pub group_approval_timeout: u64,
+    pub group_completion_timeout: u64,
+    pub sign_pdf_approval_timeout: u64,
+    pub sign_pdf_completion_timeout: u64,
+    pub sign_challenge_approval_timeout: u64,
+    pub sign_challenge_completion_timeout: u64,
+    pub decrypt_approval_timeout: u64,
+    pub decrypt_completion_timeout: u64,
+    /// Time to keep ended tasks after all devices acknowledged them
+    pub task_retention: u64,
+    /// Time to keep ended tasks which some devices never acknowledged
+    pub task_hard_retention: u64,
+    /// What to do with ended tasks after the retention period
+    pub task_cleanup: TaskCleanup,
+}
+
+impl Default for TaskLimits {
+    fn default() -> Self {
+        TaskLimits {
+            group_approval_timeout: 86400,
+            group_completion_timeout: 3600,
+            sign_pdf_approval_timeout: 86400,
+            sign_pdf_completion_timeout: 3600,
+            sign_challenge_approval_timeout: 86400,
+            sign_challenge_completion_timeout: 3600,
+            decrypt_approval_timeout: 86400,
+            decrypt_completion_timeout: 3600,
+            task_retention: 86400,
+            task_hard_retention: 2592000,
+            task_cleanup: TaskCleanup::Archive,
+        }
+    }
+}
+
+impl TaskLimits {
+    /// Approval and completion deadlines of `task_type`
+    fn timeouts(&self, task_type: TaskType) -> (u64, u64) {
+        match task_type {
+            TaskType::Group => (self.group_approval_timeout, self.group_completion_timeout),
+            TaskType::SignPdf => (
+                self.sign_pdf_approval_timeout,
+                self.sign_pdf_completion_timeout,
+            ),
+            TaskType::SignChallenge => (
+                self.sign_challenge_approval_timeout,
+                self.sign_challenge_completion_timeout,
+            ),
+            TaskType::Decrypt => (
+                self.decrypt_approval_timeout,
+                self.decrypt_completion_timeout,
+            ),
+        }
+    }
+}
+
+pub async fn run_timer(state: Arc<State>, limits: TaskLimits) -> Result<(), String> {
     let mut interval = time::interval(time::Duration::from_secs(1));
     loop {
         interval.tick().await;
-        let mut state = state.lock().await;
-        check_tasks(&mut state);
-        check_subscribers(&mut state);
+        let state = state.clone();
+        let limits = limits.clone();
+        tokio::task::spawn_blocking(move || {
+            check_tasks(&state, &limits);
+            check_subscribers(&state);
+            state.refresh_crl();
+        })
+        .await
+        .map_err(|e| e.to_string())?;
     }
 }
 
-fn check_tasks(state: &mut MutexGuard<State>) {
+fn check_tasks(state: &State, limits: &TaskLimits) {
     let mut restarts = Vec::new();
+    let mut expirations = Vec::new();
+    let mut removals = Vec::new();
     let timestamp = get_timestamp();
     for (task_id, task) in state.get_tasks() {
-        if task.get_status() != TaskStatus::Finished
-            && task.is_approved()
-            && timestamp - task.last_update() > 30
+        let task = task.lock().unwrap();
+        let status = task.get_status();
+        let (approval_timeout, completion_timeout) = limits.timeouts(task.get_type());
+        let expired = match task.approved_at() {
+            None => {
+                status == TaskStatus::Created
+                    && approval_timeout > 0
+                    && timestamp.saturating_sub(task.created_at()) > approval_timeout
+            }
+            Some(approved) => {
+                completion_timeout > 0 && timestamp.saturating_sub(approved) > completion_timeout
+            }
+        };
+        if status.has_ended() {
+            let ended_for = timestamp.saturating_sub(task.last_update());
+            let acknowledged = task
+                .get_devices()
+                .iter()
+                .all(|device| task.device_acknowledged(device.identifier()));
+            if (acknowledged && limits.task_retention > 0 && ended_for > limits.task_retention)
+                || (limits.task_hard_retention > 0 && ended_for > limits.task_hard_retention)
+            {
+                removals.push(task_id);
+            }
+        } else if expired {
+            debug!(
+                "Expired task detected task_id={:?}",
+                utils::hextrunc(task_id)
+            );
+            expirations.push(task_id);
+        } else if task.is_approved()
+            && timestamp.saturating_sub(task.last_update()) > config::get().stale_task_timeout
         {
             debug!("Stale task detected task_id={:?}", utils::hextrunc(task_id));
-            restarts.push(*task_id);
+            restarts.push(task_id);
         }
     }
     for task_id in restarts {
-        state.restart_task(&task_id);
+        if state.restart_task(&task_id) {
+            metrics::task_restarted();
+        }
+    }
+    for task_id in expirations {
+        state.expire_task(&task_id);
+    }
+    for task_id in removals {
+        state.remove_task(&task_id, limits.task_cleanup == TaskCleanup::Archive);
     }
 }
 
-fn check_subscribers(state: &mut MutexGuard<State>) {
+fn check_subscribers(state: &State) {
     let mut remove = Vec::new();
     for (device_id, tx) in state.get_subscribers() {
         if tx.is_closed() {
             debug!(
                 "Closed channel detected device_id={:?}",
-                utils::hextrunc(device_id)
+                utils::hextrunc(&device_id)
             );
-            remove.push(device_id.clone());
+            remove.push((device_id, tx));
         } else {
-            state.device_activated(device_id);
+            state.device_activated(&device_id);
         }
     }
-    for device_id in remove {
-        state.remove_subscriber(&device_id);
+    for (device_id, tx) in remove {
+        state.remove_subscriber(&device_id, &tx);
+    }
+    for (requester, tx) in state.get_requester_subscribers() {
+        if tx.is_closed() {
+            debug!("Closed channel detected requester={:?}", requester);
+            state.remove_requester_subscriber(&requester, &tx);
+        }
     }
+    state.remove_idle_subscriptions();
 }
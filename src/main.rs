@@ -6,17 +6,30 @@ use openssl::pkey::{PKey, Private};
 use openssl::x509::X509;
 
 use crate::state::State;
-use tokio::{sync::Mutex, try_join};
+use crate::storage::sqlite::SqliteStorage;
+use tokio::try_join;
 use tonic::codegen::Arc;
 
+mod approval;
+mod audit;
+mod certificate;
 mod communicator;
+mod config;
+mod der;
 mod device;
 mod group;
 mod interfaces;
+mod metrics;
+mod pdf;
+mod policy;
 mod protocols;
+mod revocation;
 mod state;
+mod storage;
+mod subscription;
 mod tasks;
 mod utils;
+mod webhook;
 
 mod proto {
     #![allow(clippy::derive_partial_eq_without_eq)]
@@ -45,9 +58,9 @@ mod proto {
 
 lazy_static! {
     static ref CA_CERT: X509 =
-        X509::from_pem(&std::fs::read("keys/meesign-ca-cert.pem").unwrap()).unwrap();
+        X509::from_pem(&std::fs::read(&config::get().ca_cert).unwrap()).unwrap();
     static ref CA_KEY: PKey<Private> =
-        PKey::private_key_from_pem(&std::fs::read("keys/meesign-ca-key.pem").unwrap()).unwrap();
+        PKey::private_key_from_pem(&std::fs::read(&config::get().ca_key).unwrap()).unwrap();
 }
 
 const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
@@ -55,15 +68,36 @@ const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    #[clap(short, long, default_value_t = 1337)]
-    port: u16,
-
-    #[clap(short, long, default_value_t = String::from("127.0.0.1"))]
-    addr: String,
-
     #[clap(short, long, default_value_t = String::from("meesign.local"))]
     host: String,
 
+    #[clap(
+        short,
+        long,
+        env = "MEESIGN_CONFIG",
+        help = "Path to a TOML configuration file"
+    )]
+    config: Option<std::path::PathBuf>,
+
+    #[clap(flatten)]
+    config_overrides: config::ConfigOverrides,
+
+    #[cfg(feature = "cli")]
+    #[clap(
+        long,
+        env = "MEESIGN_TOKEN",
+        help = "API token presented to the server"
+    )]
+    token: Option<String>,
+
+    #[cfg(feature = "cli")]
+    #[clap(long, help = "Client certificate presented to the server (PEM)")]
+    client_cert: Option<std::path::PathBuf>,
+
+    #[cfg(feature = "cli")]
+    #[clap(long, help = "Private key of the client certificate (PEM)")]
+    client_key: Option<std::path::PathBuf>,
+
     #[cfg(feature = "cli")]
     #[clap(subcommand)]
     command: Option<cli::Commands>,
@@ -80,29 +114,73 @@ pub fn get_timestamp() -> u64 {
 async fn main() -> Result<(), String> {
     env_logger::init();
     let args = Args::parse();
+    config::init(config::Config::resolve(
+        args.config_overrides.clone(),
+        args.config.as_deref(),
+    )?);
 
     #[cfg(feature = "cli")]
     if args.command.is_some() {
         return cli::handle_command(args).await;
     }
 
-    let state = Arc::new(Mutex::new(State::new()));
+    let config = config::get();
+    config.check_keys()?;
 
-    let grpc = interfaces::grpc::run_grpc(state.clone(), &args.addr, args.port);
-    let timer = interfaces::timer::run_timer(state);
+    if let Some(path) = &config.audit_log {
+        audit::init(path)?;
+    }
+    if let Some(path) = &config.approval_policy {
+        approval::init(path)?;
+    }
+    if let Some(path) = &config.webhooks {
+        webhook::init(path)?;
+    }
+    pdf::init(config.pdf_backend);
+    let storage = SqliteStorage::open(&config.database)
+        .map_err(|e| format!("Unable to open database: {}", e))?;
+    let state = Arc::new(State::new(Box::new(storage))?);
+
+    let policy = match &config.policy {
+        Some(path) => policy::Policy::load(path)?,
+        None => {
+            log::warn!("No requester policy configured, requests are not restricted");
+            policy::Policy::unrestricted()
+        }
+    };
+
+    let policy = Arc::new(policy);
+    let grpc = interfaces::grpc::run_grpc(state.clone(), policy.clone(), &config.addr, config.port);
+    let admin = async {
+        match config.admin_addr {
+            Some(addr) => interfaces::admin::run_admin(state.clone(), policy.clone(), addr).await,
+            None => Ok(()),
+        }
+    };
+    let metrics = async {
+        match config.metrics_addr {
+            Some(addr) => interfaces::metrics::run_metrics(state.clone(), addr).await,
+            None => Ok(()),
+        }
+    };
+    let timer = interfaces::timer::run_timer(state.clone(), config.task_limits.clone());
 
-    try_join!(grpc, timer).map(|_| ())
+    try_join!(grpc, admin, metrics, timer).map(|_| ())
 }
 
 #[cfg(feature = "cli")]
 mod cli {
+    use crate::proto::admin_client::AdminClient;
     use crate::proto::mpc_client::MpcClient;
     use crate::proto::KeyType;
     use crate::{Args, CA_CERT};
     use clap::Subcommand;
+    use std::path::PathBuf;
     use std::str::FromStr;
     use std::time::SystemTime;
-    use tonic::transport::{Certificate, Channel, ClientTlsConfig, Uri};
+    use tonic::metadata::MetadataValue;
+    use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity, Uri};
+    use tonic::Request;
 
     #[derive(Subcommand)]
     pub enum Commands {
@@ -118,6 +196,9 @@ mod cli {
             threshold: u32,
             #[clap(help = "sign_pdf or sign_challenge")]
             key_type: String,
+            #[clap(
+                help = "Hex identifiers, suffixed with :<shares> for devices holding several shares"
+            )]
             device_ids: Vec<String>,
         },
         RequestSignPdf {
@@ -130,29 +211,131 @@ mod cli {
             group_id: String,
             data: String,
         },
+        RevokeDevice {
+            device_id: String,
+        },
+        RemoveDevice {
+            device_id: String,
+        },
+        DeleteGroup {
+            group_id: String,
+        },
+        RestartTask {
+            task_id: String,
+        },
+        VerifyAuditLog {
+            path: PathBuf,
+        },
+    }
+
+    /// Connect to `port` of the server, presenting the client certificate if configured
+    async fn connect(args: &Args, port: u16) -> Result<Channel, String> {
+        let mut tls = ClientTlsConfig::new()
+            .domain_name(&args.host)
+            .ca_certificate(Certificate::from_pem(
+                CA_CERT
+                    .to_pem()
+                    .map_err(|_| "Unable to load CA certificate".to_string())?,
+            ));
+        if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+            let cert =
+                std::fs::read(cert).map_err(|_| "Unable to load client certificate".to_string())?;
+            let key = std::fs::read(key).map_err(|_| "Unable to load client key".to_string())?;
+            tls = tls.identity(Identity::from_pem(cert, key));
+        }
+
+        Channel::builder(
+            Uri::from_str(&format!("https://{}:{}", &args.host, port))
+                .map_err(|_| "Unable to parse URI".to_string())?,
+        )
+        .tls_config(tls)
+        .map_err(|_| "Unable to configure TLS connection".to_string())?
+        .connect()
+        .await
+        .map_err(|_| "Unable to connect to the server".to_string())
+    }
+
+    async fn handle_admin_command(command: Commands, channel: Channel) -> Result<(), String> {
+        let mut client = AdminClient::new(channel);
+        let decode = |id: String| hex::decode(id).map_err(|_| String::from("Invalid identifier"));
+        let request_failed = |e: tonic::Status| format!("Request failed: {}", e.message());
+        match command {
+            Commands::RevokeDevice { device_id } => {
+                let device_id = decode(device_id)?;
+                client
+                    .revoke_device(crate::proto::DeviceRevocation { device_id })
+                    .await
+                    .map_err(request_failed)?;
+                println!("Device revoked");
+            }
+            Commands::RemoveDevice { device_id } => {
+                let device_id = decode(device_id)?;
+                client
+                    .remove_device(crate::proto::DeviceRemoval { device_id })
+                    .await
+                    .map_err(request_failed)?;
+                println!("Device removed");
+            }
+            Commands::DeleteGroup { group_id } => {
+                let group_id = decode(group_id)?;
+                client
+                    .delete_group(crate::proto::GroupDeletion { group_id })
+                    .await
+                    .map_err(request_failed)?;
+                println!("Group deleted");
+            }
+            Commands::RestartTask { task_id } => {
+                let task_id = decode(task_id)?;
+                client
+                    .restart_task(crate::proto::TaskRestart { task_id })
+                    .await
+                    .map_err(request_failed)?;
+                println!("Task restarted");
+            }
+            _ => unreachable!("not an admin command"),
+        }
+        Ok(())
     }
 
     pub(super) async fn handle_command(args: Args) -> Result<(), String> {
+        match &args.command {
+            Some(Commands::VerifyAuditLog { path }) => {
+                let records = crate::audit::verify(path)?;
+                println!("Audit log intact ({} records)", records);
+                return Ok(());
+            }
+            Some(
+                Commands::RevokeDevice { .. }
+                | Commands::RemoveDevice { .. }
+                | Commands::DeleteGroup { .. }
+                | Commands::RestartTask { .. },
+            ) => {
+                // the admin service listens on the port of the configured address
+                let admin_addr = crate::config::get().admin_addr.ok_or_else(|| {
+                    "The admin service is not configured, set admin-addr".to_string()
+                })?;
+                let channel = connect(&args, admin_addr.port()).await?;
+                return handle_admin_command(args.command.unwrap(), channel).await;
+            }
+            _ => {}
+        }
+        let channel = connect(&args, crate::config::get().port).await?;
         if let Some(command) = args.command {
-            let tls = ClientTlsConfig::new()
-                .domain_name(&args.host)
-                .ca_certificate(Certificate::from_pem(
-                    CA_CERT
-                        .to_pem()
-                        .map_err(|_| "Unable to load CA certificate".to_string())?,
-                ));
-
-            let channel = Channel::builder(
-                Uri::from_str(&format!("https://{}:{}", &args.host, args.port))
-                    .map_err(|_| "Unable to parse URI".to_string())?,
-            )
-            .tls_config(tls)
-            .map_err(|_| "Unable to configure TLS connection".to_string())?
-            .connect()
-            .await
-            .map_err(|_| "Unable to connect to the server".to_string())?;
-
-            let mut client = MpcClient::new(channel);
+            let token = args
+                .token
+                .map(|token| MetadataValue::try_from(format!("Bearer {}", token)))
+                .transpose()
+                .map_err(|_| "Invalid API token".to_string())?;
+            #[allow(clippy::result_large_err)]
+            let mut client =
+                MpcClient::with_interceptor(channel, move |mut request: Request<()>| {
+                    if let Some(token) = &token {
+                        request
+                            .metadata_mut()
+                            .insert("authorization", token.clone());
+                    }
+                    Ok(request)
+                });
 
             // TODO Refactor once MpcClient (GrpcClient) can be passed to functions more ergonomically
             // More info here https://github.com/hyperium/tonic/issues/110
@@ -183,7 +366,10 @@ mod cli {
                 }
                 Commands::GetGroups { device_id } => {
                     let device_id = device_id.map(|x| hex::decode(x).unwrap());
-                    let request = tonic::Request::new(crate::proto::GroupsRequest { device_id });
+                    let request = tonic::Request::new(crate::proto::GroupsRequest {
+                        device_id,
+                        ..Default::default()
+                    });
 
                     let response = client
                         .get_groups(request)
@@ -203,7 +389,10 @@ mod cli {
                 }
                 Commands::GetTasks { device_id } => {
                     let device_id = device_id.map(|x| hex::decode(x).unwrap());
-                    let request = tonic::Request::new(crate::proto::TasksRequest { device_id });
+                    let request = tonic::Request::new(crate::proto::TasksRequest {
+                        device_id,
+                        ..Default::default()
+                    });
 
                     let response = client
                         .get_tasks(request)
@@ -232,9 +421,16 @@ mod cli {
                     key_type,
                     device_ids,
                 } => {
-                    let device_ids: Vec<_> =
-                        device_ids.iter().map(|x| hex::decode(x).unwrap()).collect();
-                    if device_ids.len() <= 1 {
+                    let (device_ids, shares): (Vec<_>, Vec<_>) = device_ids
+                        .iter()
+                        .map(|x| match x.split_once(':') {
+                            Some((id, shares)) => {
+                                (hex::decode(id).unwrap(), shares.parse::<u32>().unwrap())
+                            }
+                            None => (hex::decode(x).unwrap(), 1),
+                        })
+                        .unzip();
+                    if shares.iter().sum::<u32>() <= 1 {
                         return Err(String::from("Not enough parties to create a group"));
                     }
 
@@ -248,6 +444,7 @@ mod cli {
                             "sign_challenge" => KeyType::SignChallenge,
                             _ => panic!("Incorrect key type"),
                         } as i32,
+                        shares,
                     });
 
                     let response = client
@@ -281,6 +478,7 @@ mod cli {
                         name,
                         group_id,
                         data,
+                        ..Default::default()
                     });
 
                     let response = client
@@ -315,6 +513,7 @@ mod cli {
                         name,
                         group_id,
                         data,
+                        ..Default::default()
                     });
 
                     let response = client
@@ -337,6 +536,7 @@ mod cli {
                         task.round
                     );
                 }
+                _ => unreachable!("handled without the MPC client"),
             }
         }
         Ok(())
@@ -0,0 +1,149 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    Encoder, HistogramTimer, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Registry, TextEncoder,
+};
+
+use crate::proto::ProtocolType;
+use crate::state::State;
+use crate::tasks::transcript::FaultKind;
+use crate::tasks::TaskStatus;
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new_custom(Some("meesign".into()), None).unwrap();
+    static ref DEVICES: IntGauge = register(IntGauge::new("devices", "Registered devices"));
+    static ref SUBSCRIBERS: IntGauge = register(IntGauge::new(
+        "subscribers",
+        "Open subscriptions of devices to task updates"
+    ));
+    static ref TASKS: IntGaugeVec = register(IntGaugeVec::new(
+        prometheus::opts!("tasks", "Tasks held by the server"),
+        &["type", "status"]
+    ));
+    static ref ROUNDS: IntCounterVec = register(IntCounterVec::new(
+        prometheus::opts!("protocol_rounds_total", "Protocol rounds started"),
+        &["protocol"]
+    ));
+    static ref RESTARTS: IntCounter = register(IntCounter::new(
+        "task_restarts_total",
+        "Stale tasks restarted by the timer"
+    ));
+    static ref DECISIONS: IntCounterVec = register(IntCounterVec::new(
+        prometheus::opts!("task_decisions_total", "Task decisions of devices"),
+        &["decision"]
+    ));
+    static ref DEVICE_FAULTS: IntCounterVec = register(IntCounterVec::new(
+        prometheus::opts!(
+            "device_faults_total",
+            "Problems of protocol runs attributed to devices"
+        ),
+        &["kind"]
+    ));
+    static ref PDF_FAILURES: IntCounterVec = register(IntCounterVec::new(
+        prometheus::opts!("pdf_failures_total", "Failures of the PDF signing backend"),
+        &["stage"]
+    ));
+    static ref RPC_DURATION: HistogramVec = register(HistogramVec::new(
+        prometheus::histogram_opts!("rpc_duration_seconds", "Duration of gRPC requests"),
+        &["method"]
+    ));
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.unwrap();
+    REGISTRY.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+pub fn round_started(protocol: ProtocolType) {
+    ROUNDS.with_label_values(&[protocol.as_str_name()]).inc();
+}
+
+pub fn task_restarted() {
+    RESTARTS.inc();
+}
+
+pub fn task_decided(accept: bool) {
+    let decision = if accept { "accept" } else { "reject" };
+    DECISIONS.with_label_values(&[decision]).inc();
+}
+
+pub fn device_fault(kind: FaultKind) {
+    DEVICE_FAULTS.with_label_values(&[kind.as_str()]).inc();
+}
+
+/// Record a failure of the PDF backend while preparing or finishing a document
+pub fn pdf_failed(stage: &str) {
+    PDF_FAILURES.with_label_values(&[stage]).inc();
+}
+
+/// Start measuring a gRPC request; the duration is recorded when the timer is dropped
+pub fn rpc_timer(method: &str) -> HistogramTimer {
+    RPC_DURATION.with_label_values(&[method]).start_timer()
+}
+
+fn status_label(status: &TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::Created => "CREATED",
+        TaskStatus::Running(_) => "RUNNING",
+        TaskStatus::Finished => "FINISHED",
+        TaskStatus::Failed(_) => "FAILED",
+        TaskStatus::Expired => "EXPIRED",
+        TaskStatus::Cancelled => "CANCELLED",
+    }
+}
+
+/// Update the gauges derived from `state` and encode all metrics in the text format
+pub fn render(state: &State) -> String {
+    DEVICES.set(state.get_devices().len() as i64);
+    SUBSCRIBERS.set(state.get_subscribers().len() as i64);
+    TASKS.reset();
+    for (_, task) in state.get_tasks() {
+        let task = task.lock().unwrap();
+        TASKS
+            .with_label_values(&[
+                task.get_type().as_str_name(),
+                status_label(&task.get_status()),
+            ])
+            .inc();
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+
+    #[test]
+    fn render_state() {
+        let state = State::new(Box::new(MemoryStorage::new())).unwrap();
+        assert!(state.add_device(&[0x01], "d1", &[0xf1]));
+        assert!(state.add_device(&[0x02], "d2", &[0xf2]));
+        state
+            .add_group_task(
+                "Sample Group",
+                &[vec![0x01], vec![0x02]],
+                2,
+                ProtocolType::Gg18,
+                crate::proto::KeyType::SignChallenge,
+                None,
+            )
+            .unwrap();
+        round_started(ProtocolType::Frost);
+        task_decided(false);
+        drop(rpc_timer("GetDevices"));
+
+        let metrics = render(&state);
+        assert!(metrics.contains("meesign_devices 2\n"));
+        assert!(metrics.contains("meesign_tasks{status=\"CREATED\",type=\"GROUP\"} 1\n"));
+        assert!(metrics.contains("meesign_protocol_rounds_total{protocol=\"FROST\"}"));
+        assert!(metrics.contains("meesign_task_decisions_total{decision=\"reject\"}"));
+        assert!(metrics.contains("meesign_rpc_duration_seconds_count{method=\"GetDevices\"}"));
+    }
+}
@@ -0,0 +1,101 @@
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+
+use tempfile::NamedTempFile;
+
+use crate::pdf::{PdfSigner, PreparedPdf};
+
+/// Signer delegating to `java -jar MeeSignHelper.jar` in the working directory
+pub struct JavaSigner;
+
+struct JavaPreparedPdf {
+    process: Child,
+    hash: Vec<u8>,
+    _file: NamedTempFile,
+}
+
+impl PdfSigner for JavaSigner {
+    fn prepare(&self, pdf: &[u8], certificate: &[u8]) -> Result<Box<dyn PreparedPdf>, String> {
+        let mut file =
+            NamedTempFile::new().map_err(|_| "Could not create temporary file".to_string())?;
+        file.write_all(pdf)
+            .map_err(|_| "Could not write in temporary file".to_string())?;
+
+        let mut process = Command::new("java")
+            .arg("-jar")
+            .arg("MeeSignHelper.jar")
+            .arg("sign")
+            .arg(file.path())
+            .stdout(Stdio::piped())
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|_| "Could not start PDFHelper".to_string())?;
+
+        let hash = request_hash(&mut process, certificate);
+        if hash.is_empty() {
+            let _ = process.kill();
+            return Err("PDFHelper did not output a hash".into());
+        }
+
+        Ok(Box::new(JavaPreparedPdf {
+            process,
+            hash,
+            _file: file,
+        }))
+    }
+}
+
+impl PreparedPdf for JavaPreparedPdf {
+    fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    fn finish(mut self: Box<Self>, signature: &[u8]) -> Result<Vec<u8>, String> {
+        include_signature(&mut self.process, signature)
+    }
+}
+
+impl Drop for JavaPreparedPdf {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn request_hash(process: &mut Child, certificate: &[u8]) -> Vec<u8> {
+    let process_stdin = process.stdin.as_mut().unwrap();
+    let process_stdout = process.stdout.as_mut().unwrap();
+
+    if process_stdin.write_all(certificate).is_err() || process_stdin.flush().is_err() {
+        return Vec::new();
+    }
+
+    let mut in_buffer = [0u8; 65]; // \n
+    if let Ok(()) = process_stdout.read_exact(&mut in_buffer) {
+        hex::decode(&in_buffer[..64]).unwrap_or_default()
+    } else {
+        Vec::new()
+    }
+}
+
+fn include_signature(process: &mut Child, signature: &[u8]) -> Result<Vec<u8>, String> {
+    if signature.len() != 64 {
+        return Err("Invalid signature length".into());
+    }
+    let process_stdin = process.stdin.as_mut().unwrap();
+    let process_stdout = process.stdout.as_mut().unwrap();
+
+    let mut out_buffer = [0u8; 129];
+    out_buffer[..128].copy_from_slice(hex::encode(signature).as_bytes());
+    out_buffer[128] = b'\n';
+
+    process_stdin
+        .write_all(&out_buffer)
+        .map_err(|_| "Could not send signature to PDFHelper".to_string())?;
+
+    let mut result = Vec::new();
+    process_stdout
+        .read_to_end(&mut result)
+        .map_err(|_| "Could not read PDFHelper output".to_string())?;
+    hex::decode(&result).map_err(|_| "Invalid PDFHelper output".to_string())
+}
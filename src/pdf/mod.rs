@@ -0,0 +1,52 @@
+use std::sync::OnceLock;
+
+use log::info;
+use serde::Deserialize;
+
+pub mod java;
+pub mod native;
+
+/// Backend preparing PDF documents for a group signature
+pub trait PdfSigner: Send + Sync {
+    /// Prepare `pdf` to be signed by the holder of the group `certificate`
+    fn prepare(&self, pdf: &[u8], certificate: &[u8]) -> Result<Box<dyn PreparedPdf>, String>;
+}
+
+/// PDF document waiting for the group signature
+pub trait PreparedPdf: Send + Sync {
+    /// SHA-256 hash to be signed by the group
+    fn hash(&self) -> &[u8];
+
+    /// Embed the group `signature` (raw `r || s`) and return the signed document
+    fn finish(self: Box<Self>, signature: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// In-process implementation
+    Native,
+    /// External MeeSignHelper.jar run by `java`
+    Java,
+}
+
+static SIGNER: OnceLock<Box<dyn PdfSigner>> = OnceLock::new();
+
+/// Select the PDF signing backend; has to be called before the first PDF is signed
+pub fn init(backend: Backend) {
+    info!("Using PDF signing backend {:?}", backend);
+    let signer: Box<dyn PdfSigner> = match backend {
+        Backend::Native => Box::new(native::NativeSigner),
+        Backend::Java => Box::new(java::JavaSigner),
+    };
+    if SIGNER.set(signer).is_err() {
+        panic!("PDF signing backend already initialized");
+    }
+}
+
+/// The selected PDF signing backend, the native one if none was selected
+pub fn signer() -> &'static dyn PdfSigner {
+    SIGNER
+        .get_or_init(|| Box::new(native::NativeSigner))
+        .as_ref()
+}
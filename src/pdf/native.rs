@@ -0,0 +1,464 @@
+use std::ops::Range;
+
+use lopdf::{dictionary, Dictionary, IncrementalDocument, Object, StringFormat};
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::nid::Nid;
+use openssl::x509::X509;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use crate::der::{der, integer, oid, sequence, set};
+use crate::pdf::{PdfSigner, PreparedPdf};
+
+/// Bytes reserved for the CMS signature in the signature dictionary
+const SIGNATURE_SIZE: usize = 8192;
+/// Placeholder keeping space for the final `/ByteRange` values
+const BYTE_RANGE_PLACEHOLDER: i64 = 9_999_999_999;
+
+// DER encoded object identifiers
+const OID_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+const OID_CONTENT_TYPE: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x03];
+const OID_MESSAGE_DIGEST: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x04];
+const OID_SIGNING_CERTIFICATE_V2: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x10, 0x02, 0x2f,
+];
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+
+/// In-process signer producing PAdES (ETSI.CAdES.detached) signatures
+///
+/// The signature is added in an incremental update as an invisible signature field on
+/// the first page. The group signs the hash of the CMS signed attributes.
+pub struct NativeSigner;
+
+struct NativePreparedPdf {
+    document: Vec<u8>,
+    /// Position of the hexadecimal `/Contents` placeholder (without the delimiters)
+    contents: Range<usize>,
+    signed_attributes: Vec<u8>,
+    certificate: X509,
+    hash: Vec<u8>,
+}
+
+impl PdfSigner for NativeSigner {
+    fn prepare(&self, pdf: &[u8], certificate: &[u8]) -> Result<Box<dyn PreparedPdf>, String> {
+        let certificate = parse_certificate(certificate)?;
+        certificate
+            .public_key()
+            .and_then(|key| key.ec_key())
+            .map_err(|_| "Group certificate does not hold an EC key".to_string())?;
+
+        let name = certificate
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .map(|entry| entry.data().as_slice().to_vec())
+            .unwrap_or_default();
+        let (mut document, contents) = add_signature_field(pdf, name)?;
+        let byte_range = [
+            0,
+            contents.start - 1,
+            contents.end + 1,
+            document.len() - contents.end - 1,
+        ];
+        fill_byte_range(&mut document, pdf.len(), &byte_range)?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(&document[..byte_range[1]]);
+        hasher.update(&document[byte_range[2]..]);
+        let digest = hasher.finalize();
+
+        let certificate_der = certificate.to_der().map_err(|e| e.to_string())?;
+        let signed_attributes = set(&[
+            sequence(&[oid(OID_CONTENT_TYPE), set(&[oid(OID_DATA)])]),
+            sequence(&[oid(OID_MESSAGE_DIGEST), set(&[der(0x04, &digest)])]),
+            sequence(&[
+                oid(OID_SIGNING_CERTIFICATE_V2),
+                set(&[sequence(&[sequence(&[sequence(&[der(
+                    0x04,
+                    &Sha256::digest(&certificate_der),
+                )])])])]),
+            ]),
+        ]);
+        let hash = Sha256::digest(&signed_attributes).to_vec();
+
+        Ok(Box::new(NativePreparedPdf {
+            document,
+            contents,
+            signed_attributes,
+            certificate,
+            hash,
+        }))
+    }
+}
+
+impl PreparedPdf for NativePreparedPdf {
+    fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    fn finish(mut self: Box<Self>, signature: &[u8]) -> Result<Vec<u8>, String> {
+        if signature.len() != 64 {
+            return Err("Invalid signature length".into());
+        }
+        let signature = EcdsaSig::from_private_components(
+            BigNum::from_slice(&signature[..32]).map_err(|e| e.to_string())?,
+            BigNum::from_slice(&signature[32..]).map_err(|e| e.to_string())?,
+        )
+        .map_err(|e| e.to_string())?;
+        let key = self
+            .certificate
+            .public_key()
+            .and_then(|key| key.ec_key())
+            .map_err(|e| e.to_string())?;
+        if !signature.verify(&self.hash, &key).unwrap_or(false) {
+            return Err("Signature does not match the group certificate".into());
+        }
+
+        let cms = signed_data(
+            &self.certificate,
+            &self.signed_attributes,
+            &signature.to_der().map_err(|e| e.to_string())?,
+        )?;
+        let cms = hex::encode_upper(cms);
+        if cms.len() > self.contents.len() {
+            return Err("Signature does not fit the reserved space".into());
+        }
+        self.document[self.contents.start..self.contents.start + cms.len()]
+            .copy_from_slice(cms.as_bytes());
+        Ok(self.document)
+    }
+}
+
+fn parse_certificate(certificate: &[u8]) -> Result<X509, String> {
+    if certificate.starts_with(b"-----BEGIN") {
+        X509::from_pem(certificate)
+    } else {
+        X509::from_der(certificate)
+    }
+    .map_err(|_| "Invalid group certificate".to_string())
+}
+
+/// Append an incremental update with an empty signature field
+///
+/// Returns the updated document and the position of the `/Contents` placeholder.
+fn add_signature_field(pdf: &[u8], name: Vec<u8>) -> Result<(Vec<u8>, Range<usize>), String> {
+    let mut document = IncrementalDocument::load_from(pdf).map_err(|e| e.to_string())?;
+    let previous = document.get_prev_documents();
+    if previous.is_encrypted() {
+        return Err("Encrypted PDF documents are not supported".into());
+    }
+    let catalog_id = previous
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .map_err(|_| "Missing document catalog".to_string())?;
+    let page_id = *previous
+        .get_pages()
+        .values()
+        .next()
+        .ok_or_else(|| "Document has no pages".to_string())?;
+    let mut catalog = previous
+        .get_dictionary(catalog_id)
+        .map_err(|e| e.to_string())?
+        .clone();
+    let mut page = previous
+        .get_dictionary(page_id)
+        .map_err(|e| e.to_string())?
+        .clone();
+    let (form_id, mut form) = match catalog.get(b"AcroForm") {
+        Ok(Object::Reference(id)) => (
+            Some(*id),
+            previous
+                .get_dictionary(*id)
+                .map_err(|e| e.to_string())?
+                .clone(),
+        ),
+        Ok(Object::Dictionary(form)) => (None, form.clone()),
+        _ => (None, Dictionary::new()),
+    };
+    let mut fields = resolve_array(previous, form.get(b"Fields").ok())?;
+    let mut annotations = resolve_array(previous, page.get(b"Annots").ok())?;
+    let field_name = format!("Signature{}", fields.len() + 1);
+
+    let new = &mut document.new_document;
+    // cross-reference stream parameters of the previous revision do not apply to ours
+    new.trailer.remove(b"DecodeParms");
+    new.trailer.remove(b"XRefStm");
+    let signature_id = new.add_object(dictionary! {
+        "Type" => "Sig",
+        "Filter" => "Adobe.PPKLite",
+        "SubFilter" => "ETSI.CAdES.detached",
+        "ByteRange" => vec![
+            0.into(),
+            BYTE_RANGE_PLACEHOLDER.into(),
+            BYTE_RANGE_PLACEHOLDER.into(),
+            BYTE_RANGE_PLACEHOLDER.into(),
+        ],
+        "Contents" => Object::String(vec![0; SIGNATURE_SIZE], StringFormat::Hexadecimal),
+        "M" => OffsetDateTime::now_utc(),
+        "Name" => Object::String(name, StringFormat::Literal),
+    });
+    let widget_id = new.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Sig",
+        "T" => Object::string_literal(field_name),
+        "V" => signature_id,
+        "F" => 132,
+        "Rect" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+        "P" => page_id,
+    });
+
+    fields.push(Object::Reference(widget_id));
+    form.set("Fields", fields);
+    form.set("SigFlags", 3);
+    match form_id {
+        Some(form_id) => new.set_object(form_id, form),
+        None => catalog.set("AcroForm", form),
+    }
+    annotations.push(Object::Reference(widget_id));
+    page.set("Annots", annotations);
+    new.set_object(catalog_id, catalog);
+    new.set_object(page_id, page);
+
+    let mut output = Vec::new();
+    document.save_to(&mut output).map_err(|e| e.to_string())?;
+
+    let placeholder = [b"<".as_slice(), &[b'0'; 2 * SIGNATURE_SIZE], b">"].concat();
+    let start = find(&output[pdf.len()..], &placeholder)
+        .map(|position| pdf.len() + position + 1)
+        .ok_or_else(|| "Signature placeholder not found".to_string())?;
+    Ok((output, start..start + 2 * SIGNATURE_SIZE))
+}
+
+/// Resolve an optional (possibly indirect) array into a direct one
+fn resolve_array(
+    document: &lopdf::Document,
+    object: Option<&Object>,
+) -> Result<Vec<Object>, String> {
+    match object {
+        None => Ok(Vec::new()),
+        Some(object) => document
+            .dereference(object)
+            .and_then(|(_, object)| object.as_array())
+            .cloned()
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Overwrite the `/ByteRange` placeholder of the appended signature dictionary
+fn fill_byte_range(
+    document: &mut [u8],
+    offset: usize,
+    byte_range: &[usize; 4],
+) -> Result<(), String> {
+    let key = find(&document[offset..], b"/ByteRange")
+        .map(|position| offset + position)
+        .ok_or_else(|| "Byte range not found".to_string())?;
+    let start =
+        key + find(&document[key..], b"[").ok_or_else(|| "Byte range not found".to_string())?;
+    let end =
+        start + find(&document[start..], b"]").ok_or_else(|| "Byte range not found".to_string())?;
+    let value = format!(
+        "[{} {} {} {}",
+        byte_range[0], byte_range[1], byte_range[2], byte_range[3]
+    );
+    if value.len() > end - start {
+        return Err("Byte range does not fit the placeholder".into());
+    }
+    document[start..end].fill(b' ');
+    document[start..start + value.len()].copy_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Encode CMS ContentInfo with a detached SignedData holding a single signer
+fn signed_data(
+    certificate: &X509,
+    signed_attributes: &[u8],
+    signature: &[u8],
+) -> Result<Vec<u8>, String> {
+    let issuer = certificate
+        .issuer_name()
+        .to_der()
+        .map_err(|e| e.to_string())?;
+    let serial = certificate
+        .serial_number()
+        .to_bn()
+        .map_err(|e| e.to_string())?
+        .to_vec();
+    let certificate = certificate.to_der().map_err(|e| e.to_string())?;
+
+    // signedAttrs are [0] IMPLICIT in SignerInfo, but hashed as SET OF
+    let mut signed_attributes = signed_attributes.to_vec();
+    signed_attributes[0] = 0xa0;
+
+    let signer_info = sequence(&[
+        integer(&[1]),
+        sequence(&[issuer, integer(&serial)]),
+        sequence(&[oid(OID_SHA256)]),
+        signed_attributes,
+        sequence(&[oid(OID_ECDSA_WITH_SHA256)]),
+        der(0x04, signature),
+    ]);
+    let signed_data = sequence(&[
+        integer(&[1]),
+        set(&[sequence(&[oid(OID_SHA256)])]),
+        sequence(&[oid(OID_DATA)]),
+        der(0xa0, &certificate),
+        set(&[signer_info]),
+    ]);
+    Ok(sequence(&[oid(OID_SIGNED_DATA), der(0xa0, &signed_data)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{Document, Stream};
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::pkcs7::{Pkcs7, Pkcs7Flags};
+    use openssl::pkey::{PKey, Private};
+    use openssl::stack::Stack;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    fn sample_pdf() -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let content_id = document.add_object(Stream::new(
+            dictionary! {},
+            b"BT /F1 24 Tf 72 720 Td (MeeSign) Tj ET".to_vec(),
+        ));
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+        let mut pdf = Vec::new();
+        document.save_to(&mut pdf).unwrap();
+        pdf
+    }
+
+    fn group_certificate() -> (EcKey<Private>, Vec<u8>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let pkey = PKey::from_ec_key(key.clone()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "Sample Group")
+            .unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder
+            .set_not_before(&openssl::asn1::Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&openssl::asn1::Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        (key, builder.build().to_der().unwrap())
+    }
+
+    fn sign(key: &EcKey<Private>, hash: &[u8]) -> Vec<u8> {
+        let signature = EcdsaSig::sign(hash, key).unwrap();
+        [
+            signature.r().to_vec_padded(32).unwrap(),
+            signature.s().to_vec_padded(32).unwrap(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn sign_pdf() {
+        let pdf = sample_pdf();
+        let (key, certificate) = group_certificate();
+
+        let prepared = NativeSigner.prepare(&pdf, &certificate).unwrap();
+        let signature = sign(&key, prepared.hash());
+        let signed = prepared.finish(&signature).unwrap();
+        assert_eq!(&signed[..pdf.len()], &pdf[..]);
+
+        let document = Document::load_mem(&signed).unwrap();
+        let page_id = *document.get_pages().values().next().unwrap();
+        let page = document.get_dictionary(page_id).unwrap();
+        let widget_id = page.get(b"Annots").unwrap().as_array().unwrap()[0]
+            .as_reference()
+            .unwrap();
+        let widget = document.get_dictionary(widget_id).unwrap();
+        let signature_id = widget.get(b"V").unwrap().as_reference().unwrap();
+        let dictionary = document.get_dictionary(signature_id).unwrap();
+        let byte_range: Vec<_> = dictionary
+            .get(b"ByteRange")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_i64().unwrap() as usize)
+            .collect();
+        assert_eq!(byte_range[2] + byte_range[3], signed.len());
+        let contents = dictionary.get(b"Contents").unwrap().as_str().unwrap();
+        assert_eq!(
+            hex::decode(&signed[byte_range[1] + 1..byte_range[2] - 1]).unwrap(),
+            contents
+        );
+
+        let signed_content = [
+            &signed[..byte_range[1]],
+            &signed[byte_range[2]..byte_range[2] + byte_range[3]],
+        ]
+        .concat();
+        let length = 4 + u16::from_be_bytes([contents[2], contents[3]]) as usize;
+        let cms = Pkcs7::from_der(&contents[..length]).unwrap();
+        let store = X509StoreBuilder::new().unwrap().build();
+        cms.verify(
+            &Stack::new().unwrap(),
+            &store,
+            Some(&signed_content),
+            None,
+            Pkcs7Flags::NOVERIFY | Pkcs7Flags::BINARY,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_signature() {
+        let (key, certificate) = group_certificate();
+        let prepared = NativeSigner.prepare(&sample_pdf(), &certificate).unwrap();
+        let signature = sign(&key, &[0x00; 32]);
+        assert!(prepared.finish(&signature).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_input() {
+        let (_, certificate) = group_certificate();
+        assert!(NativeSigner.prepare(b"not a pdf", &certificate).is_err());
+        assert!(NativeSigner.prepare(&sample_pdf(), &[0x30]).is_err());
+    }
+}
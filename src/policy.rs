@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use log::{info, warn};
+use serde::Deserialize;
+use sha2::Digest;
+
+use crate::utils;
+
+/// Wildcard matching any device or group identifier
+const ANY: &str = "*";
+
+/// Operation a requester asks for
+pub enum Action {
+    /// Create a group of the given devices
+    CreateGroup(Vec<Vec<u8>>),
+    /// Request a signature or a decryption from the given group
+    UseGroup(Vec<u8>),
+    /// Manage registered devices, e.g. revoke their certificates
+    Administer,
+}
+
+/// Client allowed to request groups, signatures and decryptions
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Requester {
+    pub name: String,
+    /// Hex-encoded SHA-256 of the DER client certificate (issued by the MeeSign CA)
+    certificate: Option<String>,
+    /// Hex-encoded SHA-256 of the API token sent as `authorization: Bearer <token>`
+    token_sha256: Option<String>,
+    /// Devices which may be members of groups created by the requester
+    #[serde(default)]
+    group_devices: Vec<String>,
+    /// Groups from which the requester may request operations
+    #[serde(default)]
+    groups: Vec<String>,
+    /// Whether the requester may perform administrative operations
+    #[serde(default)]
+    admin: bool,
+}
+
+impl Requester {
+    pub fn permits(&self, action: &Action) -> bool {
+        match action {
+            Action::CreateGroup(devices) => {
+                !devices.is_empty()
+                    && devices
+                        .iter()
+                        .all(|device| matches_any(&self.group_devices, device))
+            }
+            Action::UseGroup(group) => matches_any(&self.groups, group),
+            Action::Administer => self.admin,
+        }
+    }
+}
+
+fn matches_any(patterns: &[String], identifier: &[u8]) -> bool {
+    let identifier = hex::encode(identifier);
+    patterns
+        .iter()
+        .any(|pattern| pattern == ANY || pattern.eq_ignore_ascii_case(&identifier))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default, rename = "requester")]
+    requesters: Vec<Requester>,
+}
+
+/// Authorization policy of requester RPCs
+pub struct Policy {
+    /// `None` if no policy is configured and requests are not restricted
+    requesters: Option<Vec<Requester>>,
+}
+
+impl Policy {
+    /// Policy allowing anyone to request anything
+    pub fn unrestricted() -> Self {
+        Policy { requesters: None }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let policy = Self::parse(&content)
+            .map_err(|e| format!("Invalid policy {}: {}", path.display(), e))?;
+        info!(
+            "Loaded requester policy with {} requesters",
+            policy.requesters.as_ref().map(Vec::len).unwrap_or(0)
+        );
+        Ok(policy)
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let file: PolicyFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        let mut names = HashSet::new();
+        let mut digests = HashSet::new();
+        for requester in &file.requesters {
+            if !names.insert(&requester.name) {
+                return Err(format!(
+                    "Requester {} is listed more than once",
+                    requester.name
+                ));
+            }
+            if requester.certificate.is_none() && requester.token_sha256.is_none() {
+                return Err(format!(
+                    "Requester {} has neither a certificate nor a token",
+                    requester.name
+                ));
+            }
+            for digest in [&requester.certificate, &requester.token_sha256]
+                .into_iter()
+                .flatten()
+            {
+                let digest = match hex::decode(digest) {
+                    Ok(digest) if digest.len() == 32 => digest,
+                    _ => {
+                        return Err(format!(
+                            "Requester {} has an invalid SHA-256 digest",
+                            requester.name
+                        ))
+                    }
+                };
+                if !digests.insert(digest) {
+                    return Err(format!(
+                        "Requester {} shares a certificate or token with another requester",
+                        requester.name
+                    ));
+                }
+            }
+        }
+        Ok(Policy {
+            requesters: Some(file.requesters),
+        })
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.requesters.is_some()
+    }
+
+    /// Find the requester presenting the client certificate with `certificate_id`
+    /// (the SHA-256 of the DER certificate, see `cert_fingerprint`) or the API `token`
+    pub fn identify(
+        &self,
+        certificate_id: Option<&[u8]>,
+        token: Option<&str>,
+    ) -> Option<&Requester> {
+        let requesters = self.requesters.as_ref()?;
+        let token_sha256 = token.map(|token| sha2::Sha256::digest(token.as_bytes()).to_vec());
+        let requester = requesters.iter().find(|requester| {
+            matches_digest(&requester.certificate, certificate_id)
+                || matches_digest(&requester.token_sha256, token_sha256.as_deref())
+        });
+        if requester.is_none() && (certificate_id.is_some() || token.is_some()) {
+            warn!(
+                "Unknown requester certificate_id={}",
+                certificate_id.map(utils::hextrunc).unwrap_or_default()
+            );
+        }
+        requester
+    }
+}
+
+fn matches_digest(expected: &Option<String>, presented: Option<&[u8]>) -> bool {
+    match (expected, presented) {
+        (Some(expected), Some(presented)) => {
+            hex::decode(expected).map(|expected| expected == presented) == Ok(true)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_policy() -> Policy {
+        Policy::parse(&format!(
+            r#"
+            [[requester]]
+            name = "portal"
+            certificate = "{}"
+            group-devices = ["*"]
+            groups = ["aaaa"]
+            admin = true
+
+            [[requester]]
+            name = "script"
+            token-sha256 = "{}"
+            group-devices = ["01", "02"]
+            "#,
+            hex::encode([0xcc; 32]),
+            hex::encode(sha2::Sha256::digest(b"secret")),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn identify() {
+        let policy = sample_policy();
+        assert!(policy.is_restricted());
+        assert_eq!(
+            policy.identify(Some(&[0xcc; 32]), None).unwrap().name,
+            "portal"
+        );
+        assert_eq!(
+            policy.identify(None, Some("secret")).unwrap().name,
+            "script"
+        );
+        assert_eq!(
+            policy
+                .identify(Some(&[0xdd; 32]), Some("secret"))
+                .unwrap()
+                .name,
+            "script"
+        );
+        assert!(policy.identify(Some(&[0xdd; 32]), Some("wrong")).is_none());
+        assert!(policy.identify(None, None).is_none());
+        assert!(Policy::unrestricted().identify(None, None).is_none());
+    }
+
+    #[test]
+    fn permits() {
+        let policy = sample_policy();
+        let portal = policy.identify(Some(&[0xcc; 32]), None).unwrap();
+        let script = policy.identify(None, Some("secret")).unwrap();
+
+        assert!(portal.permits(&Action::CreateGroup(vec![vec![0x01], vec![0x05]])));
+        assert!(portal.permits(&Action::UseGroup(vec![0xaa, 0xaa])));
+        assert!(!portal.permits(&Action::UseGroup(vec![0xbb])));
+        assert!(portal.permits(&Action::Administer));
+
+        assert!(script.permits(&Action::CreateGroup(vec![vec![0x01], vec![0x02]])));
+        assert!(!script.permits(&Action::CreateGroup(vec![vec![0x01], vec![0x03]])));
+        assert!(!script.permits(&Action::CreateGroup(Vec::new())));
+        assert!(!script.permits(&Action::UseGroup(vec![0xaa, 0xaa])));
+        assert!(!script.permits(&Action::Administer));
+    }
+
+    #[test]
+    fn invalid_policy() {
+        assert!(Policy::parse("[[requester]]\nname = \"nobody\"").is_err());
+        assert!(Policy::parse("[[requester]]\nname = \"short\"\ntoken-sha256 = \"abcd\"").is_err());
+        assert!(Policy::parse("[[requesters]]\nname = \"typo\"").is_err());
+
+        let requester = |name: &str, digest: u8| {
+            format!(
+                "[[requester]]\nname = \"{}\"\ntoken-sha256 = \"{}\"\n",
+                name,
+                hex::encode([digest; 32])
+            )
+        };
+        assert!(Policy::parse(&(requester("a", 0x01) + &requester("b", 0x02))).is_ok());
+        assert!(Policy::parse(&(requester("a", 0x01) + &requester("a", 0x02))).is_err());
+        assert!(Policy::parse(&(requester("a", 0x01) + &requester("b", 0x01))).is_err());
+        // digests are compared regardless of their case
+        let upper = requester("b", 0xab).replace("ab", "AB");
+        assert!(Policy::parse(&upper).is_ok());
+        assert!(Policy::parse(&(requester("a", 0xab) + &upper)).is_err());
+        assert!(Policy::parse("").unwrap().is_restricted());
+    }
+}
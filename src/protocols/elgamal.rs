@@ -54,6 +54,10 @@ impl Protocol for ElgamalGroup {
         self.round
     }
 
+    fn set_round(&mut self, round: u16) {
+        self.round = round;
+    }
+
     fn last_round(&self) -> u16 {
         4
     }
@@ -107,6 +111,10 @@ impl Protocol for ElgamalDecrypt {
         self.round
     }
 
+    fn set_round(&mut self, round: u16) {
+        self.round = round;
+    }
+
     fn last_round(&self) -> u16 {
         2
     }
@@ -54,6 +54,10 @@ impl Protocol for FROSTGroup {
         self.round
     }
 
+    fn set_round(&mut self, round: u16) {
+        self.round = round;
+    }
+
     fn last_round(&self) -> u16 {
         3
     }
@@ -111,6 +115,10 @@ impl Protocol for FROSTSign {
         self.round
     }
 
+    fn set_round(&mut self, round: u16) {
+        self.round = round;
+    }
+
     fn last_round(&self) -> u16 {
         3
     }
@@ -54,6 +54,10 @@ impl Protocol for GG18Group {
         self.round
     }
 
+    fn set_round(&mut self, round: u16) {
+        self.round = round;
+    }
+
     fn last_round(&self) -> u16 {
         6
     }
@@ -107,6 +111,10 @@ impl Protocol for GG18Sign {
         self.round
     }
 
+    fn set_round(&mut self, round: u16) {
+        self.round = round;
+    }
+
     fn last_round(&self) -> u16 {
         10
     }
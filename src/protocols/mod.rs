@@ -20,6 +20,8 @@ pub trait Protocol {
     fn advance(&mut self, communicator: &mut Communicator);
     fn finalize(&mut self, communicator: &mut Communicator) -> Option<Vec<u8>>;
     fn round(&self) -> u16;
+    /// Set the current round; used when a task is restored from its snapshot
+    fn set_round(&mut self, round: u16);
     fn last_round(&self) -> u16;
     fn get_type(&self) -> ProtocolType;
 }
@@ -0,0 +1,124 @@
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKeyRef, Private};
+use openssl::sign::Signer;
+use openssl::x509::{X509Ref, X509};
+
+use crate::der::{der, integer, oid, sequence, time};
+
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+
+/// Validity of a published CRL in seconds
+pub const CRL_VALIDITY: u64 = 7 * 24 * 60 * 60;
+
+/// Revoked device certificate
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Revocation {
+    /// Serial number of the certificate as an unsigned big-endian integer
+    pub serial: Vec<u8>,
+    pub device_id: Vec<u8>,
+    pub revoked_at: u64,
+    /// The certificate was replaced by a renewed one, the device itself stays valid
+    pub superseded: bool,
+}
+
+/// Serial number of a DER-encoded certificate as an unsigned big-endian integer
+pub fn certificate_serial(certificate: &[u8]) -> Result<Vec<u8>, String> {
+    X509::from_der(certificate)
+        .and_then(|certificate| certificate.serial_number().to_bn())
+        .map(|serial| serial.to_vec())
+        .map_err(|e| e.to_string())
+}
+
+/// Build a DER-encoded CRL of `revocations` issued at `timestamp` and signed by the CA
+pub fn build_crl(
+    revocations: &[Revocation],
+    timestamp: u64,
+    ca_cert: &X509Ref,
+    ca_key: &PKeyRef<Private>,
+) -> Result<Vec<u8>, String> {
+    if ca_key.id() != Id::EC {
+        return Err("CRL signing requires an ECDSA CA key".into());
+    }
+    let algorithm = sequence(&[oid(OID_ECDSA_WITH_SHA256)]);
+    let issuer = ca_cert.subject_name().to_der().map_err(|e| e.to_string())?;
+
+    let mut tbs = vec![
+        algorithm.clone(),
+        issuer,
+        time(timestamp),
+        time(timestamp + CRL_VALIDITY),
+    ];
+    if !revocations.is_empty() {
+        let revoked: Vec<_> = revocations
+            .iter()
+            .map(|revocation| sequence(&[integer(&revocation.serial), time(revocation.revoked_at)]))
+            .collect();
+        tbs.push(sequence(&revoked));
+    }
+    let tbs = sequence(&tbs);
+
+    let mut signer = Signer::new(MessageDigest::sha256(), ca_key).map_err(|e| e.to_string())?;
+    let signature = signer
+        .sign_oneshot_to_vec(&tbs)
+        .map_err(|e| e.to_string())?;
+    // BIT STRING without unused bits
+    let signature = der(0x03, &[&[0x00], signature.as_slice()].concat());
+
+    Ok(sequence(&[tbs, algorithm, signature]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::sample_ca;
+    use openssl::x509::X509Crl;
+
+    #[test]
+    fn crl() {
+        let (ca_cert, ca_key) = sample_ca();
+        let revocations = vec![
+            Revocation {
+                serial: vec![0x80, 0x01],
+                device_id: vec![0x01],
+                revoked_at: 1_700_000_000,
+                superseded: false,
+            },
+            Revocation {
+                serial: vec![0x7f; 16],
+                device_id: vec![0x02],
+                revoked_at: 1_700_000_100,
+                superseded: true,
+            },
+        ];
+        let crl = build_crl(&revocations, 1_700_000_200, &ca_cert, &ca_key).unwrap();
+        let crl = X509Crl::from_der(&crl).unwrap();
+
+        assert!(crl.verify(&ca_key).unwrap());
+        assert_eq!(
+            crl.issuer_name().to_der().unwrap(),
+            ca_cert.subject_name().to_der().unwrap()
+        );
+        let revoked: Vec<_> = crl
+            .get_revoked()
+            .unwrap()
+            .iter()
+            .map(|revoked| revoked.serial_number().to_bn().unwrap().to_vec())
+            .collect();
+        assert_eq!(revoked, vec![vec![0x80, 0x01], vec![0x7f; 16]]);
+
+        let empty = build_crl(&[], 1_700_000_200, &ca_cert, &ca_key).unwrap();
+        let empty = X509Crl::from_der(&empty).unwrap();
+        assert!(empty.verify(&ca_key).unwrap());
+        assert!(empty.get_revoked().is_none());
+    }
+
+    #[test]
+    fn serial() {
+        let (ca_cert, _) = sample_ca();
+        assert_eq!(
+            certificate_serial(&ca_cert.to_der().unwrap()).unwrap(),
+            vec![0x12, 0x34]
+        );
+        assert!(certificate_serial(&[0x30, 0x00]).is_err());
+    }
+}
@@ -1,41 +1,145 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
 
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
+use sha2::Digest;
 use uuid::Uuid;
 
+use crate::audit::{self, Event};
+use crate::communicator::Selection;
+use crate::config;
 use crate::device::Device;
 use crate::group::Group;
-use crate::interfaces::grpc::format_task;
+use crate::interfaces::grpc::{cert_to_id, format_result, format_task};
+use crate::metrics;
 use crate::proto::{KeyType, ProtocolType};
+use crate::revocation::{self, Revocation};
+use crate::storage::{Storage, TaskRecord};
+use crate::subscription::{Subscriber, Subscriptions, Updates, FEED_IDLE_TIMEOUT};
 use crate::tasks::decrypt::DecryptTask;
 use crate::tasks::group::GroupTask;
 use crate::tasks::sign::SignTask;
 use crate::tasks::sign_pdf::SignPDFTask;
-use crate::tasks::{Task, TaskResult, TaskStatus};
-use crate::utils;
+use crate::tasks::transcript::FaultKind;
+use crate::tasks::{SharedTask, Task, TaskResult, TaskSnapshot, TaskStatus};
+use crate::webhook;
+use crate::{get_timestamp, utils, CA_CERT, CA_KEY};
 use tokio::sync::mpsc::Sender;
 use tonic::codegen::Arc;
 use tonic::Status;
 
+/// Channel used to push the result of a task to a requester watching it
+pub type Watcher = Sender<Result<crate::proto::TaskResult, Status>>;
+
+/// Shared server state
+///
+/// The state is meant to be shared as `Arc<State>`. Device and group registries are
+/// guarded by read-write locks and each task by its own mutex, so operations on
+/// different tasks do not block each other. Locks are taken in the order device
+/// registry, task, group registry: removing a device locks tasks while the device
+/// registry is locked and establishing a group locks the group registry while its task
+/// is locked, never the other way around. Other registry and subscriber locks are never
+/// held while acquiring a task lock.
+///
+/// Methods which lock tasks or access storage may block (task processing, storage
+/// access), so they should be called outside of the async runtime, e.g. via
+/// `tokio::task::spawn_blocking`. Lookups in the registries, such as `get_device` or
+/// `is_revoked`, only hold their locks briefly and may be called from the runtime.
 pub struct State {
-    devices: HashMap<Vec<u8>, Arc<Device>>,
-    groups: HashMap<Vec<u8>, Group>,
-    tasks: HashMap<Uuid, Box<dyn Task + Send + Sync>>,
-    subscribers: HashMap<Vec<u8>, Sender<Result<crate::proto::Task, Status>>>,
+    devices: RwLock<HashMap<Vec<u8>, Arc<Device>>>,
+    groups: RwLock<HashMap<Vec<u8>, Group>>,
+    tasks: RwLock<HashMap<Uuid, SharedTask>>,
+    subscribers: Subscriptions<Vec<u8>>,
+    /// Requesters who created tasks, by task identifiers
+    requesters: RwLock<HashMap<Uuid, String>>,
+    /// Subscriptions of requesters to updates of the tasks they created
+    requester_subscribers: Subscriptions<String>,
+    /// Requesters waiting for unfinished tasks to end
+    watchers: Mutex<HashMap<Uuid, Vec<Watcher>>>,
+    /// Revoked device certificates by their serial numbers
+    revocations: RwLock<HashMap<Vec<u8>, Revocation>>,
+    /// Timestamp of the last CRL publication
+    crl_published: AtomicU64,
+    /// Numbers of faults attributed to devices since the server started
+    device_faults: Mutex<HashMap<Vec<u8>, HashMap<FaultKind, u64>>>,
+    storage: Box<dyn Storage>,
 }
 
 impl State {
-    pub fn new() -> Self {
-        State {
-            devices: HashMap::new(),
-            groups: HashMap::new(),
-            tasks: HashMap::new(),
-            subscribers: HashMap::new(),
+    /// Constructs a new State with devices, groups and tasks restored from `storage`
+    pub fn new(storage: Box<dyn Storage>) -> Result<Self, String> {
+        let devices: HashMap<_, _> = storage
+            .get_devices()?
+            .into_iter()
+            .map(|device| (device.identifier().to_vec(), Arc::new(device)))
+            .collect();
+        let groups: HashMap<_, _> = storage
+            .get_groups(&devices)?
+            .into_iter()
+            .map(|group| (group.identifier().to_vec(), group))
+            .collect();
+        info!(
+            "Loaded {} devices and {} groups from storage",
+            devices.len(),
+            groups.len()
+        );
+
+        let mut tasks = HashMap::new();
+        let mut requesters = HashMap::new();
+        for mut record in storage.get_tasks()? {
+            let restored = record
+                .snapshot
+                .as_deref()
+                .ok_or_else(|| String::from("Missing task snapshot"))
+                .and_then(TaskSnapshot::from_bytes)
+                .and_then(|snapshot| snapshot.restore(&record.request, &devices, &groups));
+            match restored {
+                Ok(task) => {
+                    tasks.insert(record.id, Arc::new(Mutex::new(task)));
+                    if let Some(requester) = record.requester {
+                        requesters.insert(record.id, requester);
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "Could not restore task task_id={} error={}",
+                        utils::hextrunc(record.id.as_bytes()),
+                        e
+                    );
+                    if matches!(record.status, TaskStatus::Created | TaskStatus::Running(_)) {
+                        record.status =
+                            TaskStatus::Failed("Task interrupted by server restart".into());
+                        storage.store_task(&record)?;
+                    }
+                }
+            }
         }
+        info!("Restored {} tasks from storage", tasks.len());
+
+        let revocations: HashMap<_, _> = storage
+            .get_revocations()?
+            .into_iter()
+            .map(|revocation| (revocation.serial.clone(), revocation))
+            .collect();
+
+        Ok(State {
+            devices: RwLock::new(devices),
+            groups: RwLock::new(groups),
+            tasks: RwLock::new(tasks),
+            subscribers: Subscriptions::new(),
+            requesters: RwLock::new(requesters),
+            requester_subscribers: Subscriptions::new(),
+            watchers: Mutex::new(HashMap::new()),
+            revocations: RwLock::new(revocations),
+            crl_published: AtomicU64::new(0),
+            device_faults: Mutex::new(HashMap::new()),
+            storage,
+        })
     }
 
-    pub fn add_device(&mut self, identifier: &[u8], name: &str, certificate: &[u8]) -> bool {
-        if name.chars().count() > 64
+    pub fn add_device(&self, identifier: &[u8], name: &str, certificate: &[u8]) -> bool {
+        if name.chars().count() > config::get().max_name_length
             || name
                 .chars()
                 .any(|x| x.is_ascii_punctuation() || x.is_control())
@@ -45,27 +149,41 @@ impl State {
         }
 
         let device = Device::new(identifier.to_vec(), name.to_owned(), certificate.to_vec());
+        let mut devices = self.devices.write().unwrap();
         // TODO improve when feature map_try_insert gets stabilized
-        if self.devices.contains_key(identifier) {
+        if devices.contains_key(identifier) {
             warn!(
                 "Device identifier already registered {}",
                 utils::hextrunc(identifier)
             );
             return false;
         }
-        self.devices.insert(identifier.to_vec(), Arc::new(device));
+        if let Err(e) = self.storage.add_device(&device) {
+            error!(
+                "Could not store device device_id={} error={}",
+                utils::hextrunc(identifier),
+                e
+            );
+            return false;
+        }
+        devices.insert(identifier.to_vec(), Arc::new(device));
+        audit::record(Event::DeviceRegistered {
+            device_id: hex::encode(identifier),
+            name: name.to_owned(),
+        });
         true
     }
 
     pub fn add_group_task(
-        &mut self,
+        &self,
         name: &str,
         devices: &[Vec<u8>],
         threshold: u32,
         protocol: ProtocolType,
         key_type: KeyType,
+        requester: Option<&str>,
     ) -> Option<Uuid> {
-        if name.chars().count() > 64
+        if name.chars().count() > config::get().max_name_length
             || name
                 .chars()
                 .any(|x| x.is_ascii_punctuation() || x.is_control())
@@ -74,28 +192,38 @@ impl State {
             return None;
         }
 
+        // the devices stay locked until the task is registered, so that none of them can be
+        // removed in the meantime
+        let registered = self.devices.read().unwrap();
         let mut device_list = Vec::new();
         for device in devices {
-            if !self.devices.contains_key(device.as_slice()) {
+            let Some(device) = registered.get(device.as_slice()) else {
                 warn!("Unknown Device ID {}", utils::hextrunc(device));
                 return None;
+            };
+            if self.is_device_revoked(device.identifier()) {
+                warn!("Revoked Device ID {}", utils::hextrunc(device.identifier()));
+                return None;
             }
-            device_list.push(self.devices.get(device.as_slice()).unwrap().clone());
+            device_list.push(device.clone());
         }
 
         let task = GroupTask::try_new(name, &device_list, threshold, protocol, key_type)
             .ok()
             .map(|task| Box::new(task) as Box<dyn Task + Send + Sync>);
 
-        let task_id = task.map(|task| self.add_task(task));
-        if let Some(task_id) = &task_id {
-            self.send_updates(task_id);
-        }
-        task_id
+        task.map(|task| self.add_task(task, requester))
     }
 
-    pub fn add_sign_task(&mut self, group_id: &[u8], name: &str, data: &[u8]) -> Option<Uuid> {
-        let group = self.groups.get(group_id);
+    pub fn add_sign_task(
+        &self,
+        group_id: &[u8],
+        name: &str,
+        data: &[u8],
+        selection: Selection,
+        requester: Option<&str>,
+    ) -> Option<Uuid> {
+        let group = self.get_group(group_id);
         if group.is_none() {
             warn!(
                 "Signing requested from an unknown group group_id={}",
@@ -106,12 +234,12 @@ impl State {
         let group = group.unwrap();
         let task = match group.key_type() {
             KeyType::SignPdf => {
-                SignPDFTask::try_new(group.clone(), name.to_string(), data.to_vec())
+                SignPDFTask::try_new(group, name.to_string(), data.to_vec(), selection)
                     .ok()
                     .map(|task| Box::new(task) as Box<dyn Task + Sync + Send>)
             }
             KeyType::SignChallenge => {
-                SignTask::try_new(group.clone(), name.to_string(), data.to_vec())
+                SignTask::try_new(group, name.to_string(), data.to_vec(), selection)
                     .ok()
                     .map(|task| Box::new(task) as Box<dyn Task + Sync + Send>)
             }
@@ -124,21 +252,19 @@ impl State {
             }
         };
 
-        let task_id = task.map(|task| self.add_task(task));
-        if let Some(task_id) = &task_id {
-            self.send_updates(task_id);
-        }
-        task_id
+        task.map(|task| self.add_task(task, requester))
     }
 
     pub fn add_decrypt_task(
-        &mut self,
+        &self,
         group_id: &[u8],
         name: &str,
         data: &[u8],
         data_type: &str,
+        selection: Selection,
+        requester: Option<&str>,
     ) -> Option<Uuid> {
-        let group = self.groups.get(group_id);
+        let group = self.get_group(group_id);
         if group.is_none() {
             warn!(
                 "Decryption requested from an unknown group group_id={}",
@@ -148,12 +274,14 @@ impl State {
         }
         let group = group.unwrap();
         let task = match group.key_type() {
-            KeyType::Decrypt => Some(DecryptTask::new(
-                group.clone(),
+            KeyType::Decrypt => DecryptTask::try_new(
+                group,
                 name.to_string(),
                 data.to_vec(),
                 data_type.to_string(),
-            ))
+                selection,
+            )
+            .ok()
             .map(|task| Box::new(task) as Box<dyn Task + Sync + Send>),
             KeyType::SignPdf | KeyType::SignChallenge => {
                 warn!(
@@ -164,29 +292,57 @@ impl State {
             }
         };
 
-        let task_id = task.map(|task| self.add_task(task));
-        if let Some(task_id) = &task_id {
-            self.send_updates(task_id);
-        }
-        task_id
+        task.map(|task| self.add_task(task, requester))
     }
 
-    fn add_task(&mut self, task: Box<dyn Task + Sync + Send>) -> Uuid {
+    /// Register a new task requested by `requester`, who can then subscribe to its updates
+    fn add_task(&self, task: Box<dyn Task + Sync + Send>, requester: Option<&str>) -> Uuid {
         let uuid = Uuid::new_v4();
-        self.tasks.insert(uuid, task);
+        let task = Arc::new(Mutex::new(task));
+        let guard = task.lock().unwrap();
+        self.tasks.write().unwrap().insert(uuid, task.clone());
+        if let Some(requester) = requester {
+            self.requesters
+                .write()
+                .unwrap()
+                .insert(uuid, requester.to_string());
+        }
+        self.persist_task(&uuid, guard.as_ref());
+        webhook::task_created(&uuid, guard.as_ref());
+        self.send_updates(&uuid, guard.as_ref());
         uuid
     }
 
-    pub fn get_device_tasks(&self, device: &[u8]) -> Vec<(Uuid, &dyn Task)> {
+    pub fn get_task_requester(&self, task_id: &Uuid) -> Option<String> {
+        self.requesters.read().unwrap().get(task_id).cloned()
+    }
+
+    fn persist_task(&self, task_id: &Uuid, task: &dyn Task) {
+        let requester = self.get_task_requester(task_id);
+        let record = TaskRecord::new(task_id, task, requester.as_deref());
+        if let Err(e) = self.storage.store_task(&record) {
+            error!(
+                "Could not store task task_id={} error={}",
+                utils::hextrunc(task_id.as_bytes()),
+                e
+            );
+        }
+    }
+
+    pub fn get_device_tasks(&self, device: &[u8]) -> Vec<(Uuid, SharedTask)> {
         let mut tasks = Vec::new();
-        for (uuid, task) in self.tasks.iter() {
+        for (uuid, task) in self.get_tasks() {
+            let guard = task.lock().unwrap();
             // TODO refactor
-            if task.has_device(device)
-                && (task.get_status() != TaskStatus::Finished
-                    || (task.get_status() == TaskStatus::Finished
-                        && !task.device_acknowledged(device)))
-            {
-                tasks.push((*uuid, task.as_ref() as &dyn Task));
+            let hidden = match guard.get_status() {
+                TaskStatus::Finished | TaskStatus::Expired | TaskStatus::Cancelled => {
+                    guard.device_acknowledged(device)
+                }
+                _ => false,
+            };
+            if guard.has_device(device) && !hidden {
+                drop(guard);
+                tasks.push((uuid, task));
             }
         }
         tasks
@@ -194,7 +350,7 @@ impl State {
 
     pub fn get_device_groups(&self, device: &[u8]) -> Vec<Group> {
         let mut groups = Vec::new();
-        for group in self.groups.values() {
+        for group in self.groups.read().unwrap().values() {
             if group.contains(device) {
                 groups.push(group.clone());
             }
@@ -202,26 +358,53 @@ impl State {
         groups
     }
 
-    pub fn get_groups(&self) -> &HashMap<Vec<u8>, Group> {
-        &self.groups
+    pub fn get_group(&self, group_id: &[u8]) -> Option<Group> {
+        self.groups.read().unwrap().get(group_id).cloned()
     }
 
-    pub fn get_tasks(&self) -> &HashMap<Uuid, Box<dyn Task + Send + Sync>> {
-        &self.tasks
+    pub fn get_groups(&self) -> Vec<Group> {
+        self.groups.read().unwrap().values().cloned().collect()
     }
 
-    pub fn get_task(&self, task: &Uuid) -> Option<&dyn Task> {
-        self.tasks.get(task).map(|task| task.as_ref() as &dyn Task)
+    /// Delete a group; tasks which have already been created for it are not affected
+    pub fn remove_group(&self, group_id: &[u8]) -> Result<(), String> {
+        let mut groups = self.groups.write().unwrap();
+        if !groups.contains_key(group_id) {
+            return Err("Unknown group".to_string());
+        }
+        self.storage.remove_group(group_id)?;
+        groups.remove(group_id);
+        info!("Group removed group_id={}", utils::hextrunc(group_id));
+        Ok(())
+    }
+
+    pub fn get_tasks(&self) -> Vec<(Uuid, SharedTask)> {
+        self.tasks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(uuid, task)| (*uuid, task.clone()))
+            .collect()
+    }
+
+    pub fn get_task(&self, task_id: &Uuid) -> Option<SharedTask> {
+        self.tasks.read().unwrap().get(task_id).cloned()
     }
 
     pub fn update_task(
-        &mut self,
+        &self,
         task_id: &Uuid,
         device: &[u8],
-        data: &[u8],
+        data: &[Vec<u8>],
         attempt: u32,
     ) -> Result<bool, String> {
-        let task = self.tasks.get_mut(task_id).unwrap();
+        let task = self
+            .get_task(task_id)
+            .ok_or_else(|| "Unknown task".to_string())?;
+        let mut task = task.lock().unwrap();
+        if task.get_status().has_ended() {
+            return Err("Task has ended".to_string());
+        }
         if attempt != task.get_attempts() {
             warn!(
                 "Stale update discarded task_id={} device_id={} attempt={}",
@@ -233,24 +416,69 @@ impl State {
         }
 
         let previous_status = task.get_status();
+        let known_faults = task.get_transcript().faults().len();
         let update_result = task.update(device, data);
+        self.report_faults(task_id, task.as_ref(), known_faults);
+        audit_outcome(task_id, task.as_ref(), &previous_status);
+        webhook::task_changed(task_id, task.as_ref(), &previous_status);
         if previous_status != TaskStatus::Finished && task.get_status() == TaskStatus::Finished {
             // TODO join if statements once #![feature(let_chains)] gets stabilized
             if let TaskResult::GroupEstablished(group) = task.get_result().unwrap() {
-                self.groups.insert(group.identifier().to_vec(), group);
+                if let Err(e) = self.storage.add_group(&group) {
+                    error!(
+                        "Could not store group group_id={} error={}",
+                        utils::hextrunc(group.identifier()),
+                        e
+                    );
+                }
+                self.groups
+                    .write()
+                    .unwrap()
+                    .insert(group.identifier().to_vec(), group);
             }
         }
-        if let Ok(true) = update_result {
-            self.send_updates(task_id);
+        // a rejected update may still fail the task
+        let failed = update_result.is_err() && task.get_status() != previous_status;
+        if update_result.is_ok() || failed {
+            self.persist_task(task_id, task.as_ref());
+        }
+        if matches!(update_result, Ok(true)) || failed {
+            self.send_updates(task_id, task.as_ref());
         }
         update_result
     }
 
-    pub fn decide_task(&mut self, task_id: &Uuid, device: &[u8], decision: bool) -> bool {
-        let task = self.tasks.get_mut(task_id).unwrap();
+    pub fn decide_task(&self, task_id: &Uuid, device: &[u8], decision: bool) -> bool {
+        let Some(task) = self.get_task(task_id) else {
+            return false;
+        };
+        let mut task = task.lock().unwrap();
+        if matches!(
+            task.get_status(),
+            TaskStatus::Expired | TaskStatus::Cancelled
+        ) {
+            return false;
+        }
+        // only the first decision of each member is counted
+        if !task.has_device(device) || task.device_decided(device) {
+            return false;
+        }
+        metrics::task_decided(decision);
+        audit::record(Event::TaskDecided {
+            task_id: hex::encode(task_id.as_bytes()),
+            device_id: hex::encode(device),
+            accept: decision,
+        });
+        let previous_status = task.get_status();
         let change = task.decide(device, decision);
+        if change == Some(true) {
+            audit_protocol_started(task_id, task.as_ref());
+        }
+        audit_outcome(task_id, task.as_ref(), &previous_status);
+        webhook::task_changed(task_id, task.as_ref(), &previous_status);
+        self.persist_task(task_id, task.as_ref());
         if change.is_some() {
-            self.send_updates(task_id);
+            self.send_updates(task_id, task.as_ref());
             if change.unwrap() {
                 log::info!(
                     "Task approved task_id={}",
@@ -267,77 +495,1078 @@ impl State {
         false
     }
 
-    pub fn acknowledge_task(&mut self, task: &Uuid, device: &[u8]) {
-        let task = self.tasks.get_mut(task).unwrap();
-        task.acknowledge(device);
+    pub fn acknowledge_task(&self, task_id: &Uuid, device: &[u8]) {
+        if let Some(task) = self.get_task(task_id) {
+            let mut task = task.lock().unwrap();
+            task.acknowledge(device);
+            self.persist_task(task_id, task.as_ref());
+        }
+    }
+
+    pub fn get_device(&self, device_id: &[u8]) -> Option<Arc<Device>> {
+        self.devices.read().unwrap().get(device_id).cloned()
+    }
+
+    /// Remove a device which is neither a member of a group nor part of an unfinished task
+    pub fn remove_device(&self, device_id: &[u8]) -> Result<(), String> {
+        {
+            // no group task can take up the device while it is locked; tasks are checked
+            // before groups, as a group task establishes its group while the task is locked
+            let mut devices = self.devices.write().unwrap();
+            if !devices.contains_key(device_id) {
+                return Err("Unknown device".to_string());
+            }
+            let busy = self.get_tasks().iter().any(|(_, task)| {
+                let task = task.lock().unwrap();
+                task.has_device(device_id) && !task.get_status().has_ended()
+            });
+            if busy {
+                return Err("Device is part of an unfinished task".to_string());
+            }
+            if !self.get_device_groups(device_id).is_empty() {
+                return Err("Device is a member of a group".to_string());
+            }
+            self.storage.remove_device(device_id)?;
+            devices.remove(device_id);
+        }
+        self.subscribers.remove_all(device_id);
+        info!("Device removed device_id={}", utils::hextrunc(device_id));
+        Ok(())
+    }
+
+    /// Replace the certificate of a registered device, keeping its identity and groups;
+    /// the previous certificate is revoked
+    pub fn renew_device(&self, device_id: &[u8], certificate: &[u8]) -> Result<(), String> {
+        let device = self
+            .get_device(device_id)
+            .ok_or_else(|| "Unknown device".to_string())?;
+        let superseded = Revocation {
+            serial: revocation::certificate_serial(&device.certificate())?,
+            device_id: device_id.to_vec(),
+            revoked_at: get_timestamp(),
+            superseded: true,
+        };
+        self.storage
+            .set_device_certificate(device_id, certificate)?;
+        device.set_certificate(certificate.to_vec());
+        {
+            let mut revocations = self.revocations.write().unwrap();
+            if !revocations.contains_key(&superseded.serial) {
+                self.storage.add_revocation(&superseded)?;
+                revocations.insert(superseded.serial.clone(), superseded);
+            }
+        }
+        audit::record(Event::CertificateRenewed {
+            device_id: hex::encode(device_id),
+        });
+        info!(
+            "Device certificate renewed device_id={}",
+            utils::hextrunc(device_id)
+        );
+        self.publish_crl();
+        Ok(())
     }
 
-    pub fn get_devices(&self) -> &HashMap<Vec<u8>, Arc<Device>> {
-        &self.devices
+    pub fn get_devices(&self) -> Vec<Arc<Device>> {
+        self.devices.read().unwrap().values().cloned().collect()
     }
 
     pub fn device_activated(&self, device_id: &[u8]) {
-        if let Some(device) = self.devices.get(device_id) {
+        if let Some(device) = self.devices.read().unwrap().get(device_id) {
             device.activated();
         } else {
             error!("Unknown Device ID {}", utils::hextrunc(device_id));
         }
     }
 
-    pub fn restart_task(&mut self, task_id: &Uuid) -> bool {
-        if self
-            .tasks
-            .get_mut(task_id)
-            .and_then(|task| task.restart().ok())
-            .unwrap_or(false)
-        {
-            self.send_updates(task_id);
+    pub fn restart_task(&self, task_id: &Uuid) -> bool {
+        let Some(task) = self.get_task(task_id) else {
+            return false;
+        };
+        let mut task = task.lock().unwrap();
+        let previous_status = task.get_status();
+        let known_faults = task.get_transcript().faults().len();
+        if task.restart().unwrap_or(false) {
+            self.report_faults(task_id, task.as_ref(), known_faults);
+            audit_protocol_started(task_id, task.as_ref());
+            audit_outcome(task_id, task.as_ref(), &previous_status);
+            webhook::task_changed(task_id, task.as_ref(), &previous_status);
+            self.persist_task(task_id, task.as_ref());
+            self.send_updates(task_id, task.as_ref());
             true
         } else {
             false
         }
     }
 
-    pub fn add_subscriber(
-        &mut self,
-        device_id: Vec<u8>,
-        tx: Sender<Result<crate::proto::Task, Status>>,
-    ) {
-        self.subscribers.insert(device_id, tx);
+    /// Count and audit the faults of `task` recorded after the first `known` ones
+    fn report_faults(&self, task_id: &Uuid, task: &dyn Task, known: usize) {
+        let faults = &task.get_transcript().faults()[known..];
+        if faults.is_empty() {
+            return;
+        }
+        let mut device_faults = self.device_faults.lock().unwrap();
+        for fault in faults {
+            info!(
+                "Fault recorded task_id={} device_id={} attempt={} round={} kind={}",
+                utils::hextrunc(task_id.as_bytes()),
+                fault
+                    .device_id
+                    .as_deref()
+                    .map_or_else(|| "none".into(), utils::hextrunc),
+                fault.attempt,
+                fault.round,
+                fault.kind.as_str()
+            );
+            if let Some(device_id) = &fault.device_id {
+                *device_faults
+                    .entry(device_id.clone())
+                    .or_default()
+                    .entry(fault.kind)
+                    .or_default() += 1;
+            }
+            metrics::device_fault(fault.kind);
+            audit::record(Event::DeviceFault {
+                task_id: hex::encode(task_id.as_bytes()),
+                device_id: fault.device_id.as_ref().map(hex::encode),
+                attempt: fault.attempt,
+                round: fault.round,
+                kind: fault.kind.as_str().into(),
+            });
+        }
+    }
+
+    /// Get the numbers of faults attributed to the device since the server started
+    pub fn get_device_faults(&self, device_id: &[u8]) -> HashMap<FaultKind, u64> {
+        self.device_faults
+            .lock()
+            .unwrap()
+            .get(device_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// End an unfinished task on behalf of the requester
+    pub fn cancel_task(&self, task_id: &Uuid) -> Result<(), String> {
+        let task = self
+            .get_task(task_id)
+            .ok_or_else(|| "Unknown task".to_string())?;
+        let mut task = task.lock().unwrap();
+        let previous_status = task.get_status();
+        if !task.terminate(TaskStatus::Cancelled) {
+            return Err("Task has already ended".to_string());
+        }
+        info!(
+            "Task cancelled task_id={}",
+            utils::hextrunc(task_id.as_bytes())
+        );
+        audit_outcome(task_id, task.as_ref(), &previous_status);
+        webhook::task_changed(task_id, task.as_ref(), &previous_status);
+        self.persist_task(task_id, task.as_ref());
+        self.send_updates(task_id, task.as_ref());
+        Ok(())
+    }
+
+    /// End an unfinished task which missed its deadline
+    pub fn expire_task(&self, task_id: &Uuid) -> bool {
+        let Some(task) = self.get_task(task_id) else {
+            return false;
+        };
+        let mut task = task.lock().unwrap();
+        let previous_status = task.get_status();
+        if !task.terminate(TaskStatus::Expired) {
+            return false;
+        }
+        info!(
+            "Task expired task_id={}",
+            utils::hextrunc(task_id.as_bytes())
+        );
+        audit_outcome(task_id, task.as_ref(), &previous_status);
+        webhook::task_changed(task_id, task.as_ref(), &previous_status);
+        self.persist_task(task_id, task.as_ref());
+        self.send_updates(task_id, task.as_ref());
+        true
     }
 
-    pub fn remove_subscriber(&mut self, device_id: &Vec<u8>) {
-        self.subscribers.remove(device_id);
+    /// Drop an ended task from memory; its record is either archived or deleted from storage
+    pub fn remove_task(&self, task_id: &Uuid, archive: bool) {
+        if self.tasks.write().unwrap().remove(task_id).is_none() {
+            return;
+        }
+        self.requesters.write().unwrap().remove(task_id);
+        let result = if archive {
+            self.storage.archive_task(task_id)
+        } else {
+            self.storage.delete_task(task_id)
+        };
+        if let Err(e) = result {
+            error!(
+                "Could not remove task task_id={} error={}",
+                utils::hextrunc(task_id.as_bytes()),
+                e
+            );
+        }
         debug!(
-            "Removing subscriber device_id={}",
-            utils::hextrunc(device_id)
+            "Removed task task_id={} archived={}",
+            utils::hextrunc(task_id.as_bytes()),
+            archive
         );
     }
 
-    pub fn get_subscribers(&self) -> &HashMap<Vec<u8>, Sender<Result<crate::proto::Task, Status>>> {
-        &self.subscribers
+    /// Revoke the certificate of a registered device and end its subscription
+    pub fn revoke_device(&self, device_id: &[u8]) -> Result<(), String> {
+        let certificate = self
+            .devices
+            .read()
+            .unwrap()
+            .get(device_id)
+            .map(|device| device.certificate())
+            .ok_or_else(|| "Unknown device".to_string())?;
+        let revocation = Revocation {
+            serial: revocation::certificate_serial(&certificate)?,
+            device_id: device_id.to_vec(),
+            revoked_at: get_timestamp(),
+            superseded: false,
+        };
+        {
+            let mut revocations = self.revocations.write().unwrap();
+            if revocations.contains_key(&revocation.serial) {
+                return Err("Device already revoked".to_string());
+            }
+            self.storage.add_revocation(&revocation)?;
+            revocations.insert(revocation.serial.clone(), revocation);
+        }
+        info!("Device revoked device_id={}", utils::hextrunc(device_id));
+        self.subscribers.remove_all(device_id);
+        self.publish_crl();
+        Ok(())
+    }
+
+    /// Check whether a DER-encoded certificate has been revoked, either by itself
+    /// or together with all other certificates of its device
+    pub fn is_revoked(&self, certificate: &[u8]) -> bool {
+        match revocation::certificate_serial(certificate) {
+            Ok(serial) => {
+                self.revocations.read().unwrap().contains_key(&serial)
+                    || self.is_device_revoked(&cert_to_id(certificate))
+            }
+            Err(_) => true,
+        }
+    }
+
+    pub fn is_device_revoked(&self, device_id: &[u8]) -> bool {
+        self.revocations
+            .read()
+            .unwrap()
+            .values()
+            .any(|revocation| !revocation.superseded && revocation.device_id == device_id)
+    }
+
+    /// Identifiers of revoked devices
+    pub fn get_revoked_devices(&self) -> Vec<Vec<u8>> {
+        let mut devices: Vec<_> = self
+            .revocations
+            .read()
+            .unwrap()
+            .values()
+            .filter(|revocation| !revocation.superseded)
+            .map(|revocation| revocation.device_id.clone())
+            .collect();
+        devices.sort();
+        devices.dedup();
+        devices
+    }
+
+    /// Write the CRL to the configured path, if any
+    pub fn publish_crl(&self) {
+        let Some(path) = &config::get().crl else {
+            return;
+        };
+        let timestamp = get_timestamp();
+        let mut revocations: Vec<_> = self.revocations.read().unwrap().values().cloned().collect();
+        revocations.sort_by_key(|revocation| revocation.revoked_at);
+        let result = revocation::build_crl(&revocations, timestamp, &CA_CERT, &CA_KEY)
+            .and_then(|crl| std::fs::write(path, crl).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => {
+                self.crl_published.store(timestamp, Ordering::Relaxed);
+                debug!("CRL published revocations={}", revocations.len());
+            }
+            Err(e) => error!("Could not publish CRL error={}", e),
+        }
+    }
+
+    /// Republish the CRL once a day so that it never gets past its next update
+    pub fn refresh_crl(&self) {
+        if get_timestamp().saturating_sub(self.crl_published.load(Ordering::Relaxed)) > 24 * 60 * 60
+        {
+            self.publish_crl();
+        }
+    }
+
+    /// Stream updates of the tasks of `device_id`, resuming after `resume_token` if given
+    #[allow(clippy::result_large_err)]
+    pub fn add_subscriber(
+        &self,
+        device_id: Vec<u8>,
+        resume_token: Option<&str>,
+    ) -> Result<Updates, Status> {
+        self.subscribers.subscribe(device_id, resume_token)
+    }
+
+    /// Remove the subscription of `device_id` which uses the channel of `tx`
+    pub fn remove_subscriber(&self, device_id: &[u8], tx: &Subscriber) {
+        self.subscribers.remove(device_id, tx);
+    }
+
+    pub fn is_subscribed(&self, device_id: &[u8]) -> bool {
+        self.subscribers.contains(device_id)
+    }
+
+    /// Subscriptions of devices, one entry per channel
+    pub fn get_subscribers(&self) -> Vec<(Vec<u8>, Subscriber)> {
+        self.subscribers.get()
+    }
+
+    /// Stream updates of the tasks created by `requester`, resuming after `resume_token`
+    /// if given
+    #[allow(clippy::result_large_err)]
+    pub fn add_requester_subscriber(
+        &self,
+        requester: String,
+        resume_token: Option<&str>,
+    ) -> Result<Updates, Status> {
+        self.requester_subscribers
+            .subscribe(requester, resume_token)
+    }
+
+    /// Remove the subscription of `requester` which uses the channel of `tx`
+    pub fn remove_requester_subscriber(&self, requester: &str, tx: &Subscriber) {
+        self.requester_subscribers.remove(requester, tx);
+    }
+
+    pub fn get_requester_subscribers(&self) -> Vec<(String, Subscriber)> {
+        self.requester_subscribers.get()
+    }
+
+    /// Drop the retained updates of devices and requesters which have not resumed their
+    /// subscriptions in time
+    pub fn remove_idle_subscriptions(&self) {
+        self.subscribers.remove_idle(FEED_IDLE_TIMEOUT);
+        self.requester_subscribers.remove_idle(FEED_IDLE_TIMEOUT);
+    }
+
+    /// Send the result of the task to `tx` once the task has ended
+    ///
+    /// # Returns
+    /// `false` if the task is unknown; `true` otherwise.
+    pub fn watch_task(&self, task_id: &Uuid, tx: Watcher) -> bool {
+        let Some(task) = self.get_task(task_id) else {
+            return false;
+        };
+        // the task lock is held so that the task cannot end before the watcher is stored
+        let task = task.lock().unwrap();
+        if task.get_status().has_ended() {
+            let _ = tx.try_send(Ok(format_result(task_id, task.as_ref())));
+        } else {
+            self.watchers
+                .lock()
+                .unwrap()
+                .entry(*task_id)
+                .or_default()
+                .push(tx);
+        }
+        true
     }
 
-    fn send_updates(&mut self, task_id: &Uuid) {
-        let task = self.get_task(task_id).unwrap();
-        let mut remove = Vec::new();
+    fn send_updates(&self, task_id: &Uuid, task: &dyn Task) {
+        if task.get_status().has_ended() {
+            if let Some(watchers) = self.watchers.lock().unwrap().remove(task_id) {
+                let result = format_result(task_id, task);
+                for tx in watchers {
+                    let _ = tx.try_send(Ok(result.clone()));
+                }
+            }
+        }
 
         for device_id in task.get_devices().iter().map(|device| device.identifier()) {
-            if let Some(tx) = self.subscribers.get(device_id) {
-                let result = tx.try_send(Ok(format_task(task_id, task, Some(device_id), None)));
+            self.subscribers.send(device_id, || {
+                format_task(task_id, task, Some(device_id), None)
+            });
+        }
+        if let Some(requester) = self.get_task_requester(task_id) {
+            self.requester_subscribers
+                .send(&requester, || format_task(task_id, task, None, None));
+        }
+    }
+}
 
-                if result.is_err() {
-                    debug!(
-                        "Closed channel detected device_id={}…",
-                        utils::hextrunc(&device_id[..4])
-                    );
-                    remove.push(device_id.to_vec());
+fn audit_protocol_started(task_id: &Uuid, task: &dyn Task) {
+    audit::record(Event::ProtocolStarted {
+        task_id: hex::encode(task_id.as_bytes()),
+        attempt: task.get_attempts(),
+        active_devices: task
+            .get_active_devices()
+            .unwrap_or_default()
+            .iter()
+            .map(hex::encode)
+            .collect(),
+    });
+}
+
+/// Record the result of `task` if it has ended since it had `previous_status`
+fn audit_outcome(task_id: &Uuid, task: &dyn Task, previous_status: &TaskStatus) {
+    let status = task.get_status();
+    if previous_status.has_ended() || !status.has_ended() {
+        return;
+    }
+    let task_id = hex::encode(task_id.as_bytes());
+    let event = match status {
+        TaskStatus::Finished => {
+            let Some(result) = task.get_result() else {
+                return;
+            };
+            let (group_id, signature) = match &result {
+                TaskResult::GroupEstablished(group) => {
+                    (Some(hex::encode(group.identifier())), None)
                 }
+                TaskResult::Signed(signature) => (None, Some(hex::encode(signature))),
+                TaskResult::SignedPdf(_) | TaskResult::Decrypted(_) => (None, None),
+            };
+            Event::TaskFinished {
+                task_id,
+                group_id,
+                signature,
+                result_sha256: hex::encode(sha2::Sha256::digest(result.as_bytes())),
+            }
+        }
+        TaskStatus::Failed(reason) => Event::TaskFailed { task_id, reason },
+        TaskStatus::Expired => Event::TaskFailed {
+            task_id,
+            reason: "Task expired".into(),
+        },
+        _ => Event::TaskFailed {
+            task_id,
+            reason: "Task cancelled".into(),
+        },
+    };
+    audit::record(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use meesign_crypto::proto::{Message as _, ProtocolMessage};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// State with the devices `00`, `01`, ... registered
+    fn sample_state(devices: u8) -> State {
+        let state = State::new(Box::new(MemoryStorage::new())).unwrap();
+        for i in 0..devices {
+            assert!(state.add_device(&[i], &format!("d{}", i), &[0xf0 | i]));
+        }
+        state
+    }
+
+    /// Stores and registers the group `aa` of `devices`
+    fn add_sample_group(
+        state: &State,
+        mut devices: Vec<Arc<Device>>,
+        threshold: u32,
+        key_type: KeyType,
+    ) {
+        devices.sort_by_key(|device| device.identifier().to_vec());
+        let group = Group::new(
+            vec![0xaa],
+            String::from("Sample Group"),
+            devices,
+            threshold,
+            ProtocolType::Gg18,
+            key_type,
+            None,
+        );
+        state.storage.add_group(&group).unwrap();
+        state
+            .groups
+            .write()
+            .unwrap()
+            .insert(group.identifier().to_vec(), group);
+    }
+
+    #[test]
+    fn load_from_storage() {
+        let storage = MemoryStorage::new();
+        storage
+            .add_device(&Device::new(vec![0x01], String::from("d1"), vec![0xf1]))
+            .unwrap();
+        storage
+            .store_task(&TaskRecord {
+                id: Uuid::new_v4(),
+                task_type: crate::proto::TaskType::SignChallenge,
+                status: TaskStatus::Running(2),
+                attempts: 0,
+                last_update: 0,
+                request: vec![],
+                result: None,
+                snapshot: None,
+                requester: None,
+            })
+            .unwrap();
+
+        let state = State::new(Box::new(storage)).unwrap();
+        assert_eq!(state.get_devices().len(), 1);
+        assert_eq!(state.get_devices()[0].identifier(), &[0x01]);
+        let tasks = state.storage.get_tasks().unwrap();
+        assert!(matches!(tasks[0].status, TaskStatus::Failed(_)));
+    }
+
+    #[test]
+    fn restore_tasks() {
+        let state = sample_state(3);
+        add_sample_group(&state, state.get_devices(), 2, KeyType::SignChallenge);
+
+        let group_task = state
+            .add_group_task(
+                "Second Group",
+                &[vec![0x00], vec![0x01], vec![0x02]],
+                2,
+                ProtocolType::Gg18,
+                KeyType::SignChallenge,
+                None,
+            )
+            .unwrap();
+        let created_task = state
+            .add_sign_task(&[0xaa], "Created", &[0x01], Selection::default(), None)
+            .unwrap();
+        let running_task = state
+            .add_sign_task(&[0xaa], "Running", &[0x02], Selection::default(), None)
+            .unwrap();
+        state.decide_task(&created_task, &[0x00], false);
+        for i in 0..3u8 {
+            state.decide_task(&group_task, &[i], true);
+        }
+        state.decide_task(&running_task, &[0x00], true);
+        state.decide_task(&running_task, &[0x02], true);
+        assert!(
+            state
+                .get_task(&running_task)
+                .unwrap()
+                .lock()
+                .unwrap()
+                .get_status()
+                == TaskStatus::Running(1)
+        );
+
+        let previous: HashMap<_, _> = state
+            .get_tasks()
+            .iter()
+            .map(|(task_id, task)| {
+                let task = task.lock().unwrap();
+                (*task_id, format_task(task_id, task.as_ref(), None, None))
+            })
+            .collect();
+        let work: Vec<_> = (0..3u8)
+            .map(|i| {
+                let task = state.get_task(&running_task).unwrap();
+                let work = task.lock().unwrap().get_work(Some(&[i]));
+                work
+            })
+            .collect();
+
+        let state = State::new(state.storage).unwrap();
+        assert_eq!(state.get_tasks().len(), 3);
+        for (task_id, task) in state.get_tasks() {
+            let task = task.lock().unwrap();
+            assert_eq!(
+                format_task(&task_id, task.as_ref(), None, None),
+                previous[&task_id]
+            );
+        }
+        for i in 0..3u8 {
+            assert_eq!(
+                state
+                    .get_task(&running_task)
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .get_work(Some(&[i])),
+                work[i as usize]
+            );
+        }
+    }
+
+    #[test]
+    fn add_device() {
+        let state = State::new(Box::new(MemoryStorage::new())).unwrap();
+        assert!(state.add_device(&[0x01], "d1", &[0xf1]));
+        assert!(!state.add_device(&[0x01], "d1", &[0xf1]));
+        assert!(!state.add_device(&[0x02], "d2!", &[0xf2]));
+        assert_eq!(state.storage.get_devices().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn cancel_and_expire() {
+        let state = sample_state(2);
+        add_sample_group(&state, state.get_devices(), 2, KeyType::SignChallenge);
+
+        let cancelled = state
+            .add_sign_task(&[0xaa], "Cancelled", &[0x01], Selection::default(), None)
+            .unwrap();
+        let expired = state
+            .add_sign_task(&[0xaa], "Expired", &[0x02], Selection::default(), None)
+            .unwrap();
+        assert!(state.cancel_task(&cancelled).is_ok());
+        assert!(state.cancel_task(&cancelled).is_err());
+        assert!(state.cancel_task(&Uuid::new_v4()).is_err());
+        assert!(state.expire_task(&expired));
+        assert!(!state.expire_task(&expired));
+
+        assert!(!state.decide_task(&cancelled, &[0x00], true));
+        assert!(state.update_task(&expired, &[0x00], &[], 0).is_err());
+        assert!(!state.restart_task(&expired));
+
+        let status = |state: &State, task_id: &Uuid| {
+            state
+                .get_task(task_id)
+                .unwrap()
+                .lock()
+                .unwrap()
+                .get_status()
+        };
+        assert!(status(&state, &cancelled) == TaskStatus::Cancelled);
+        assert!(status(&state, &expired) == TaskStatus::Expired);
+
+        let state = State::new(state.storage).unwrap();
+        assert!(status(&state, &cancelled) == TaskStatus::Cancelled);
+        assert!(status(&state, &expired) == TaskStatus::Expired);
+        assert_eq!(state.get_device_tasks(&[0x00]).len(), 2);
+        state.acknowledge_task(&cancelled, &[0x00]);
+        assert_eq!(state.get_device_tasks(&[0x00]).len(), 1);
+
+        state.remove_task(&cancelled, false);
+        assert!(state.get_task(&cancelled).is_none());
+        assert_eq!(state.storage.get_tasks().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failed_pdf_preparation() {
+        let state = sample_state(2);
+        // without a group certificate the document cannot be prepared for signing
+        add_sample_group(&state, state.get_devices(), 2, KeyType::SignPdf);
+
+        let task_id = state
+            .add_sign_task(
+                &[0xaa],
+                "document.pdf",
+                b"%PDF-1.7",
+                Selection::default(),
+                None,
+            )
+            .unwrap();
+        state.decide_task(&task_id, &[0x00], true);
+        state.decide_task(&task_id, &[0x01], true);
+        let task = state.get_task(&task_id).unwrap();
+        let task = task.lock().unwrap();
+        assert!(matches!(
+            task.get_status(),
+            TaskStatus::Failed(reason) if reason == "Task failed (missing group certificate)"
+        ));
+        assert!(task.get_status().has_ended());
+    }
+
+    #[test]
+    fn ignored_decisions() {
+        let state = sample_state(2);
+        add_sample_group(&state, state.get_devices(), 2, KeyType::SignChallenge);
+
+        let task_id = state
+            .add_sign_task(&[0xaa], "Task", &[0x01], Selection::default(), None)
+            .unwrap();
+        assert!(!state.decide_task(&task_id, &[0x00], true));
+        // a repeated vote and a vote of a non-member would decline the task if counted
+        assert!(!state.decide_task(&task_id, &[0x00], false));
+        assert!(!state.decide_task(&task_id, &[0x09], false));
+        let task = state.get_task(&task_id).unwrap();
+        let task = task.lock().unwrap();
+        assert!(task.get_status() == TaskStatus::Created);
+        assert_eq!(task.get_decisions(), (1, 0));
+        assert!(task.device_decided(&[0x00]));
+        assert!(!task.device_decided(&[0x09]));
+    }
+
+    #[test]
+    fn weighted_device() {
+        let state = sample_state(2);
+        // the second device holds two of three shares, one short of the threshold
+        let mut devices = state.get_devices();
+        devices.sort_by_key(|device| device.identifier().to_vec());
+        devices.push(devices[1].clone());
+        add_sample_group(&state, devices, 3, KeyType::SignChallenge);
+
+        let task_id = state
+            .add_sign_task(&[0xaa], "Weighted", &[0x01], Selection::default(), None)
+            .unwrap();
+        let task = state.get_task(&task_id).unwrap();
+        assert_eq!(task.lock().unwrap().get_devices().len(), 2);
+        assert!(!state.decide_task(&task_id, &[0x01], true));
+        assert_eq!(task.lock().unwrap().get_decisions(), (2, 0));
+        assert!(state.decide_task(&task_id, &[0x00], true));
+        let task = task.lock().unwrap();
+        assert!(task.is_approved());
+        assert_eq!(task.get_decisions(), (3, 0));
+        assert_eq!(
+            task.get_active_devices(),
+            Some(vec![vec![0x00], vec![0x01], vec![0x01]])
+        );
+        assert_eq!(task.get_work(Some(&[0x01])).len(), 2);
+        assert_eq!(task.get_work(Some(&[0x00])).len(), 1);
+    }
+
+    #[test]
+    fn fault_attribution() {
+        let state = sample_state(3);
+        add_sample_group(&state, state.get_devices(), 2, KeyType::SignChallenge);
+
+        let task_id = state
+            .add_sign_task(&[0xaa], "Faulty", &[0x01], Selection::default(), None)
+            .unwrap();
+        state.decide_task(&task_id, &[0x00], true);
+        assert!(state.decide_task(&task_id, &[0x01], true));
+        state.decide_task(&task_id, &[0x02], true);
+        let attempt = state
+            .get_task(&task_id)
+            .unwrap()
+            .lock()
+            .unwrap()
+            .get_attempts();
+        let message = |parts: usize| {
+            ProtocolMessage {
+                protocol_type: ProtocolType::Gg18 as i32,
+                message: vec![vec![0x00; 64]; parts],
             }
+            .encode_to_vec()
+        };
+
+        // the first device stalls the protocol run
+        assert!(state
+            .update_task(&task_id, &[0x01], &[message(1)], attempt)
+            .is_ok());
+        assert!(state.restart_task(&task_id));
+        // the third device replaces it and sends messages for too many parties
+        assert!(state
+            .update_task(&task_id, &[0x02], &[message(2)], attempt + 1)
+            .is_err());
+        assert!(state
+            .update_task(&task_id, &[0x02], &[message(2)], attempt + 1)
+            .is_err());
+
+        let task = state.get_task(&task_id).unwrap();
+        let task = task.lock().unwrap();
+        assert!(matches!(task.get_status(), TaskStatus::Failed(_)));
+        let faults = task.get_transcript().faults();
+        assert_eq!(
+            faults
+                .iter()
+                .map(|fault| (fault.device_id.clone(), fault.attempt, fault.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(vec![0x00]), attempt, FaultKind::Stalled),
+                (Some(vec![0x02]), attempt + 1, FaultKind::MalformedMessage)
+            ]
+        );
+        assert!(faults.iter().all(|fault| fault.round == 1));
+        let messages = task.get_transcript().messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].device_id, vec![0x01]);
+        assert_eq!(task.get_excluded_devices(), vec![vec![0x00]]);
+
+        assert_eq!(
+            state.get_device_faults(&[0x00]),
+            HashMap::from([(FaultKind::Stalled, 1)])
+        );
+        assert_eq!(
+            state.get_device_faults(&[0x02]),
+            HashMap::from([(FaultKind::MalformedMessage, 1)])
+        );
+        assert!(state.get_device_faults(&[0x01]).is_empty());
+    }
+
+    #[test]
+    fn watch_task() {
+        let state = sample_state(2);
+        add_sample_group(&state, state.get_devices(), 2, KeyType::SignChallenge);
+        let task_id = state
+            .add_sign_task(&[0xaa], "Watched", &[0x01], Selection::default(), None)
+            .unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        assert!(!state.watch_task(&Uuid::new_v4(), tx.clone()));
+        assert!(state.watch_task(&task_id, tx));
+        assert!(rx.try_recv().is_err());
+        state.cancel_task(&task_id).unwrap();
+
+        let result = rx.try_recv().unwrap().unwrap();
+        assert_eq!(result.name, "Watched");
+        assert_eq!(result.group_id, Some(vec![0xaa]));
+        assert_eq!(
+            result.state,
+            crate::proto::task::TaskState::Cancelled as i32
+        );
+        assert!(result.result.is_none());
+        // the stream closes after the result
+        assert!(matches!(
+            rx.try_recv(),
+            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected)
+        ));
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        assert!(state.watch_task(&task_id, tx));
+        assert_eq!(rx.try_recv().unwrap().unwrap(), result);
+    }
+
+    #[tokio::test]
+    async fn requester_subscription() {
+        let state = sample_state(2);
+        add_sample_group(&state, state.get_devices(), 2, KeyType::SignChallenge);
+
+        let mut rx = state
+            .add_requester_subscriber(String::from("portal"), None)
+            .unwrap();
+        let own = state
+            .add_sign_task(
+                &[0xaa],
+                "Own",
+                &[0x01],
+                Selection::default(),
+                Some("portal"),
+            )
+            .unwrap();
+        let other = state
+            .add_sign_task(&[0xaa], "Other", &[0x02], Selection::default(), None)
+            .unwrap();
+        // the requester is notified of the creation of its task
+        let update = rx.recv().await.unwrap().unwrap();
+        assert_eq!(update.id, own.as_bytes().to_vec());
+        assert_eq!(update.sequence, 1);
+
+        state.cancel_task(&other).unwrap();
+        state.cancel_task(&own).unwrap();
+        // updates of tasks created by others are not streamed
+        let update = rx.recv().await.unwrap().unwrap();
+        assert_eq!(update.id, own.as_bytes().to_vec());
+        assert_eq!(update.sequence, 2);
+        assert_eq!(
+            update.state,
+            crate::proto::task::TaskState::Cancelled as i32
+        );
+
+        drop(rx);
+        for (requester, tx) in state.get_requester_subscribers() {
+            tx.closed().await;
+            state.remove_requester_subscriber(&requester, &tx);
         }
+        assert!(state.get_requester_subscribers().is_empty());
+
+        let state = State::new(state.storage).unwrap();
+        assert_eq!(state.get_task_requester(&own).as_deref(), Some("portal"));
+        assert_eq!(state.get_task_requester(&other), None);
+        state.remove_task(&own, false);
+        assert_eq!(state.get_task_requester(&own), None);
+    }
+
+    #[test]
+    fn revoke_device() {
+        let (certificate, _) = crate::certificate::tests::sample_ca();
+        let certificate = certificate.to_der().unwrap();
+        let state = State::new(Box::new(MemoryStorage::new())).unwrap();
+        assert!(state.add_device(&[0x01], "d1", &certificate));
+        assert!(state.add_device(&[0x02], "d2", &[0xf2]));
+        assert!(!state.is_revoked(&certificate));
+
+        assert!(state.revoke_device(&[0x01]).is_ok());
+        assert!(state.revoke_device(&[0x01]).is_err());
+        assert!(state.revoke_device(&[0x03]).is_err());
+        assert!(state.is_revoked(&certificate));
+        assert!(state
+            .add_group_task(
+                "Group",
+                &[vec![0x01], vec![0x02]],
+                2,
+                ProtocolType::Gg18,
+                KeyType::SignChallenge,
+                None
+            )
+            .is_none());
+
+        let state = State::new(state.storage).unwrap();
+        assert!(state.is_revoked(&certificate));
+    }
+
+    #[test]
+    fn renew_device() {
+        let state = State::new(Box::new(MemoryStorage::new())).unwrap();
+        let certificate = |serial| {
+            let (certificate, _) = crate::certificate::tests::sample_certificate(serial);
+            certificate.to_der().unwrap()
+        };
+        let (previous, renewed) = (certificate(0x01), certificate(0x02));
+        assert!(state.add_device(&[0x01], "d1", &previous));
+        assert!(state.add_device(&[0x02], "d2", &certificate(0x03)));
+        add_sample_group(&state, state.get_devices(), 2, KeyType::SignChallenge);
+
+        assert!(state.renew_device(&[0x01], &renewed).is_ok());
+        assert!(state.renew_device(&[0x03], &certificate(0x04)).is_err());
+        assert_eq!(state.get_device(&[0x01]).unwrap().certificate(), renewed);
+        // the previous certificate is revoked, the device is not
+        assert!(state.is_revoked(&previous));
+        assert!(!state.is_revoked(&renewed));
+        assert!(!state.is_device_revoked(&[0x01]));
+        assert!(state.get_revoked_devices().is_empty());
+
+        let state = State::new(state.storage).unwrap();
+        assert_eq!(state.get_device(&[0x01]).unwrap().certificate(), renewed);
+        assert_eq!(state.get_device_groups(&[0x01]).len(), 1);
+        assert!(state.is_revoked(&previous));
+        assert!(!state.is_revoked(&renewed));
+    }
 
-        for device_id in remove {
-            self.remove_subscriber(&device_id);
+    #[test]
+    fn remove_device_and_group() {
+        let state = State::new(Box::new(MemoryStorage::new())).unwrap();
+        for i in 1..=3u8 {
+            assert!(state.add_device(&[i], &format!("d{}", i), &[0xf0 | i]));
+        }
+        let devices = vec![
+            state.get_device(&[0x01]).unwrap(),
+            state.get_device(&[0x02]).unwrap(),
+        ];
+        add_sample_group(&state, devices, 2, KeyType::SignChallenge);
+        let state = State::new(state.storage).unwrap();
+
+        assert!(state.remove_device(&[0x01]).is_err());
+        assert!(state.remove_device(&[0x03]).is_ok());
+        assert!(state.remove_device(&[0x03]).is_err());
+        assert!(state.remove_group(&[0xaa]).is_ok());
+        assert!(state.remove_group(&[0xaa]).is_err());
+
+        state
+            .add_group_task(
+                "Another Group",
+                &[vec![0x01], vec![0x02]],
+                2,
+                ProtocolType::Gg18,
+                KeyType::SignChallenge,
+                None,
+            )
+            .unwrap();
+        assert!(state.remove_device(&[0x01]).is_err());
+
+        let state = State::new(state.storage).unwrap();
+        assert!(state.get_device(&[0x03]).is_none());
+        assert!(state.get_groups().is_empty());
+    }
+
+    /// Throughput of hundreds of concurrently running signing tasks while one task
+    /// is blocked (e.g. by a slow PDF helper), compared against a single global lock
+    ///
+    /// Run with `cargo test --release concurrent_tasks_throughput -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn concurrent_tasks_throughput() {
+        const TASKS: usize = 512;
+        const THREADS: usize = 8;
+        const STALL: Duration = Duration::from_millis(500);
+
+        let message = ProtocolMessage {
+            protocol_type: ProtocolType::Gg18 as i32,
+            message: vec![vec![0x00; 64]],
+        }
+        .encode_to_vec();
+
+        for global_lock in [true, false] {
+            let state = Arc::new(sample_state(2));
+            add_sample_group(&state, state.get_devices(), 2, KeyType::SignChallenge);
+            let tasks: Vec<_> = (0..TASKS)
+                .map(|i| {
+                    state
+                        .add_sign_task(
+                            &[0xaa],
+                            &format!("Task {}", i),
+                            &i.to_le_bytes(),
+                            Selection::default(),
+                            None,
+                        )
+                        .unwrap()
+                })
+                .collect();
+            let global = Arc::new(Mutex::new(()));
+
+            let stalled = state.get_task(&tasks[0]).unwrap();
+            let stall_global = global.clone();
+            let stall = thread::spawn(move || {
+                let _global = global_lock.then(|| stall_global.lock().unwrap());
+                let _task = stalled.lock().unwrap();
+                thread::sleep(STALL);
+            });
+            thread::sleep(Duration::from_millis(10));
+
+            let start = Instant::now();
+            let workers: Vec<_> = tasks[1..]
+                .chunks(TASKS / THREADS)
+                .map(|chunk| {
+                    let state = state.clone();
+                    let global = global.clone();
+                    let chunk = chunk.to_vec();
+                    let message = message.clone();
+                    thread::spawn(move || {
+                        let lock = || global_lock.then(|| global.lock().unwrap());
+                        for task_id in &chunk {
+                            for device in 0..2u8 {
+                                let _global = lock();
+                                state.decide_task(task_id, &[device], true);
+                            }
+                        }
+                        for _ in 0..10 {
+                            for task_id in &chunk {
+                                for device in 0..2u8 {
+                                    let _global = lock();
+                                    state
+                                        .update_task(
+                                            task_id,
+                                            &[device],
+                                            std::slice::from_ref(&message),
+                                            0,
+                                        )
+                                        .unwrap();
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+            let elapsed = start.elapsed();
+            stall.join().unwrap();
+
+            for task_id in &tasks[1..] {
+                let task = state.get_task(task_id).unwrap();
+                assert!(task.lock().unwrap().get_status() == TaskStatus::Finished);
+            }
+            println!(
+                "{}: {} tasks in {:?} ({:.0} tasks/s)",
+                if global_lock {
+                    "global lock"
+                } else {
+                    "per-task locks"
+                },
+                TASKS - 1,
+                elapsed,
+                (TASKS - 1) as f64 / elapsed.as_secs_f64()
+            );
         }
     }
 }
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tonic::codegen::Arc;
+use uuid::Uuid;
+
+use crate::device::Device;
+use crate::group::Group;
+use crate::revocation::Revocation;
+use crate::storage::{resolve_devices, Storage, TaskRecord};
+
+/// Non-persistent Storage keeping all records in memory
+#[derive(Default)]
+pub struct MemoryStorage {
+    devices: Mutex<Vec<Device>>,
+    groups: Mutex<Vec<Group>>,
+    tasks: Mutex<HashMap<Uuid, TaskRecord>>,
+    revocations: Mutex<Vec<Revocation>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn add_device(&self, device: &Device) -> Result<(), String> {
+        let mut devices = self.devices.lock().unwrap();
+        if devices
+            .iter()
+            .any(|stored| stored.identifier() == device.identifier())
+        {
+            return Err("Device already stored".into());
+        }
+        devices.push(Device::new(
+            device.identifier().to_vec(),
+            device.name().to_owned(),
+            device.certificate(),
+        ));
+        Ok(())
+    }
+
+    fn get_devices(&self) -> Result<Vec<Device>, String> {
+        Ok(self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|device| {
+                Device::new(
+                    device.identifier().to_vec(),
+                    device.name().to_owned(),
+                    device.certificate(),
+                )
+            })
+            .collect())
+    }
+
+    fn set_device_certificate(&self, device_id: &[u8], certificate: &[u8]) -> Result<(), String> {
+        let mut devices = self.devices.lock().unwrap();
+        let device = devices
+            .iter_mut()
+            .find(|device| device.identifier() == device_id)
+            .ok_or_else(|| "Unknown device".to_string())?;
+        device.set_certificate(certificate.to_vec());
+        Ok(())
+    }
+
+    fn remove_device(&self, device_id: &[u8]) -> Result<(), String> {
+        let mut devices = self.devices.lock().unwrap();
+        let count = devices.len();
+        devices.retain(|device| device.identifier() != device_id);
+        if devices.len() == count {
+            return Err("Unknown device".into());
+        }
+        Ok(())
+    }
+
+    fn add_group(&self, group: &Group) -> Result<(), String> {
+        let mut groups = self.groups.lock().unwrap();
+        if groups
+            .iter()
+            .any(|stored| stored.identifier() == group.identifier())
+        {
+            return Err("Group already stored".into());
+        }
+        groups.push(group.clone());
+        Ok(())
+    }
+
+    fn get_groups(&self, devices: &HashMap<Vec<u8>, Arc<Device>>) -> Result<Vec<Group>, String> {
+        self.groups
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|group| {
+                let members: Vec<_> = group
+                    .share_holders()
+                    .iter()
+                    .map(|device| device.identifier().to_vec())
+                    .collect();
+                Ok(Group::new(
+                    group.identifier().to_vec(),
+                    group.name().to_owned(),
+                    resolve_devices(devices, &members)?,
+                    group.threshold(),
+                    group.protocol(),
+                    group.key_type(),
+                    group.certificate().cloned(),
+                ))
+            })
+            .collect()
+    }
+
+    fn remove_group(&self, group_id: &[u8]) -> Result<(), String> {
+        let mut groups = self.groups.lock().unwrap();
+        let count = groups.len();
+        groups.retain(|group| group.identifier() != group_id);
+        if groups.len() == count {
+            return Err("Unknown group".into());
+        }
+        Ok(())
+    }
+
+    fn store_task(&self, task: &TaskRecord) -> Result<(), String> {
+        self.tasks.lock().unwrap().insert(task.id, task.clone());
+        Ok(())
+    }
+
+    fn get_tasks(&self) -> Result<Vec<TaskRecord>, String> {
+        Ok(self.tasks.lock().unwrap().values().cloned().collect())
+    }
+
+    fn archive_task(&self, id: &Uuid) -> Result<(), String> {
+        self.delete_task(id)
+    }
+
+    fn delete_task(&self, id: &Uuid) -> Result<(), String> {
+        self.tasks.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn add_revocation(&self, revocation: &Revocation) -> Result<(), String> {
+        let mut revocations = self.revocations.lock().unwrap();
+        if revocations
+            .iter()
+            .any(|stored| stored.serial == revocation.serial)
+        {
+            return Err("Certificate already revoked".into());
+        }
+        revocations.push(revocation.clone());
+        Ok(())
+    }
+
+    fn get_revocations(&self) -> Result<Vec<Revocation>, String> {
+        Ok(self.revocations.lock().unwrap().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{KeyType, ProtocolType, TaskType};
+    use crate::tasks::TaskStatus;
+
+    #[test]
+    fn devices_and_groups() {
+        let storage = MemoryStorage::new();
+        let device = Device::new(vec![0x01], String::from("d1"), vec![0xf1]);
+        storage.add_device(&device).unwrap();
+        storage
+            .add_device(&Device::new(vec![0x02], String::from("d2"), vec![0xf2]))
+            .unwrap();
+        assert!(storage.add_device(&device).is_err());
+
+        let devices: HashMap<_, _> = storage
+            .get_devices()
+            .unwrap()
+            .into_iter()
+            .map(|device| (device.identifier().to_vec(), Arc::new(device)))
+            .collect();
+        assert_eq!(devices.len(), 2);
+
+        let group = Group::new(
+            vec![0xaa],
+            String::from("Sample Group"),
+            devices.values().cloned().collect(),
+            2,
+            ProtocolType::Gg18,
+            KeyType::SignPdf,
+            Some(vec![0xcc]),
+        );
+        storage.add_group(&group).unwrap();
+        assert!(storage.add_group(&group).is_err());
+
+        let groups = storage.get_groups(&devices).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].identifier(), group.identifier());
+        assert_eq!(groups[0].devices().len(), 2);
+        assert_eq!(groups[0].certificate(), Some(&vec![0xcc]));
+        assert!(storage.get_groups(&HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn replace_task() {
+        let storage = MemoryStorage::new();
+        let mut record = TaskRecord {
+            id: Uuid::new_v4(),
+            task_type: TaskType::SignChallenge,
+            status: TaskStatus::Created,
+            attempts: 0,
+            last_update: 0,
+            request: vec![0x01],
+            result: None,
+            snapshot: None,
+            requester: None,
+        };
+        storage.store_task(&record).unwrap();
+        record.status = TaskStatus::Finished;
+        record.result = Some(vec![0x02]);
+        storage.store_task(&record).unwrap();
+        assert!(storage.get_tasks().unwrap() == vec![record]);
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use tonic::codegen::Arc;
+use uuid::Uuid;
+
+use crate::device::Device;
+use crate::group::Group;
+use crate::proto::TaskType;
+use crate::revocation::Revocation;
+use crate::tasks::{Task, TaskStatus};
+
+#[cfg(test)]
+pub mod memory;
+pub mod sqlite;
+
+/// Persisted summary of a Task
+#[derive(Clone, PartialEq)]
+pub struct TaskRecord {
+    pub id: Uuid,
+    pub task_type: TaskType,
+    pub status: TaskStatus,
+    pub attempts: u32,
+    pub last_update: u64,
+    pub request: Vec<u8>,
+    pub result: Option<Vec<u8>>,
+    /// Serialized `TaskSnapshot`; missing in records created before snapshots were introduced
+    pub snapshot: Option<Vec<u8>>,
+    /// Name of the requester who created the task, if requests are restricted
+    pub requester: Option<String>,
+}
+
+impl TaskRecord {
+    pub fn new(id: &Uuid, task: &dyn Task, requester: Option<&str>) -> Self {
+        TaskRecord {
+            id: *id,
+            task_type: task.get_type(),
+            status: task.get_status(),
+            attempts: task.get_attempts(),
+            last_update: task.last_update(),
+            request: task.get_request().to_vec(),
+            result: task.get_result().map(|result| result.as_bytes().to_vec()),
+            snapshot: Some(task.snapshot().to_bytes()),
+            requester: requester.map(String::from),
+        }
+    }
+}
+
+/// Persistent backend of the server State
+pub trait Storage: Send + Sync {
+    fn add_device(&self, device: &Device) -> Result<(), String>;
+    fn get_devices(&self) -> Result<Vec<Device>, String>;
+    /// Replace the certificate of a device after its renewal
+    fn set_device_certificate(&self, device_id: &[u8], certificate: &[u8]) -> Result<(), String>;
+    /// Delete a device which is not a member of any group
+    fn remove_device(&self, device_id: &[u8]) -> Result<(), String>;
+
+    fn add_group(&self, group: &Group) -> Result<(), String>;
+    /// Load all groups; group members are resolved from the given `devices`
+    fn get_groups(&self, devices: &HashMap<Vec<u8>, Arc<Device>>) -> Result<Vec<Group>, String>;
+    fn remove_group(&self, group_id: &[u8]) -> Result<(), String>;
+
+    /// Insert a new task record or replace the existing one with the same identifier
+    fn store_task(&self, task: &TaskRecord) -> Result<(), String>;
+    /// Load all task records which were neither archived nor deleted
+    fn get_tasks(&self) -> Result<Vec<TaskRecord>, String>;
+    /// Keep the task record for later inspection, but do not load it anymore
+    fn archive_task(&self, id: &Uuid) -> Result<(), String>;
+    fn delete_task(&self, id: &Uuid) -> Result<(), String>;
+
+    fn add_revocation(&self, revocation: &Revocation) -> Result<(), String>;
+    fn get_revocations(&self) -> Result<Vec<Revocation>, String>;
+}
+
+fn resolve_devices(
+    devices: &HashMap<Vec<u8>, Arc<Device>>,
+    identifiers: &[Vec<u8>],
+) -> Result<Vec<Arc<Device>>, String> {
+    identifiers
+        .iter()
+        .map(|identifier| {
+            devices
+                .get(identifier)
+                .cloned()
+                .ok_or_else(|| format!("Unknown group member {}", hex::encode(identifier)))
+        })
+        .collect()
+}
@@ -0,0 +1,637 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use log::info;
+use rusqlite::{params, Connection, Row};
+use tonic::codegen::Arc;
+use uuid::Uuid;
+
+use crate::device::Device;
+use crate::group::Group;
+use crate::proto::{KeyType, ProtocolType, TaskType};
+use crate::revocation::Revocation;
+use crate::storage::{resolve_devices, Storage, TaskRecord};
+use crate::tasks::TaskStatus;
+
+/// Schema migrations; the n-th item upgrades the schema from version n to n + 1
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE devices (
+        identifier BLOB PRIMARY KEY,
+        name TEXT NOT NULL,
+        certificate BLOB NOT NULL
+    );
+    CREATE TABLE groups (
+        identifier BLOB PRIMARY KEY,
+        name TEXT NOT NULL,
+        threshold INTEGER NOT NULL,
+        protocol INTEGER NOT NULL,
+        key_type INTEGER NOT NULL,
+        certificate BLOB
+    );
+    CREATE TABLE group_members (
+        group_id BLOB NOT NULL REFERENCES groups(identifier),
+        device_id BLOB NOT NULL REFERENCES devices(identifier),
+        position INTEGER NOT NULL,
+        PRIMARY KEY (group_id, device_id)
+    );
+    CREATE TABLE tasks (
+        identifier BLOB PRIMARY KEY,
+        task_type INTEGER NOT NULL,
+        state INTEGER NOT NULL,
+        round INTEGER NOT NULL,
+        error TEXT,
+        attempts INTEGER NOT NULL,
+        last_update INTEGER NOT NULL,
+        request BLOB NOT NULL,
+        result BLOB
+    );
+",
+    "
+    ALTER TABLE tasks ADD COLUMN snapshot BLOB;
+",
+    "
+    ALTER TABLE tasks ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
+",
+    "
+    CREATE TABLE revocations (
+        serial BLOB PRIMARY KEY,
+        device_id BLOB NOT NULL,
+        revoked_at INTEGER NOT NULL
+    );
+",
+    "
+    ALTER TABLE tasks ADD COLUMN requester TEXT;
+",
+    "
+    ALTER TABLE group_members ADD COLUMN shares INTEGER NOT NULL DEFAULT 1;
+",
+    "
+    ALTER TABLE revocations ADD COLUMN superseded INTEGER NOT NULL DEFAULT 0;
+",
+];
+
+/// Storage backed by an embedded SQLite database
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Opens (or creates) the database at `path` and migrates it to the current schema
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let mut connection = Connection::open(path).map_err(|e| e.to_string())?;
+        connection
+            .pragma_update(None, "foreign_keys", true)
+            .map_err(|e| e.to_string())?;
+        migrate(&mut connection).map_err(|e| e.to_string())?;
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", idx + 1)?;
+        transaction.commit()?;
+        info!("Database migrated to version {}", idx + 1);
+    }
+    Ok(())
+}
+
+fn encode_status(status: &TaskStatus) -> (i32, u16, Option<&str>) {
+    match status {
+        TaskStatus::Created => (0, 0, None),
+        TaskStatus::Running(round) => (1, *round, None),
+        TaskStatus::Finished => (2, u16::MAX, None),
+        TaskStatus::Failed(error) => (3, u16::MAX, Some(error)),
+        TaskStatus::Expired => (4, u16::MAX, None),
+        TaskStatus::Cancelled => (5, u16::MAX, None),
+    }
+}
+
+fn decode_status(state: i32, round: u16, error: Option<String>) -> rusqlite::Result<TaskStatus> {
+    match state {
+        0 => Ok(TaskStatus::Created),
+        1 => Ok(TaskStatus::Running(round)),
+        2 => Ok(TaskStatus::Finished),
+        3 => Ok(TaskStatus::Failed(error.unwrap_or_default())),
+        4 => Ok(TaskStatus::Expired),
+        5 => Ok(TaskStatus::Cancelled),
+        _ => Err(rusqlite::Error::IntegralValueOutOfRange(2, state.into())),
+    }
+}
+
+fn task_from_row(row: &Row) -> rusqlite::Result<TaskRecord> {
+    let id: Vec<u8> = row.get("identifier")?;
+    let task_type: i32 = row.get("task_type")?;
+    Ok(TaskRecord {
+        id: Uuid::from_slice(&id).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Blob, Box::new(e))
+        })?,
+        task_type: TaskType::try_from(task_type)
+            .map_err(|_| rusqlite::Error::IntegralValueOutOfRange(1, task_type.into()))?,
+        status: decode_status(row.get("state")?, row.get("round")?, row.get("error")?)?,
+        attempts: row.get("attempts")?,
+        last_update: row.get("last_update")?,
+        request: row.get("request")?,
+        result: row.get("result")?,
+        snapshot: row.get("snapshot")?,
+        requester: row.get("requester")?,
+    })
+}
+
+impl Storage for SqliteStorage {
+    fn add_device(&self, device: &Device) -> Result<(), String> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO devices (identifier, name, certificate) VALUES (?1, ?2, ?3)",
+                params![device.identifier(), device.name(), device.certificate()],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn get_devices(&self) -> Result<Vec<Device>, String> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT identifier, name, certificate FROM devices")
+            .map_err(|e| e.to_string())?;
+        let devices = statement
+            .query_map([], |row| {
+                Ok(Device::new(row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .and_then(Iterator::collect)
+            .map_err(|e| e.to_string());
+        devices
+    }
+
+    fn set_device_certificate(&self, device_id: &[u8], certificate: &[u8]) -> Result<(), String> {
+        let updated = self
+            .connection
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE devices SET certificate = ?2 WHERE identifier = ?1",
+                params![device_id, certificate],
+            )
+            .map_err(|e| e.to_string())?;
+        if updated == 0 {
+            return Err("Unknown device".into());
+        }
+        Ok(())
+    }
+
+    fn remove_device(&self, device_id: &[u8]) -> Result<(), String> {
+        let removed = self
+            .connection
+            .lock()
+            .unwrap()
+            .execute(
+                "DELETE FROM devices WHERE identifier = ?1",
+                params![device_id],
+            )
+            .map_err(|e| e.to_string())?;
+        if removed == 0 {
+            return Err("Unknown device".into());
+        }
+        Ok(())
+    }
+
+    fn add_group(&self, group: &Group) -> Result<(), String> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(|e| e.to_string())?;
+        transaction
+            .execute(
+                "INSERT INTO groups (identifier, name, threshold, protocol, key_type, certificate)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    group.identifier(),
+                    group.name(),
+                    group.threshold(),
+                    group.protocol() as i32,
+                    group.key_type() as i32,
+                    group.certificate(),
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        for (position, (device, shares)) in group.devices().iter().zip(group.shares()).enumerate() {
+            transaction
+                .execute(
+                    "INSERT INTO group_members (group_id, device_id, position, shares)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![group.identifier(), device.identifier(), position, shares],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+        transaction.commit().map_err(|e| e.to_string())
+    }
+
+    fn get_groups(&self, devices: &HashMap<Vec<u8>, Arc<Device>>) -> Result<Vec<Group>, String> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT identifier, name, threshold, protocol, key_type, certificate FROM groups",
+            )
+            .map_err(|e| e.to_string())?;
+        let mut members = connection
+            .prepare(
+                "SELECT device_id, shares FROM group_members WHERE group_id = ?1 ORDER BY position",
+            )
+            .map_err(|e| e.to_string())?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, Vec<u8>>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u32>(2)?,
+                    row.get::<_, i32>(3)?,
+                    row.get::<_, i32>(4)?,
+                    row.get::<_, Option<Vec<u8>>>(5)?,
+                ))
+            })
+            .and_then(Iterator::collect::<rusqlite::Result<Vec<_>>>)
+            .map_err(|e| e.to_string())?;
+
+        let mut groups = Vec::new();
+        for (identifier, name, threshold, protocol, key_type, certificate) in rows {
+            // members are listed once per key share they hold
+            let member_ids = members
+                .query_map([&identifier], |row| {
+                    Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, u32>(1)?))
+                })
+                .and_then(Iterator::collect::<rusqlite::Result<Vec<_>>>)
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .flat_map(|(device_id, shares)| (0..shares).map(move |_| device_id.clone()))
+                .collect::<Vec<_>>();
+            groups.push(Group::new(
+                identifier,
+                name,
+                resolve_devices(devices, &member_ids)?,
+                threshold,
+                ProtocolType::try_from(protocol).map_err(|e| e.to_string())?,
+                KeyType::try_from(key_type).map_err(|e| e.to_string())?,
+                certificate,
+            ));
+        }
+        Ok(groups)
+    }
+
+    fn remove_group(&self, group_id: &[u8]) -> Result<(), String> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(|e| e.to_string())?;
+        transaction
+            .execute(
+                "DELETE FROM group_members WHERE group_id = ?1",
+                params![group_id],
+            )
+            .map_err(|e| e.to_string())?;
+        let removed = transaction
+            .execute(
+                "DELETE FROM groups WHERE identifier = ?1",
+                params![group_id],
+            )
+            .map_err(|e| e.to_string())?;
+        if removed == 0 {
+            return Err("Unknown group".into());
+        }
+        transaction.commit().map_err(|e| e.to_string())
+    }
+
+    fn store_task(&self, task: &TaskRecord) -> Result<(), String> {
+        let (state, round, error) = encode_status(&task.status);
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO tasks (identifier, task_type, state, round, error, attempts,
+                                    last_update, request, result, snapshot, requester)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                 ON CONFLICT (identifier) DO UPDATE SET
+                    state = excluded.state,
+                    round = excluded.round,
+                    error = excluded.error,
+                    attempts = excluded.attempts,
+                    last_update = excluded.last_update,
+                    result = excluded.result,
+                    snapshot = excluded.snapshot",
+                params![
+                    task.id.as_bytes(),
+                    task.task_type as i32,
+                    state,
+                    round,
+                    error,
+                    task.attempts,
+                    task.last_update,
+                    task.request,
+                    task.result,
+                    task.snapshot,
+                    task.requester,
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn get_tasks(&self) -> Result<Vec<TaskRecord>, String> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT * FROM tasks WHERE archived = 0")
+            .map_err(|e| e.to_string())?;
+        let tasks = statement
+            .query_map([], task_from_row)
+            .and_then(Iterator::collect)
+            .map_err(|e| e.to_string());
+        tasks
+    }
+
+    fn add_revocation(&self, revocation: &Revocation) -> Result<(), String> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO revocations (serial, device_id, revoked_at, superseded) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    revocation.serial,
+                    revocation.device_id,
+                    revocation.revoked_at,
+                    revocation.superseded
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn get_revocations(&self) -> Result<Vec<Revocation>, String> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT serial, device_id, revoked_at, superseded FROM revocations ORDER BY revoked_at",
+            )
+            .map_err(|e| e.to_string())?;
+        let revocations = statement
+            .query_map([], |row| {
+                Ok(Revocation {
+                    serial: row.get(0)?,
+                    device_id: row.get(1)?,
+                    revoked_at: row.get(2)?,
+                    superseded: row.get(3)?,
+                })
+            })
+            .and_then(Iterator::collect)
+            .map_err(|e| e.to_string());
+        revocations
+    }
+
+    fn archive_task(&self, id: &Uuid) -> Result<(), String> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE tasks SET archived = 1, snapshot = NULL WHERE identifier = ?1",
+                params![id.as_bytes()],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn delete_task(&self, id: &Uuid) -> Result<(), String> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "DELETE FROM tasks WHERE identifier = ?1",
+                params![id.as_bytes()],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        let version: usize = storage
+            .connection
+            .lock()
+            .unwrap()
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn migrate_existing() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        {
+            let connection = Connection::open(file.path()).unwrap();
+            connection.execute_batch(MIGRATIONS[0]).unwrap();
+            connection.pragma_update(None, "user_version", 1).unwrap();
+            connection
+                .execute(
+                    "INSERT INTO tasks (identifier, task_type, state, round, attempts, last_update, request)
+                     VALUES (?1, 1, 2, 65535, 0, 0, x'00')",
+                    params![Uuid::nil().as_bytes()],
+                )
+                .unwrap();
+        }
+
+        let storage = SqliteStorage::open(file.path()).unwrap();
+        let tasks = storage.get_tasks().unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert!(tasks[0].task_type == TaskType::SignPdf);
+        assert!(tasks[0].status == TaskStatus::Finished);
+        assert!(tasks[0].snapshot.is_none());
+    }
+
+    #[test]
+    fn reopen() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let task = TaskRecord {
+            id: Uuid::new_v4(),
+            task_type: TaskType::Group,
+            status: TaskStatus::Failed(String::from("Task declined")),
+            attempts: 1,
+            last_update: 42,
+            request: vec![0x01, 0x02],
+            result: None,
+            snapshot: Some(vec![0x03]),
+            requester: Some(String::from("portal")),
+        };
+        {
+            let storage = SqliteStorage::open(file.path()).unwrap();
+            for i in 0..3u8 {
+                storage
+                    .add_device(&Device::new(vec![i], format!("d{}", i), vec![0xf0 | i]))
+                    .unwrap();
+            }
+            let devices: HashMap<_, _> = storage
+                .get_devices()
+                .unwrap()
+                .into_iter()
+                .map(|device| (device.identifier().to_vec(), Arc::new(device)))
+                .collect();
+            let mut members: Vec<_> = devices.values().cloned().collect();
+            members.push(devices[&vec![0x02]].clone());
+            members.sort_by_key(|device| device.identifier().to_vec());
+            storage
+                .add_group(&Group::new(
+                    vec![0xaa],
+                    String::from("Sample Group"),
+                    members,
+                    2,
+                    ProtocolType::Frost,
+                    KeyType::SignChallenge,
+                    None,
+                ))
+                .unwrap();
+            storage.store_task(&task).unwrap();
+        }
+
+        let storage = SqliteStorage::open(file.path()).unwrap();
+        let devices: HashMap<_, _> = storage
+            .get_devices()
+            .unwrap()
+            .into_iter()
+            .map(|device| (device.identifier().to_vec(), Arc::new(device)))
+            .collect();
+        assert_eq!(devices.len(), 3);
+        assert_eq!(devices[&vec![0x02]].name(), "d2");
+
+        let groups = storage.get_groups(&devices).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].threshold(), 2);
+        assert_eq!(groups[0].protocol(), ProtocolType::Frost);
+        assert_eq!(groups[0].key_type(), KeyType::SignChallenge);
+        assert_eq!(
+            groups[0]
+                .devices()
+                .iter()
+                .map(|device| device.identifier().to_vec())
+                .collect::<Vec<_>>(),
+            vec![vec![0x00], vec![0x01], vec![0x02]]
+        );
+        assert_eq!(groups[0].shares(), &[1, 1, 2]);
+
+        assert!(storage.get_tasks().unwrap() == vec![task]);
+
+        storage.set_device_certificate(&[0x01], &[0xe1]).unwrap();
+        assert!(storage.set_device_certificate(&[0x05], &[0xe5]).is_err());
+        let device = storage
+            .get_devices()
+            .unwrap()
+            .into_iter()
+            .find(|device| device.identifier() == [0x01])
+            .unwrap();
+        assert_eq!(device.certificate(), vec![0xe1]);
+    }
+
+    #[test]
+    fn archive_and_delete() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        let mut records = Vec::new();
+        for status in [
+            TaskStatus::Expired,
+            TaskStatus::Cancelled,
+            TaskStatus::Created,
+        ] {
+            let record = TaskRecord {
+                id: Uuid::new_v4(),
+                task_type: TaskType::Decrypt,
+                status,
+                attempts: 0,
+                last_update: 0,
+                request: vec![0x01],
+                result: None,
+                snapshot: Some(vec![0x02]),
+                requester: None,
+            };
+            storage.store_task(&record).unwrap();
+            records.push(record);
+        }
+        let mut tasks = storage.get_tasks().unwrap();
+        assert_eq!(tasks.len(), 3);
+        assert!(tasks.iter().any(|task| task.status == TaskStatus::Expired));
+        assert!(tasks
+            .iter()
+            .any(|task| task.status == TaskStatus::Cancelled));
+
+        storage.archive_task(&records[0].id).unwrap();
+        storage.delete_task(&records[1].id).unwrap();
+        tasks = storage.get_tasks().unwrap();
+        assert!(tasks == vec![records[2].clone()]);
+
+        let stored: usize = storage
+            .connection
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM tasks", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, 2);
+    }
+
+    #[test]
+    fn remove_device_and_group() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        for i in 0..3u8 {
+            storage
+                .add_device(&Device::new(vec![i], format!("d{}", i), vec![0xf0 | i]))
+                .unwrap();
+        }
+        let devices: HashMap<_, _> = storage
+            .get_devices()
+            .unwrap()
+            .into_iter()
+            .map(|device| (device.identifier().to_vec(), Arc::new(device)))
+            .collect();
+        let group = Group::new(
+            vec![0xaa],
+            String::from("Sample Group"),
+            vec![devices[&vec![0x00]].clone(), devices[&vec![0x01]].clone()],
+            2,
+            ProtocolType::Frost,
+            KeyType::SignChallenge,
+            None,
+        );
+        storage.add_group(&group).unwrap();
+
+        storage.remove_device(&[0x02]).unwrap();
+        assert!(storage.remove_device(&[0x02]).is_err());
+        storage.remove_group(&[0xaa]).unwrap();
+        assert!(storage.remove_group(&[0xaa]).is_err());
+        storage.remove_device(&[0x00]).unwrap();
+
+        assert_eq!(storage.get_devices().unwrap().len(), 1);
+        assert!(storage.get_groups(&devices).unwrap().is_empty());
+    }
+
+    #[test]
+    fn revocations() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let revocation = Revocation {
+            serial: vec![0x12, 0x34],
+            device_id: vec![0x01],
+            revoked_at: 42,
+            superseded: false,
+        };
+        {
+            let storage = SqliteStorage::open(file.path()).unwrap();
+            storage.add_revocation(&revocation).unwrap();
+            assert!(storage.add_revocation(&revocation).is_err());
+        }
+        let storage = SqliteStorage::open(file.path()).unwrap();
+        assert_eq!(storage.get_revocations().unwrap(), vec![revocation]);
+    }
+}
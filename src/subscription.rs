@@ -0,0 +1,473 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::debug;
+use prost::Message as _;
+use rand::Rng;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::watch;
+use tonic::Status;
+
+use crate::proto as msg;
+
+/// Number of recent updates retained in each feed for resuming subscriptions
+const FEED_HISTORY: usize = 256;
+
+/// Encoded size in bytes of the updates retained in each feed; the last update is
+/// retained regardless of its size
+const FEED_HISTORY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Time for which the feed of a key without subscriptions is kept for resuming
+pub const FEED_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Channel used to push task updates to a subscriber
+pub type Subscriber = Sender<Result<msg::Task, Status>>;
+
+/// Receiving end of a subscription
+pub type Updates = Receiver<Result<msg::Task, Status>>;
+
+/// Updates of a single device or requester, numbered by consecutive sequence numbers
+struct Feed {
+    inner: Mutex<FeedInner>,
+    /// Sequence number of the last update, watched by the forwarding tasks
+    last_sequence: watch::Sender<u64>,
+}
+
+#[derive(Default)]
+struct FeedInner {
+    last_sequence: u64,
+    /// Recent updates ordered by their sequence numbers
+    updates: VecDeque<msg::Task>,
+    /// Encoded size of `updates`
+    size: usize,
+    subscribers: Vec<Subscriber>,
+    /// Time since which the feed has no subscriptions
+    idle_since: Option<Instant>,
+    closed: bool,
+}
+
+impl Feed {
+    fn new() -> Self {
+        Feed {
+            inner: Mutex::new(FeedInner::default()),
+            last_sequence: watch::Sender::new(0),
+        }
+    }
+
+    /// Updates following the one with sequence number `cursor`
+    ///
+    /// # Returns
+    /// `Ok(None)` if the feed has been closed.
+    #[allow(clippy::result_large_err)]
+    fn since(&self, cursor: u64) -> Result<Option<Vec<msg::Task>>, Status> {
+        let inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return Ok(None);
+        }
+        let oldest = inner.last_sequence + 1 - inner.updates.len() as u64;
+        if cursor + 1 < oldest {
+            return Err(Status::out_of_range(
+                "Updates since the resume token are no longer available",
+            ));
+        }
+        Ok(Some(
+            inner
+                .updates
+                .iter()
+                .skip((cursor + 1 - oldest) as usize)
+                .cloned()
+                .collect(),
+        ))
+    }
+}
+
+/// Push the updates of `feed` following `cursor` to `tx`, waiting for the subscriber
+/// to receive each of them so that slow subscribers do not lose updates
+async fn forward(feed: Arc<Feed>, mut cursor: u64, tx: Subscriber) {
+    let mut changed = feed.last_sequence.subscribe();
+    loop {
+        match feed.since(cursor) {
+            Ok(Some(updates)) => {
+                for update in updates {
+                    cursor = update.sequence;
+                    if tx.send(Ok(update)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Ok(None) => return,
+            Err(status) => {
+                // the subscriber fell behind the retained history
+                let _ = tx.send(Err(status)).await;
+                return;
+            }
+        }
+        tokio::select! {
+            result = changed.changed() => if result.is_err() {
+                return;
+            },
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+/// Subscriptions to task updates keyed by device identifiers or requester names
+///
+/// Each key has a feed of updates with sequence numbers. A key may hold several
+/// subscriptions at once, e.g. when a device reconnects before its previous stream
+/// is detected closed; each of them receives all updates in order. Recent updates are
+/// retained, so that a reconnecting subscriber can resume after the last update it saw.
+pub struct Subscriptions<K> {
+    feeds: Mutex<HashMap<K, Arc<Feed>>>,
+    /// Random identifier of this server run, included in the resume tokens
+    epoch: u64,
+}
+
+impl<K: Clone + Debug + Eq + Hash> Subscriptions<K> {
+    pub fn new() -> Self {
+        Subscriptions {
+            feeds: Mutex::new(HashMap::new()),
+            epoch: rand::thread_rng().gen(),
+        }
+    }
+
+    fn resume_token(&self, sequence: u64) -> String {
+        format!("{:016x}{:016x}", self.epoch, sequence)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn parse_resume_token(&self, token: &str) -> Result<u64, Status> {
+        let parse = |part: Option<&str>| part.and_then(|part| u64::from_str_radix(part, 16).ok());
+        match (parse(token.get(..16)), parse(token.get(16..))) {
+            (Some(epoch), Some(sequence)) if token.len() == 32 && epoch == self.epoch => {
+                Ok(sequence)
+            }
+            (Some(_), Some(_)) if token.len() == 32 => Err(Status::out_of_range(
+                "Updates since the resume token are no longer available",
+            )),
+            _ => Err(Status::invalid_argument("Invalid resume token")),
+        }
+    }
+
+    /// Subscribe to the updates of `key`
+    ///
+    /// Without a `resume_token`, only new updates are streamed; otherwise the stream
+    /// starts after the update which carried the token. Must be called within the runtime.
+    #[allow(clippy::result_large_err)]
+    pub fn subscribe(&self, key: K, resume_token: Option<&str>) -> Result<Updates, Status> {
+        let feed = self
+            .feeds
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(Feed::new()))
+            .clone();
+
+        let (tx, rx) = mpsc::channel(8);
+        let cursor = {
+            let mut inner = feed.inner.lock().unwrap();
+            let cursor = match resume_token {
+                Some(token) => self.parse_resume_token(token)?,
+                None => inner.last_sequence,
+            };
+            if cursor > inner.last_sequence {
+                // the feed may have been dropped while idle
+                return Err(if inner.last_sequence == 0 {
+                    Status::out_of_range("Updates since the resume token are no longer available")
+                } else {
+                    Status::invalid_argument("Invalid resume token")
+                });
+            }
+            if cursor + (inner.updates.len() as u64) < inner.last_sequence {
+                return Err(Status::out_of_range(
+                    "Updates since the resume token are no longer available",
+                ));
+            }
+            inner.subscribers.push(tx.clone());
+            inner.idle_since = None;
+            cursor
+        };
+        tokio::spawn(forward(feed, cursor, tx));
+        Ok(rx)
+    }
+
+    /// Remove the subscription of `key` which uses the channel of `tx`; the feed is kept
+    /// so that the subscription can be resumed
+    pub fn remove<Q>(&self, key: &Q, tx: &Subscriber)
+    where
+        K: Borrow<Q>,
+        Q: Debug + Eq + Hash + ?Sized,
+    {
+        if let Some(feed) = self.feeds.lock().unwrap().get(key) {
+            let mut inner = feed.inner.lock().unwrap();
+            inner
+                .subscribers
+                .retain(|current| !current.same_channel(tx));
+            if inner.subscribers.is_empty() && inner.idle_since.is_none() {
+                inner.idle_since = Some(Instant::now());
+            }
+            debug!("Removing subscriber key={:?}", key);
+        }
+    }
+
+    /// Remove the feed of `key` and end all of its subscriptions
+    pub fn remove_all<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if let Some(feed) = self.feeds.lock().unwrap().remove(key) {
+            let mut inner = feed.inner.lock().unwrap();
+            inner.closed = true;
+            inner.subscribers.clear();
+            feed.last_sequence.send_replace(inner.last_sequence);
+        }
+    }
+
+    /// Remove the feeds which have had no subscriptions for longer than `timeout`
+    pub fn remove_idle(&self, timeout: Duration) {
+        self.feeds.lock().unwrap().retain(|key, feed| {
+            let mut inner = feed.inner.lock().unwrap();
+            if inner
+                .idle_since
+                .is_some_and(|idle_since| idle_since.elapsed() >= timeout)
+            {
+                debug!("Removing idle feed key={:?}", key);
+                inner.closed = true;
+                feed.last_sequence.send_replace(inner.last_sequence);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// All subscriptions, one entry per channel
+    pub fn get(&self) -> Vec<(K, Subscriber)> {
+        self.feeds
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|(key, feed)| {
+                let inner = feed.inner.lock().unwrap();
+                inner
+                    .subscribers
+                    .iter()
+                    .map(|tx| (key.clone(), tx.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.feeds
+            .lock()
+            .unwrap()
+            .get(key)
+            .is_some_and(|feed| !feed.inner.lock().unwrap().subscribers.is_empty())
+    }
+
+    /// Append the update created by `update` to the feed of `key`, if it has one
+    pub fn send<Q>(&self, key: &Q, update: impl FnOnce() -> msg::Task)
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let Some(feed) = self.feeds.lock().unwrap().get(key).cloned() else {
+            return;
+        };
+        let mut inner = feed.inner.lock().unwrap();
+        let sequence = inner.last_sequence + 1;
+        let mut update = update();
+        update.sequence = sequence;
+        update.resume_token = self.resume_token(sequence);
+        inner.size += update.encoded_len();
+        inner.updates.push_back(update);
+        while inner.updates.len() > 1
+            && (inner.updates.len() > FEED_HISTORY || inner.size > FEED_HISTORY_SIZE)
+        {
+            let oldest = inner.updates.pop_front().unwrap();
+            inner.size -= oldest.encoded_len();
+        }
+        inner.last_sequence = sequence;
+        feed.last_sequence.send_replace(sequence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(round: u32) -> impl FnOnce() -> msg::Task {
+        move || msg::Task {
+            round,
+            ..Default::default()
+        }
+    }
+
+    async fn receive(rx: &mut Updates) -> msg::Task {
+        rx.recv().await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn multiple_subscriptions() {
+        let subscriptions = Subscriptions::new();
+        let mut first = subscriptions.subscribe(vec![0x01], None).unwrap();
+        let mut second = subscriptions.subscribe(vec![0x01], None).unwrap();
+        assert_eq!(subscriptions.get().len(), 2);
+
+        subscriptions.send([0x01].as_slice(), update(1));
+        assert_eq!(receive(&mut first).await.round, 1);
+        assert_eq!(receive(&mut second).await.sequence, 1);
+
+        // a closed stream does not affect the other subscription
+        drop(first);
+        subscriptions.send([0x01].as_slice(), update(2));
+        assert_eq!(receive(&mut second).await.round, 2);
+
+        for (key, tx) in subscriptions.get() {
+            if tx.is_closed() {
+                subscriptions.remove(&key, &tx);
+            }
+        }
+        assert_eq!(subscriptions.get().len(), 1);
+        subscriptions.remove_all([0x01].as_slice());
+        assert!(!subscriptions.contains([0x01].as_slice()));
+        assert!(second.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        let subscriptions = Subscriptions::new();
+        let mut rx = subscriptions
+            .subscribe(String::from("portal"), None)
+            .unwrap();
+        subscriptions.send("portal", update(1));
+        let token = receive(&mut rx).await.resume_token;
+        drop(rx);
+
+        subscriptions.send("portal", update(2));
+        subscriptions.send("portal", update(3));
+        let mut rx = subscriptions
+            .subscribe(String::from("portal"), Some(&token))
+            .unwrap();
+        assert_eq!(receive(&mut rx).await.round, 2);
+        assert_eq!(receive(&mut rx).await.round, 3);
+
+        let invalid = |token: &str| {
+            subscriptions
+                .subscribe(String::from("portal"), Some(token))
+                .unwrap_err()
+                .code()
+        };
+        assert_eq!(invalid("token"), tonic::Code::InvalidArgument);
+        assert_eq!(
+            invalid(&format!("{:016x}{:016x}", subscriptions.epoch, 9)),
+            tonic::Code::InvalidArgument
+        );
+        assert_eq!(
+            invalid(&format!("{:016x}{:016x}", !subscriptions.epoch, 1)),
+            tonic::Code::OutOfRange
+        );
+
+        // updates which are no longer retained cannot be resumed
+        for round in 0..FEED_HISTORY as u32 {
+            subscriptions.send("portal", update(round));
+        }
+        assert_eq!(invalid(&token), tonic::Code::OutOfRange);
+    }
+
+    #[tokio::test]
+    async fn history_size() {
+        let subscriptions = Subscriptions::new();
+        let mut rx = subscriptions.subscribe(vec![0x01], None).unwrap();
+        let large = || msg::Task {
+            data: Some(vec![0x00; FEED_HISTORY_SIZE / 2]),
+            ..Default::default()
+        };
+        subscriptions.send([0x01].as_slice(), large);
+        let token = receive(&mut rx).await.resume_token;
+        drop(rx);
+
+        subscriptions.send([0x01].as_slice(), large);
+        subscriptions.send([0x01].as_slice(), large);
+        assert_eq!(
+            subscriptions
+                .subscribe(vec![0x01], Some(&token))
+                .unwrap_err()
+                .code(),
+            tonic::Code::OutOfRange
+        );
+        let feeds = subscriptions.feeds.lock().unwrap();
+        let inner = feeds[[0x01].as_slice()].inner.lock().unwrap();
+        assert_eq!(inner.updates.len(), 1);
+        assert_eq!(inner.size, inner.updates[0].encoded_len());
+    }
+
+    #[tokio::test]
+    async fn idle_feeds() {
+        let subscriptions = Subscriptions::new();
+        let rx = subscriptions
+            .subscribe(String::from("portal"), None)
+            .unwrap();
+        subscriptions.send("portal", update(1));
+        subscriptions.remove_idle(Duration::ZERO);
+        assert!(subscriptions.contains("portal"));
+
+        drop(rx);
+        for (key, tx) in subscriptions.get() {
+            subscriptions.remove(&key, &tx);
+        }
+        subscriptions.remove_idle(FEED_IDLE_TIMEOUT);
+        assert_eq!(subscriptions.feeds.lock().unwrap().len(), 1);
+        subscriptions.remove_idle(Duration::ZERO);
+        assert!(subscriptions.feeds.lock().unwrap().is_empty());
+
+        // the updates of a dropped feed cannot be resumed
+        let token = subscriptions.resume_token(1);
+        assert_eq!(
+            subscriptions
+                .subscribe(String::from("portal"), Some(&token))
+                .unwrap_err()
+                .code(),
+            tonic::Code::OutOfRange
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber() {
+        let subscriptions = Subscriptions::new();
+        let mut rx = subscriptions.subscribe(vec![0x01], None).unwrap();
+        // more updates than fit into the channel are delivered in order
+        for round in 1..=32 {
+            subscriptions.send([0x01].as_slice(), update(round));
+        }
+        for round in 1..=32 {
+            let update = receive(&mut rx).await;
+            assert_eq!((update.round, update.sequence), (round, round as u64));
+        }
+
+        // a subscriber falling behind the retained history is told to resynchronize
+        let mut lagging = subscriptions.subscribe(vec![0x01], None).unwrap();
+        for round in 0..2 * FEED_HISTORY as u32 {
+            subscriptions.send([0x01].as_slice(), update(round));
+        }
+        let mut received = 0;
+        let status = loop {
+            match lagging.recv().await.unwrap() {
+                Ok(_) => received += 1,
+                Err(status) => break status,
+            }
+        };
+        assert!(received < 2 * FEED_HISTORY);
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+    }
+}
@@ -1,14 +1,18 @@
-use crate::communicator::Communicator;
+use crate::approval::{self, Verdict};
+use crate::communicator::{Communicator, CommunicatorSnapshot, Selection};
 use crate::device::Device;
 use crate::group::Group;
+use crate::metrics;
 use crate::proto::{DecryptRequest, ProtocolType, TaskType};
 use crate::protocols::elgamal::ElgamalDecrypt;
 use crate::protocols::Protocol;
-use crate::tasks::{Task, TaskResult, TaskStatus};
+use crate::tasks::transcript::{FaultKind, Transcript};
+use crate::tasks::{find_group, receive_messages, Task, TaskResult, TaskSnapshot, TaskStatus};
 use crate::{get_timestamp, utils};
-use log::info;
-use meesign_crypto::proto::{Message as _, ProtocolMessage};
+use log::{info, warn};
 use prost::Message as _;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tonic::codegen::Arc;
 
 pub struct DecryptTask {
@@ -20,24 +24,52 @@ pub struct DecryptTask {
     request: Vec<u8>,
     pub(super) last_update: u64,
     pub(super) attempts: u32,
+    created: u64,
+    approved: Option<u64>,
+    terminated: Option<TaskStatus>,
+    transcript: Transcript,
 }
 
-impl DecryptTask {
-    pub fn new(group: Group, name: String, data: Vec<u8>, data_type: String) -> Self {
-        let mut devices: Vec<Arc<Device>> = group.devices().to_vec();
-        devices.sort_by_key(|x| x.identifier().to_vec());
+#[derive(Serialize, Deserialize)]
+pub struct DecryptTaskSnapshot {
+    communicator: CommunicatorSnapshot,
+    result: Option<Result<Vec<u8>, String>>,
+    round: u16,
+    attempts: u32,
+    created: u64,
+    approved: Option<u64>,
+    terminated: Option<TaskStatus>,
+    transcript: Transcript,
+}
 
-        let communicator = Communicator::new(&devices, group.threshold(), ProtocolType::Elgamal);
+impl DecryptTask {
+    pub fn try_new(
+        group: Group,
+        name: String,
+        data: Vec<u8>,
+        data_type: String,
+        selection: Selection,
+    ) -> Result<Self, String> {
+        let devices = group.share_holders();
+
+        let mut communicator =
+            Communicator::new(&devices, group.threshold(), ProtocolType::Elgamal);
+        if let Err(e) = communicator.set_selection(selection.clone()) {
+            warn!("Invalid participant selection error={}", e);
+            return Err(e);
+        }
 
         let request = (DecryptRequest {
             group_id: group.identifier().to_vec(),
             name,
             data: data.clone(),
             data_type,
+            participants: selection.pinned,
+            selection: selection.mode.into(),
         })
         .encode_to_vec();
 
-        DecryptTask {
+        Ok(DecryptTask {
             group,
             communicator,
             result: None,
@@ -46,6 +78,57 @@ impl DecryptTask {
             request,
             last_update: get_timestamp(),
             attempts: 0,
+            created: get_timestamp(),
+            approved: None,
+            terminated: None,
+            transcript: Transcript::default(),
+        })
+    }
+
+    pub fn restore(
+        request: &[u8],
+        snapshot: DecryptTaskSnapshot,
+        groups: &HashMap<Vec<u8>, Group>,
+    ) -> Result<Self, String> {
+        let request = DecryptRequest::decode(request)
+            .map_err(|_| String::from("Expected DecryptRequest."))?;
+        let group = find_group(groups, &request.group_id)?;
+        let selection = Selection {
+            mode: request.selection(),
+            pinned: request.participants,
+        };
+        let mut task = DecryptTask::try_new(
+            group,
+            request.name,
+            request.data,
+            request.data_type,
+            selection,
+        )?;
+        task.communicator.restore(snapshot.communicator)?;
+        task.result = snapshot.result;
+        task.protocol.set_round(snapshot.round);
+        task.attempts = snapshot.attempts;
+        task.created = snapshot.created;
+        task.approved = snapshot.approved;
+        task.terminated = snapshot.terminated;
+        task.transcript = snapshot.transcript;
+        Ok(task)
+    }
+
+    /// Avoid the devices which stalled the current protocol run in the next one
+    pub(super) fn mark_stalled(&mut self) {
+        for device_id in self.communicator.mark_stalled() {
+            warn!(
+                "Device stalled the protocol device_id={} attempt={}",
+                utils::hextrunc(&device_id),
+                self.attempts
+            );
+            self.transcript.record_fault(
+                &device_id,
+                self.attempts,
+                self.protocol.round(),
+                FaultKind::Stalled,
+            );
         }
     }
 
@@ -61,6 +144,12 @@ impl DecryptTask {
     pub(super) fn finalize_task(&mut self) {
         let decrypted = self.protocol.finalize(&mut self.communicator);
         if decrypted.is_none() {
+            // every active device sent its final messages, so none of them can be blamed
+            self.transcript.record_unattributed_fault(
+                self.attempts,
+                self.protocol.last_round(),
+                FaultKind::MissingOutput,
+            );
             self.result = Some(Err("Task failed (data not output)".to_string()));
             return;
         }
@@ -76,6 +165,7 @@ impl DecryptTask {
     }
 
     pub(super) fn next_round(&mut self) {
+        metrics::round_started(self.protocol.get_type());
         if self.protocol.round() == 0 {
             self.start_task();
         } else if self.protocol.round() < self.protocol.last_round() {
@@ -88,7 +178,7 @@ impl DecryptTask {
     pub(super) fn update_internal(
         &mut self,
         device_id: &[u8],
-        data: &[u8],
+        data: &[Vec<u8>],
     ) -> Result<bool, String> {
         if self.communicator.accept_count() < self.group.threshold() {
             return Err("Not enough agreements to proceed with the protocol.".to_string());
@@ -98,9 +188,17 @@ impl DecryptTask {
             return Err("Wasn't waiting for a message from this ID.".to_string());
         }
 
-        let data =
-            ProtocolMessage::decode(data).map_err(|_| String::from("Expected ProtocolMessage."))?;
-        self.communicator.receive_messages(device_id, data.message);
+        if let Err(e) = receive_messages(
+            &mut self.communicator,
+            &mut self.transcript,
+            device_id,
+            data,
+            self.attempts,
+            self.protocol.round(),
+        ) {
+            self.result = Some(Err("Task failed (malformed message)".to_string()));
+            return Err(e);
+        }
         self.last_update = get_timestamp();
 
         if self.communicator.round_received() && self.protocol.round() <= self.protocol.last_round()
@@ -114,19 +212,39 @@ impl DecryptTask {
         self.communicator.decide(device_id, decision);
         self.last_update = get_timestamp();
         if self.result.is_none() && self.protocol.round() == 0 {
+            let verdict = self.approval();
             if self.communicator.reject_count() >= self.group.reject_threshold() {
                 self.result = Some(Err("Task declined".to_string()));
                 return Some(false);
-            } else if self.communicator.accept_count() >= self.group.threshold() {
+            } else if self.communicator.pinned_verdict() == Verdict::Declined {
+                self.result = Some(Err("Task declined by a pinned participant".to_string()));
+                return Some(false);
+            } else if verdict == Verdict::Declined {
+                self.result = Some(Err("Task declined by the approval policy".to_string()));
+                return Some(false);
+            } else if self.communicator.accept_count() >= self.group.threshold()
+                && verdict == Verdict::Approved
+            {
+                self.approved = Some(get_timestamp());
                 return Some(true);
             }
         }
         None
     }
+
+    /// Evaluate the decisions against the approval rules of the group and the pinned
+    /// participants
+    fn approval(&self) -> Verdict {
+        approval::evaluate(self.group.identifier(), self.communicator.decisions())
+            .and(self.communicator.pinned_verdict())
+    }
 }
 
 impl Task for DecryptTask {
     fn get_status(&self) -> TaskStatus {
+        if let Some(status) = &self.terminated {
+            return status.clone();
+        }
         match &self.result {
             Some(Err(e)) => TaskStatus::Failed(e.clone()),
             Some(Ok(_)) => TaskStatus::Finished,
@@ -144,12 +262,12 @@ impl Task for DecryptTask {
         TaskType::Decrypt
     }
 
-    fn get_work(&self, device_id: Option<&[u8]>) -> Option<Vec<u8>> {
+    fn get_work(&self, device_id: Option<&[u8]>) -> Vec<Vec<u8>> {
         if device_id.is_none() || !self.waiting_for(device_id.unwrap()) {
-            return None;
+            return Vec::new();
         }
 
-        self.communicator.get_message(device_id.unwrap())
+        self.communicator.get_messages(device_id.unwrap())
     }
 
     fn get_result(&self) -> Option<TaskResult> {
@@ -160,6 +278,14 @@ impl Task for DecryptTask {
         }
     }
 
+    fn get_active_devices(&self) -> Option<Vec<Vec<u8>>> {
+        self.communicator.get_active_devices()
+    }
+
+    fn get_excluded_devices(&self) -> Vec<Vec<u8>> {
+        self.communicator.get_excluded_devices()
+    }
+
     fn get_decisions(&self) -> (u32, u32) {
         (
             self.communicator.accept_count(),
@@ -167,7 +293,7 @@ impl Task for DecryptTask {
         )
     }
 
-    fn update(&mut self, device_id: &[u8], data: &[u8]) -> Result<bool, String> {
+    fn update(&mut self, device_id: &[u8], data: &[Vec<u8>]) -> Result<bool, String> {
         let result = self.update_internal(device_id, data);
         if let Ok(true) = result {
             self.next_round();
@@ -177,11 +303,12 @@ impl Task for DecryptTask {
 
     fn restart(&mut self) -> Result<bool, String> {
         self.last_update = get_timestamp();
-        if self.result.is_some() {
+        if self.result.is_some() || self.terminated.is_some() {
             return Ok(false);
         }
 
         if self.is_approved() {
+            self.mark_stalled();
             self.attempts += 1;
             self.start_task();
             Ok(true)
@@ -196,6 +323,7 @@ impl Task for DecryptTask {
 
     fn is_approved(&self) -> bool {
         self.communicator.accept_count() >= self.group.threshold()
+            && self.approval() == Verdict::Approved
     }
 
     fn has_device(&self, device_id: &[u8]) -> bool {
@@ -224,6 +352,10 @@ impl Task for DecryptTask {
         result
     }
 
+    fn device_decided(&self, device_id: &[u8]) -> bool {
+        self.communicator.device_decided(device_id)
+    }
+
     fn acknowledge(&mut self, device_id: &[u8]) {
         self.communicator.acknowledge(device_id);
     }
@@ -239,4 +371,38 @@ impl Task for DecryptTask {
     fn get_attempts(&self) -> u32 {
         self.attempts
     }
+
+    fn get_transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    fn created_at(&self) -> u64 {
+        self.created
+    }
+
+    fn approved_at(&self) -> Option<u64> {
+        self.approved
+    }
+
+    fn terminate(&mut self, status: TaskStatus) -> bool {
+        if self.result.is_some() || self.terminated.is_some() {
+            return false;
+        }
+        self.terminated = Some(status);
+        self.last_update = get_timestamp();
+        true
+    }
+
+    fn snapshot(&self) -> TaskSnapshot {
+        TaskSnapshot::Decrypt(DecryptTaskSnapshot {
+            communicator: self.communicator.snapshot(),
+            result: self.result.clone(),
+            round: self.protocol.round(),
+            attempts: self.attempts,
+            created: self.created,
+            approved: self.approved,
+            terminated: self.terminated.clone(),
+            transcript: self.transcript.clone(),
+        })
+    }
 }
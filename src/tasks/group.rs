@@ -1,24 +1,27 @@
-use crate::communicator::Communicator;
+use crate::certificate::issue_group_certificate;
+use crate::communicator::{Communicator, CommunicatorSnapshot};
 use crate::device::Device;
 use crate::group::Group;
+use crate::metrics;
 use crate::proto::{KeyType, ProtocolType, TaskType};
 use crate::protocols::elgamal::ElgamalGroup;
 use crate::protocols::frost::FROSTGroup;
 use crate::protocols::gg18::GG18Group;
 use crate::protocols::Protocol;
-use crate::tasks::{Task, TaskResult, TaskStatus};
+use crate::tasks::transcript::{FaultKind, Transcript};
+use crate::tasks::{find_group, receive_messages, Task, TaskResult, TaskSnapshot, TaskStatus};
 use crate::{get_timestamp, utils};
-use log::{info, warn};
-use meesign_crypto::proto::{Message as _, ProtocolMessage};
+use log::{error, info, warn};
 use prost::Message as _;
-use std::io::Read;
-use std::process::{Command, Stdio};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tonic::codegen::Arc;
 
 pub struct GroupTask {
     name: String,
     threshold: u32,
     key_type: KeyType,
+    /// Devices listed once per key share they are to hold
     devices: Vec<Arc<Device>>,
     communicator: Communicator,
     result: Option<Result<Group, String>>,
@@ -26,9 +29,66 @@ pub struct GroupTask {
     request: Vec<u8>,
     last_update: u64,
     attempts: u32,
+    created: u64,
+    approved: Option<u64>,
+    terminated: Option<TaskStatus>,
+    transcript: Transcript,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GroupTaskSnapshot {
+    communicator: CommunicatorSnapshot,
+    /// Identifier of the established group
+    result: Option<Result<Vec<u8>, String>>,
+    round: u16,
+    attempts: u32,
+    created: u64,
+    approved: Option<u64>,
+    terminated: Option<TaskStatus>,
+    transcript: Transcript,
+}
+
+/// Check the numbers of key shares requested for `device_count` devices, which hold a
+/// single share each if `shares` is empty
+///
+/// At least two devices have to take part and none of them may hold `threshold` shares,
+/// so that no device can act on behalf of the group alone. The total number of shares is
+/// limited by `max_shares` if given.
+pub fn check_shares(
+    device_count: usize,
+    shares: &[u32],
+    threshold: u32,
+    max_shares: Option<u32>,
+) -> Result<(), String> {
+    if !shares.is_empty() && shares.len() != device_count {
+        return Err("Shares must be given for each device".into());
+    }
+    if shares.contains(&0) {
+        return Err("Each device must hold a share".into());
+    }
+    if device_count < 2 {
+        return Err("A group must have at least two devices".into());
+    }
+    let max_device_shares = shares.iter().copied().max().unwrap_or(1);
+    if max_device_shares >= threshold {
+        return Err("A device must not hold the threshold of shares".into());
+    }
+    let total: u64 = if shares.is_empty() {
+        device_count as u64
+    } else {
+        shares.iter().copied().map(u64::from).sum()
+    };
+    if let Some(max_shares) = max_shares {
+        if total > u64::from(max_shares) {
+            return Err(format!("A group may have at most {} shares", max_shares));
+        }
+    }
+    Ok(())
 }
 
 impl GroupTask {
+    /// Constructs a task establishing a group of `devices`; a device is listed once for each
+    /// key share it is to hold
     pub fn try_new(
         name: &str,
         devices: &[Arc<Device>],
@@ -73,12 +133,23 @@ impl GroupTask {
 
         let communicator = Communicator::new(&devices, devices.len() as u32, protocol.get_type());
 
+        let mut device_ids: Vec<Vec<u8>> = Vec::new();
+        let mut shares = Vec::new();
+        for device in &devices {
+            if device_ids.last().map(Vec::as_slice) == Some(device.identifier()) {
+                *shares.last_mut().unwrap() += 1;
+            } else {
+                device_ids.push(device.identifier().to_vec());
+                shares.push(1);
+            }
+        }
         let request = (crate::proto::GroupRequest {
-            device_ids: devices.iter().map(|x| x.identifier().to_vec()).collect(),
+            device_ids,
             name: String::from(name),
             threshold,
             protocol: protocol.get_type() as i32,
             key_type: key_type as i32,
+            shares,
         })
         .encode_to_vec();
 
@@ -93,9 +164,67 @@ impl GroupTask {
             request,
             last_update: get_timestamp(),
             attempts: 0,
+            created: get_timestamp(),
+            approved: None,
+            terminated: None,
+            transcript: Transcript::default(),
         })
     }
 
+    pub fn restore(
+        request: &[u8],
+        snapshot: GroupTaskSnapshot,
+        devices: &HashMap<Vec<u8>, Arc<Device>>,
+        groups: &HashMap<Vec<u8>, Group>,
+    ) -> Result<Self, String> {
+        let request = crate::proto::GroupRequest::decode(request)
+            .map_err(|_| String::from("Expected GroupRequest."))?;
+        // the limit may have been lowered since the task was created
+        check_shares(
+            request.device_ids.len(),
+            &request.shares,
+            request.threshold,
+            None,
+        )?;
+        let device_list = request
+            .device_ids
+            .iter()
+            .enumerate()
+            .map(|(idx, id)| {
+                let device = devices
+                    .get(id)
+                    .cloned()
+                    .ok_or_else(|| format!("Unknown device {}", hex::encode(id)))?;
+                let shares = request.shares.get(idx).copied().unwrap_or(1);
+                Ok(vec![device; shares as usize])
+            })
+            .collect::<Result<Vec<_>, String>>()?
+            .concat();
+        let protocol = ProtocolType::try_from(request.protocol).map_err(|e| e.to_string())?;
+        let key_type = KeyType::try_from(request.key_type).map_err(|e| e.to_string())?;
+
+        let mut task = GroupTask::try_new(
+            &request.name,
+            &device_list,
+            request.threshold,
+            protocol,
+            key_type,
+        )?;
+        task.communicator.restore(snapshot.communicator)?;
+        task.result = match snapshot.result {
+            Some(Ok(group_id)) => Some(Ok(find_group(groups, &group_id)?)),
+            Some(Err(e)) => Some(Err(e)),
+            None => None,
+        };
+        task.protocol.set_round(snapshot.round);
+        task.attempts = snapshot.attempts;
+        task.created = snapshot.created;
+        task.approved = snapshot.approved;
+        task.terminated = snapshot.terminated;
+        task.transcript = snapshot.transcript;
+        Ok(task)
+    }
+
     fn start_task(&mut self) {
         self.protocol.initialize(&mut self.communicator, &[]);
     }
@@ -104,16 +233,49 @@ impl GroupTask {
         self.protocol.advance(&mut self.communicator);
     }
 
+    /// Attribute the stall of the current protocol run; every device takes part in the next one
+    fn mark_stalled(&mut self) {
+        for device_id in self.communicator.mark_stalled() {
+            warn!(
+                "Device stalled the protocol device_id={} attempt={}",
+                utils::hextrunc(&device_id),
+                self.attempts
+            );
+            self.transcript.record_fault(
+                &device_id,
+                self.attempts,
+                self.protocol.round(),
+                FaultKind::Stalled,
+            );
+        }
+    }
+
     fn finalize_task(&mut self) {
         let identifier = self.protocol.finalize(&mut self.communicator);
         if identifier.is_none() {
+            // every active device sent its final messages, so none of them can be blamed
+            self.transcript.record_unattributed_fault(
+                self.attempts,
+                self.protocol.last_round(),
+                FaultKind::MissingOutput,
+            );
             self.result = Some(Err("Task failed (group key not output)".to_string()));
             return;
         }
         let identifier = identifier.unwrap();
-        // TODO
         let certificate = if self.protocol.get_type() == ProtocolType::Gg18 {
-            Some(issue_certificate(&self.name, &identifier))
+            match issue_group_certificate(&self.name, &identifier) {
+                Ok(certificate) => Some(certificate),
+                Err(e) if self.key_type == KeyType::SignPdf => {
+                    error!("Could not issue group certificate error={}", e);
+                    self.result = Some(Err("Task failed (certificate not issued)".to_string()));
+                    return;
+                }
+                Err(e) => {
+                    warn!("Could not issue group certificate error={}", e);
+                    None
+                }
+            }
         } else {
             None
         };
@@ -121,7 +283,7 @@ impl GroupTask {
         info!(
             "Group established group_id={} devices={:?}",
             utils::hextrunc(&identifier),
-            self.devices
+            self.get_devices()
                 .iter()
                 .map(|device| utils::hextrunc(device.identifier()))
                 .collect::<Vec<_>>()
@@ -141,6 +303,7 @@ impl GroupTask {
     }
 
     fn next_round(&mut self) {
+        metrics::round_started(self.protocol.get_type());
         if self.protocol.round() == 0 {
             self.start_task();
         } else if self.protocol.round() < self.protocol.last_round() {
@@ -153,6 +316,9 @@ impl GroupTask {
 
 impl Task for GroupTask {
     fn get_status(&self) -> TaskStatus {
+        if let Some(status) = &self.terminated {
+            return status.clone();
+        }
         match &self.result {
             Some(Err(e)) => TaskStatus::Failed(e.clone()),
             Some(Ok(_)) => TaskStatus::Finished,
@@ -170,12 +336,12 @@ impl Task for GroupTask {
         TaskType::Group
     }
 
-    fn get_work(&self, device_id: Option<&[u8]>) -> Option<Vec<u8>> {
+    fn get_work(&self, device_id: Option<&[u8]>) -> Vec<Vec<u8>> {
         if device_id.is_none() || !self.waiting_for(device_id.unwrap()) {
-            return None;
+            return Vec::new();
         }
 
-        self.communicator.get_message(device_id.unwrap())
+        self.communicator.get_messages(device_id.unwrap())
     }
 
     fn get_result(&self) -> Option<TaskResult> {
@@ -186,6 +352,14 @@ impl Task for GroupTask {
         }
     }
 
+    fn get_active_devices(&self) -> Option<Vec<Vec<u8>>> {
+        self.communicator.get_active_devices()
+    }
+
+    fn get_excluded_devices(&self) -> Vec<Vec<u8>> {
+        self.communicator.get_excluded_devices()
+    }
+
     fn get_decisions(&self) -> (u32, u32) {
         (
             self.communicator.accept_count(),
@@ -193,7 +367,7 @@ impl Task for GroupTask {
         )
     }
 
-    fn update(&mut self, device_id: &[u8], data: &[u8]) -> Result<bool, String> {
+    fn update(&mut self, device_id: &[u8], data: &[Vec<u8>]) -> Result<bool, String> {
         if self.communicator.accept_count() != self.devices.len() as u32 {
             return Err("Not enough agreements to proceed with the protocol.".to_string());
         }
@@ -202,9 +376,17 @@ impl Task for GroupTask {
             return Err("Wasn't waiting for a message from this ID.".to_string());
         }
 
-        let data =
-            ProtocolMessage::decode(data).map_err(|_| String::from("Expected ProtocolMessage."))?;
-        self.communicator.receive_messages(device_id, data.message);
+        if let Err(e) = receive_messages(
+            &mut self.communicator,
+            &mut self.transcript,
+            device_id,
+            data,
+            self.attempts,
+            self.protocol.round(),
+        ) {
+            self.result = Some(Err("Task failed (malformed message)".to_string()));
+            return Err(e);
+        }
         self.last_update = get_timestamp();
 
         if self.communicator.round_received() && self.protocol.round() <= self.protocol.last_round()
@@ -218,11 +400,12 @@ impl Task for GroupTask {
 
     fn restart(&mut self) -> Result<bool, String> {
         self.last_update = get_timestamp();
-        if self.result.is_some() {
+        if self.result.is_some() || self.terminated.is_some() {
             return Ok(false);
         }
 
         if self.is_approved() {
+            self.mark_stalled();
             self.attempts += 1;
             self.start_task();
             Ok(true)
@@ -248,7 +431,9 @@ impl Task for GroupTask {
     }
 
     fn get_devices(&self) -> Vec<Arc<Device>> {
-        self.devices.clone()
+        let mut devices = self.devices.clone();
+        devices.dedup_by(|a, b| a.identifier() == b.identifier());
+        devices
     }
 
     fn waiting_for(&self, device: &[u8]) -> bool {
@@ -269,6 +454,7 @@ impl Task for GroupTask {
                 self.result = Some(Err("Task declined".to_string()));
                 return Some(false);
             } else if self.communicator.accept_count() == self.devices.len() as u32 {
+                self.approved = Some(get_timestamp());
                 self.next_round();
                 return Some(true);
             }
@@ -276,6 +462,10 @@ impl Task for GroupTask {
         None
     }
 
+    fn device_decided(&self, device_id: &[u8]) -> bool {
+        self.communicator.device_decided(device_id)
+    }
+
     fn acknowledge(&mut self, device_id: &[u8]) {
         self.communicator.acknowledge(device_id);
     }
@@ -291,26 +481,65 @@ impl Task for GroupTask {
     fn get_attempts(&self) -> u32 {
         self.attempts
     }
+
+    fn get_transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    fn created_at(&self) -> u64 {
+        self.created
+    }
+
+    fn approved_at(&self) -> Option<u64> {
+        self.approved
+    }
+
+    fn terminate(&mut self, status: TaskStatus) -> bool {
+        if self.result.is_some() || self.terminated.is_some() {
+            return false;
+        }
+        self.terminated = Some(status);
+        self.last_update = get_timestamp();
+        true
+    }
+
+    fn snapshot(&self) -> TaskSnapshot {
+        TaskSnapshot::Group(GroupTaskSnapshot {
+            communicator: self.communicator.snapshot(),
+            result: self.result.as_ref().map(|result| {
+                result
+                    .as_ref()
+                    .map(|group| group.identifier().to_vec())
+                    .map_err(String::clone)
+            }),
+            round: self.protocol.round(),
+            attempts: self.attempts,
+            created: self.created,
+            approved: self.approved,
+            terminated: self.terminated.clone(),
+            transcript: self.transcript.clone(),
+        })
+    }
 }
 
-fn issue_certificate(name: &str, public_key: &[u8]) -> Vec<u8> {
-    assert_eq!(public_key.len(), 65);
-    let mut process = Command::new("java")
-        .arg("-jar")
-        .arg("MeeSignHelper.jar")
-        .arg("cert")
-        .arg(name)
-        .arg(hex::encode(public_key))
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap();
-
-    let mut result = Vec::new();
-    process
-        .stdout
-        .as_mut()
-        .unwrap()
-        .read_to_end(&mut result)
-        .unwrap();
-    result
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares() {
+        assert!(check_shares(3, &[], 2, Some(3)).is_ok());
+        assert!(check_shares(3, &[1, 2, 1], 3, Some(4)).is_ok());
+        // the limit is checked only when given
+        assert!(check_shares(3, &[1, 2, 1], 3, Some(3)).is_err());
+        assert!(check_shares(3, &[1, 2, 1], 3, None).is_ok());
+
+        assert!(check_shares(3, &[1, 2], 2, None).is_err());
+        assert!(check_shares(2, &[1, 0], 2, None).is_err());
+        // a single device could act on behalf of the group
+        assert!(check_shares(1, &[], 2, None).is_err());
+        assert!(check_shares(1, &[2], 2, None).is_err());
+        assert!(check_shares(2, &[2, 1], 2, None).is_err());
+        assert!(check_shares(2, &[3, 1], 3, None).is_err());
+    }
 }
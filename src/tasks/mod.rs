@@ -2,18 +2,40 @@ pub(crate) mod decrypt;
 pub(crate) mod group;
 pub(crate) mod sign;
 pub(crate) mod sign_pdf;
+pub(crate) mod transcript;
 
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::communicator::Communicator;
 use crate::device::Device;
 use crate::group::Group;
+use crate::tasks::transcript::{FaultKind, Transcript};
+use meesign_crypto::proto::{Message as _, ProtocolMessage};
+use serde::{Deserialize, Serialize};
 use tonic::codegen::Arc;
 
-#[derive(Clone, PartialEq, Eq)]
+/// Task shared between the State and request handlers, each task is locked separately
+pub type SharedTask = Arc<Mutex<Box<dyn Task + Send + Sync>>>;
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TaskStatus {
     Created,
     Running(u16),
     // round
     Finished,
     Failed(String),
+    /// Not approved or completed before its deadline
+    Expired,
+    /// Cancelled by the requester
+    Cancelled,
+}
+
+impl TaskStatus {
+    /// True if the task can no longer change
+    pub fn has_ended(&self) -> bool {
+        !matches!(self, TaskStatus::Created | TaskStatus::Running(_))
+    }
 }
 
 #[derive(Clone)]
@@ -35,17 +57,106 @@ impl TaskResult {
     }
 }
 
+/// Serializable state of a Task used to restore it after a server restart
+#[derive(Serialize, Deserialize)]
+pub enum TaskSnapshot {
+    Group(group::GroupTaskSnapshot),
+    Sign(sign::SignTaskSnapshot),
+    SignPdf(sign_pdf::SignPDFTaskSnapshot),
+    Decrypt(decrypt::DecryptTaskSnapshot),
+}
+
+impl TaskSnapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        bincode::deserialize(bytes).map_err(|e| e.to_string())
+    }
+
+    /// Reconstruct the task created by `request` and bring it to the captured state
+    pub fn restore(
+        self,
+        request: &[u8],
+        devices: &HashMap<Vec<u8>, Arc<Device>>,
+        groups: &HashMap<Vec<u8>, Group>,
+    ) -> Result<Box<dyn Task + Send + Sync>, String> {
+        Ok(match self {
+            TaskSnapshot::Group(snapshot) => Box::new(group::GroupTask::restore(
+                request, snapshot, devices, groups,
+            )?),
+            TaskSnapshot::Sign(snapshot) => {
+                Box::new(sign::SignTask::restore(request, snapshot, groups)?)
+            }
+            TaskSnapshot::SignPdf(snapshot) => {
+                Box::new(sign_pdf::SignPDFTask::restore(request, snapshot, groups)?)
+            }
+            TaskSnapshot::Decrypt(snapshot) => {
+                Box::new(decrypt::DecryptTask::restore(request, snapshot, groups)?)
+            }
+        })
+    }
+}
+
+fn find_group(groups: &HashMap<Vec<u8>, Group>, group_id: &[u8]) -> Result<Group, String> {
+    groups
+        .get(group_id)
+        .cloned()
+        .ok_or_else(|| format!("Unknown group {}", hex::encode(group_id)))
+}
+
+/// Decode the serialized `ProtocolMessage` of each share into its messages for other parties
+fn decode_messages(data: &[Vec<u8>]) -> Result<Vec<Vec<Vec<u8>>>, String> {
+    data.iter()
+        .map(|data| {
+            ProtocolMessage::decode(data.as_slice())
+                .map(|data| data.message)
+                .map_err(|_| String::from("Expected ProtocolMessage."))
+        })
+        .collect()
+}
+
+/// Pass the messages of `device_id` to `communicator` and record them in `transcript`;
+/// malformed messages are attributed to the device and should fail the task
+fn receive_messages(
+    communicator: &mut Communicator,
+    transcript: &mut Transcript,
+    device_id: &[u8],
+    data: &[Vec<u8>],
+    attempt: u32,
+    round: u16,
+) -> Result<(), String> {
+    let received = decode_messages(data).and_then(|messages| {
+        if communicator.receive_messages(device_id, messages) {
+            Ok(())
+        } else {
+            Err("Expected a message for each other party and active share.".to_string())
+        }
+    });
+    match received {
+        Ok(()) => transcript.record_message(device_id, attempt, round, data),
+        Err(_) => transcript.record_fault(device_id, attempt, round, FaultKind::MalformedMessage),
+    }
+    received
+}
+
 pub trait Task {
     fn get_status(&self) -> TaskStatus;
     fn get_type(&self) -> crate::proto::TaskType;
-    fn get_work(&self, device_id: Option<&[u8]>) -> Option<Vec<u8>>;
+    /// Data awaiting the action of `device_id`, one item per its active key share
+    fn get_work(&self, device_id: Option<&[u8]>) -> Vec<Vec<u8>>;
     fn get_result(&self) -> Option<TaskResult>;
     fn get_decisions(&self) -> (u32, u32);
-    /// Update protocol state with `data` from `device_id`
+    /// Devices chosen to participate in the current protocol run, if it has started
+    fn get_active_devices(&self) -> Option<Vec<Vec<u8>>>;
+    /// Devices left out of the current protocol run after stalling an earlier one
+    fn get_excluded_devices(&self) -> Vec<Vec<u8>>;
+    /// Update protocol state with `data` from `device_id`, one message per its active key share
     ///
     /// # Returns
     /// `Ok(true)` if this update caused the next round to start; `Ok(false)` otherwise.
-    fn update(&mut self, device_id: &[u8], data: &[u8]) -> Result<bool, String>;
+    fn update(&mut self, device_id: &[u8], data: &[Vec<u8>]) -> Result<bool, String>;
 
     /// Attempt to restart protocol in task
     ///
@@ -70,10 +181,30 @@ pub trait Task {
     /// `Some(false)` if this decision caused the protocol to fail;
     /// `None` otherwise.
     fn decide(&mut self, device_id: &[u8], decision: bool) -> Option<bool>;
+    /// True if `device_id` already submitted its decision
+    fn device_decided(&self, device_id: &[u8]) -> bool;
 
     fn acknowledge(&mut self, device_id: &[u8]);
     fn device_acknowledged(&self, device_id: &[u8]) -> bool;
     fn get_request(&self) -> &[u8];
 
     fn get_attempts(&self) -> u32;
+
+    /// Messages received in the protocol runs and the faults attributed to devices
+    fn get_transcript(&self) -> &Transcript;
+
+    /// Get timestamp of the task creation
+    fn created_at(&self) -> u64;
+
+    /// Get timestamp of the task approval, `None` if the task has not been approved
+    fn approved_at(&self) -> Option<u64>;
+
+    /// End an unfinished task with `TaskStatus::Expired` or `TaskStatus::Cancelled`
+    ///
+    /// # Returns
+    /// `false` if the task has already ended; `true` otherwise.
+    fn terminate(&mut self, status: TaskStatus) -> bool;
+
+    /// Capture the task state so that it can be restored after a server restart
+    fn snapshot(&self) -> TaskSnapshot;
 }
@@ -1,15 +1,19 @@
-use crate::communicator::Communicator;
+use crate::approval::{self, Verdict};
+use crate::communicator::{Communicator, CommunicatorSnapshot, Selection};
 use crate::device::Device;
 use crate::group::Group;
+use crate::metrics;
 use crate::proto::{ProtocolType, SignRequest, TaskType};
 use crate::protocols::frost::FROSTSign;
 use crate::protocols::gg18::GG18Sign;
 use crate::protocols::Protocol;
-use crate::tasks::{Task, TaskResult, TaskStatus};
+use crate::tasks::transcript::{FaultKind, Transcript};
+use crate::tasks::{find_group, receive_messages, Task, TaskResult, TaskSnapshot, TaskStatus};
 use crate::{get_timestamp, utils};
 use log::{info, warn};
-use meesign_crypto::proto::{Message as _, ProtocolMessage};
 use prost::Message as _;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tonic::codegen::Arc;
 
 pub struct SignTask {
@@ -22,20 +26,47 @@ pub struct SignTask {
     request: Vec<u8>,
     pub(super) last_update: u64,
     pub(super) attempts: u32,
+    created: u64,
+    approved: Option<u64>,
+    pub(super) terminated: Option<TaskStatus>,
+    transcript: Transcript,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SignTaskSnapshot {
+    communicator: CommunicatorSnapshot,
+    result: Option<Result<Vec<u8>, String>>,
+    preprocessed: Option<Vec<u8>>,
+    round: u16,
+    attempts: u32,
+    created: u64,
+    approved: Option<u64>,
+    terminated: Option<TaskStatus>,
+    transcript: Transcript,
 }
 
 impl SignTask {
-    pub fn try_new(group: Group, name: String, data: Vec<u8>) -> Result<Self, String> {
-        let mut devices: Vec<Arc<Device>> = group.devices().to_vec();
-        devices.sort_by_key(|x| x.identifier().to_vec());
+    pub fn try_new(
+        group: Group,
+        name: String,
+        data: Vec<u8>,
+        selection: Selection,
+    ) -> Result<Self, String> {
+        let devices = group.share_holders();
         let protocol_type = group.protocol();
 
-        let communicator = Communicator::new(&devices, group.threshold(), protocol_type);
+        let mut communicator = Communicator::new(&devices, group.threshold(), protocol_type);
+        if let Err(e) = communicator.set_selection(selection.clone()) {
+            warn!("Invalid participant selection error={}", e);
+            return Err(e);
+        }
 
         let request = (SignRequest {
             group_id: group.identifier().to_vec(),
             name,
             data: data.clone(),
+            participants: selection.pinned,
+            selection: selection.mode.into(),
         })
         .encode_to_vec();
 
@@ -56,9 +87,52 @@ impl SignTask {
             request,
             last_update: get_timestamp(),
             attempts: 0,
+            created: get_timestamp(),
+            approved: None,
+            terminated: None,
+            transcript: Transcript::default(),
         })
     }
 
+    pub fn restore(
+        request: &[u8],
+        snapshot: SignTaskSnapshot,
+        groups: &HashMap<Vec<u8>, Group>,
+    ) -> Result<Self, String> {
+        let request =
+            SignRequest::decode(request).map_err(|_| String::from("Expected SignRequest."))?;
+        let group = find_group(groups, &request.group_id)?;
+        let selection = Selection {
+            mode: request.selection(),
+            pinned: request.participants,
+        };
+        let mut task = SignTask::try_new(group, request.name, request.data, selection)?;
+        task.communicator.restore(snapshot.communicator)?;
+        task.result = snapshot.result;
+        task.preprocessed = snapshot.preprocessed;
+        task.protocol.set_round(snapshot.round);
+        task.attempts = snapshot.attempts;
+        task.created = snapshot.created;
+        task.approved = snapshot.approved;
+        task.terminated = snapshot.terminated;
+        task.transcript = snapshot.transcript;
+        Ok(task)
+    }
+
+    pub(super) fn snapshot_internal(&self) -> SignTaskSnapshot {
+        SignTaskSnapshot {
+            communicator: self.communicator.snapshot(),
+            result: self.result.clone(),
+            preprocessed: self.preprocessed.clone(),
+            round: self.protocol.round(),
+            attempts: self.attempts,
+            created: self.created,
+            approved: self.approved,
+            terminated: self.terminated.clone(),
+            transcript: self.transcript.clone(),
+        }
+    }
+
     pub fn get_group(&self) -> &Group {
         &self.group
     }
@@ -68,6 +142,23 @@ impl SignTask {
         self.preprocessed = Some(preprocessed);
     }
 
+    /// Avoid the devices which stalled the current protocol run in the next one
+    pub(super) fn mark_stalled(&mut self) {
+        for device_id in self.communicator.mark_stalled() {
+            warn!(
+                "Device stalled the protocol device_id={} attempt={}",
+                utils::hextrunc(&device_id),
+                self.attempts
+            );
+            self.transcript.record_fault(
+                &device_id,
+                self.attempts,
+                self.protocol.round(),
+                FaultKind::Stalled,
+            );
+        }
+    }
+
     pub(super) fn start_task(&mut self) {
         assert!(self.communicator.accept_count() >= self.group.threshold());
         self.protocol.initialize(
@@ -83,6 +174,12 @@ impl SignTask {
     pub(super) fn finalize_task(&mut self) {
         let signature = self.protocol.finalize(&mut self.communicator);
         if signature.is_none() {
+            // every active device sent its final messages, so none of them can be blamed
+            self.transcript.record_unattributed_fault(
+                self.attempts,
+                self.protocol.last_round(),
+                FaultKind::MissingOutput,
+            );
             self.result = Some(Err("Task failed (signature not output)".to_string()));
             return;
         }
@@ -98,6 +195,7 @@ impl SignTask {
     }
 
     pub(super) fn next_round(&mut self) {
+        metrics::round_started(self.protocol.get_type());
         if self.protocol.round() == 0 {
             self.start_task();
         } else if self.protocol.round() < self.protocol.last_round() {
@@ -110,7 +208,7 @@ impl SignTask {
     pub(super) fn update_internal(
         &mut self,
         device_id: &[u8],
-        data: &[u8],
+        data: &[Vec<u8>],
     ) -> Result<bool, String> {
         if self.communicator.accept_count() < self.group.threshold() {
             return Err("Not enough agreements to proceed with the protocol.".to_string());
@@ -120,9 +218,17 @@ impl SignTask {
             return Err("Wasn't waiting for a message from this ID.".to_string());
         }
 
-        let data =
-            ProtocolMessage::decode(data).map_err(|_| String::from("Expected ProtocolMessage."))?;
-        self.communicator.receive_messages(device_id, data.message);
+        if let Err(e) = receive_messages(
+            &mut self.communicator,
+            &mut self.transcript,
+            device_id,
+            data,
+            self.attempts,
+            self.protocol.round(),
+        ) {
+            self.result = Some(Err("Task failed (malformed message)".to_string()));
+            return Err(e);
+        }
         self.last_update = get_timestamp();
 
         if self.communicator.round_received() && self.protocol.round() <= self.protocol.last_round()
@@ -136,19 +242,39 @@ impl SignTask {
         self.communicator.decide(device_id, decision);
         self.last_update = get_timestamp();
         if self.result.is_none() && self.protocol.round() == 0 {
+            let verdict = self.approval();
             if self.communicator.reject_count() >= self.group.reject_threshold() {
                 self.result = Some(Err("Task declined".to_string()));
                 return Some(false);
-            } else if self.communicator.accept_count() >= self.group.threshold() {
+            } else if self.communicator.pinned_verdict() == Verdict::Declined {
+                self.result = Some(Err("Task declined by a pinned participant".to_string()));
+                return Some(false);
+            } else if verdict == Verdict::Declined {
+                self.result = Some(Err("Task declined by the approval policy".to_string()));
+                return Some(false);
+            } else if self.communicator.accept_count() >= self.group.threshold()
+                && verdict == Verdict::Approved
+            {
+                self.approved = Some(get_timestamp());
                 return Some(true);
             }
         }
         None
     }
+
+    /// Evaluate the decisions against the approval rules of the group and the pinned
+    /// participants
+    fn approval(&self) -> Verdict {
+        approval::evaluate(self.group.identifier(), self.communicator.decisions())
+            .and(self.communicator.pinned_verdict())
+    }
 }
 
 impl Task for SignTask {
     fn get_status(&self) -> TaskStatus {
+        if let Some(status) = &self.terminated {
+            return status.clone();
+        }
         match &self.result {
             Some(Err(e)) => TaskStatus::Failed(e.clone()),
             Some(Ok(_)) => TaskStatus::Finished,
@@ -166,12 +292,12 @@ impl Task for SignTask {
         TaskType::SignChallenge
     }
 
-    fn get_work(&self, device_id: Option<&[u8]>) -> Option<Vec<u8>> {
+    fn get_work(&self, device_id: Option<&[u8]>) -> Vec<Vec<u8>> {
         if device_id.is_none() || !self.waiting_for(device_id.unwrap()) {
-            return None;
+            return Vec::new();
         }
 
-        self.communicator.get_message(device_id.unwrap())
+        self.communicator.get_messages(device_id.unwrap())
     }
 
     fn get_result(&self) -> Option<TaskResult> {
@@ -182,6 +308,14 @@ impl Task for SignTask {
         }
     }
 
+    fn get_active_devices(&self) -> Option<Vec<Vec<u8>>> {
+        self.communicator.get_active_devices()
+    }
+
+    fn get_excluded_devices(&self) -> Vec<Vec<u8>> {
+        self.communicator.get_excluded_devices()
+    }
+
     fn get_decisions(&self) -> (u32, u32) {
         (
             self.communicator.accept_count(),
@@ -189,7 +323,7 @@ impl Task for SignTask {
         )
     }
 
-    fn update(&mut self, device_id: &[u8], data: &[u8]) -> Result<bool, String> {
+    fn update(&mut self, device_id: &[u8], data: &[Vec<u8>]) -> Result<bool, String> {
         let result = self.update_internal(device_id, data);
         if let Ok(true) = result {
             self.next_round();
@@ -199,11 +333,12 @@ impl Task for SignTask {
 
     fn restart(&mut self) -> Result<bool, String> {
         self.last_update = get_timestamp();
-        if self.result.is_some() {
+        if self.result.is_some() || self.terminated.is_some() {
             return Ok(false);
         }
 
         if self.is_approved() {
+            self.mark_stalled();
             self.attempts += 1;
             self.start_task();
             Ok(true)
@@ -218,6 +353,7 @@ impl Task for SignTask {
 
     fn is_approved(&self) -> bool {
         self.communicator.accept_count() >= self.group.threshold()
+            && self.approval() == Verdict::Approved
     }
 
     fn has_device(&self, device_id: &[u8]) -> bool {
@@ -246,6 +382,10 @@ impl Task for SignTask {
         result
     }
 
+    fn device_decided(&self, device_id: &[u8]) -> bool {
+        self.communicator.device_decided(device_id)
+    }
+
     fn acknowledge(&mut self, device_id: &[u8]) {
         self.communicator.acknowledge(device_id);
     }
@@ -261,4 +401,29 @@ impl Task for SignTask {
     fn get_attempts(&self) -> u32 {
         self.attempts
     }
+
+    fn get_transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    fn created_at(&self) -> u64 {
+        self.created
+    }
+
+    fn approved_at(&self) -> Option<u64> {
+        self.approved
+    }
+
+    fn terminate(&mut self, status: TaskStatus) -> bool {
+        if self.result.is_some() || self.terminated.is_some() {
+            return false;
+        }
+        self.terminated = Some(status);
+        self.last_update = get_timestamp();
+        true
+    }
+
+    fn snapshot(&self) -> TaskSnapshot {
+        TaskSnapshot::Sign(self.snapshot_internal())
+    }
 }
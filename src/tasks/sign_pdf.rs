@@ -1,78 +1,119 @@
+use crate::communicator::Selection;
+use crate::config;
 use crate::device::Device;
 use crate::get_timestamp;
 use crate::group::Group;
+use crate::metrics;
+use crate::pdf::{self, PreparedPdf};
 use crate::proto::TaskType;
-use crate::tasks::sign::SignTask;
-use crate::tasks::{Task, TaskResult, TaskStatus};
+use crate::tasks::sign::{SignTask, SignTaskSnapshot};
+use crate::tasks::transcript::Transcript;
+use crate::tasks::{Task, TaskResult, TaskSnapshot, TaskStatus};
+use crate::utils;
 use log::{error, info, warn};
-use std::io::{Read, Write};
-use std::process::{Child, Command, Stdio};
-use tempfile::NamedTempFile;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tonic::codegen::Arc;
 
 pub struct SignPDFTask {
     sign_task: SignTask,
     result: Option<Result<Vec<u8>, String>>,
-    pdfhelper: Option<Child>,
+    prepared: Option<Box<dyn PreparedPdf>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SignPDFTaskSnapshot {
+    sign_task: SignTaskSnapshot,
+    result: Option<Result<Vec<u8>, String>>,
 }
 
 impl SignPDFTask {
-    pub fn try_new(group: Group, name: String, data: Vec<u8>) -> Result<Self, String> {
-        if data.len() > 8 * 1024 * 1024 || name.len() > 256 || name.chars().any(|x| x.is_control())
+    pub fn try_new(
+        group: Group,
+        name: String,
+        data: Vec<u8>,
+        selection: Selection,
+    ) -> Result<Self, String> {
+        let config = config::get();
+        if data.len() > config.max_pdf_size
+            || name.len() > config.max_document_name_length
+            || name.chars().any(|x| x.is_control())
         {
             warn!("Invalid input name={} len={}", name, data.len());
             return Err("Invalid input".to_string());
         }
 
-        let sign_task = SignTask::try_new(group, name, data)?;
+        let sign_task = SignTask::try_new(group, name, data, selection)?;
 
         Ok(SignPDFTask {
             sign_task,
             result: None,
-            pdfhelper: None,
+            prepared: None,
         })
     }
 
-    fn start_task(&mut self) {
-        let file = NamedTempFile::new();
-        if file.is_err() {
-            error!("Could not create temporary file");
-            self.result = Some(Err("Task failed (server error)".to_string()));
-            return;
+    /// Restore the task from its snapshot
+    ///
+    /// The prepared document is not part of the snapshot, so a running protocol
+    /// cannot be continued and the task gets restarted instead.
+    pub fn restore(
+        request: &[u8],
+        snapshot: SignPDFTaskSnapshot,
+        groups: &HashMap<Vec<u8>, Group>,
+    ) -> Result<Self, String> {
+        let mut task = SignPDFTask {
+            sign_task: SignTask::restore(request, snapshot.sign_task, groups)?,
+            result: snapshot.result,
+            prepared: None,
+        };
+        if matches!(task.get_status(), TaskStatus::Running(_)) {
+            // the devices could not respond while the server was down
+            task.restart_protocol(false)?;
         }
-        let mut file = file.unwrap();
-        if file.write_all(&self.sign_task.data).is_err() {
-            error!("Could not write in temporary file");
-            self.result = Some(Err("Task failed (server error)".to_string()));
-            return;
+        Ok(task)
+    }
+
+    /// Start a new protocol run, avoiding the devices which stalled the current one if
+    /// `mark_stalled` is set
+    fn restart_protocol(&mut self, mark_stalled: bool) -> Result<bool, String> {
+        self.sign_task.last_update = get_timestamp();
+        if self.result.is_some() || self.sign_task.terminated.is_some() {
+            return Ok(false);
         }
 
-        let pdfhelper = Command::new("java")
-            .arg("-jar")
-            .arg("MeeSignHelper.jar")
-            .arg("sign")
-            .arg(file.path().to_str().unwrap())
-            .stdout(Stdio::piped())
-            .stdin(Stdio::piped())
-            .spawn();
-
-        if pdfhelper.is_err() {
-            error!("Could not start PDFHelper");
-            self.result = Some(Err("Task failed (server error)".to_string()));
-            return;
+        if self.is_approved() {
+            if mark_stalled {
+                self.sign_task.mark_stalled();
+            }
+            self.prepared = None;
+            self.sign_task.attempts += 1;
+            self.start_task();
+            Ok(true)
+        } else {
+            Ok(false)
         }
-        let mut pdfhelper = pdfhelper.unwrap();
-
-        let hash = request_hash(
-            &mut pdfhelper,
-            self.sign_task.get_group().certificate().unwrap(),
-        );
-        if hash.is_empty() {
-            self.result = Some(Err("Task failed (invalid PDF)".to_string()));
+    }
+
+    fn start_task(&mut self) {
+        let Some(certificate) = self.sign_task.get_group().certificate() else {
+            error!(
+                "Missing group certificate group_id={}",
+                utils::hextrunc(self.sign_task.get_group().identifier())
+            );
+            self.result = Some(Err("Task failed (missing group certificate)".to_string()));
             return;
-        }
-        self.pdfhelper = Some(pdfhelper);
-        self.sign_task.set_preprocessed(hash);
+        };
+        let prepared = match pdf::signer().prepare(&self.sign_task.data, certificate) {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                error!("Could not prepare PDF error={}", e);
+                metrics::pdf_failed("prepare");
+                self.result = Some(Err("Task failed (invalid PDF)".to_string()));
+                return;
+            }
+        };
+        self.sign_task.set_preprocessed(prepared.hash().to_vec());
+        self.prepared = Some(prepared);
         self.sign_task.start_task();
     }
 
@@ -83,20 +124,27 @@ impl SignPDFTask {
     fn finalize_task(&mut self) {
         self.sign_task.finalize_task();
         if let Some(TaskResult::Signed(signature)) = self.sign_task.get_result() {
-            let signed = include_signature(self.pdfhelper.as_mut().unwrap(), &signature);
-            self.pdfhelper = None;
-
-            info!(
-                "PDF signed by group_id={}",
-                hex::encode(self.sign_task.get_group().identifier())
-            );
-            self.result = Some(Ok(signed));
+            match self.prepared.take().unwrap().finish(&signature) {
+                Ok(signed) => {
+                    info!(
+                        "PDF signed by group_id={}",
+                        hex::encode(self.sign_task.get_group().identifier())
+                    );
+                    self.result = Some(Ok(signed));
+                }
+                Err(e) => {
+                    error!("Could not include PDF signature error={}", e);
+                    metrics::pdf_failed("finish");
+                    self.result = Some(Err("Task failed (signature not included)".to_string()));
+                }
+            }
         } else {
             self.result = Some(Err("Task failed (signature not output)".to_string()));
         }
     }
 
     fn next_round(&mut self) {
+        metrics::round_started(self.sign_task.protocol.get_type());
         if self.sign_task.protocol.round() == 0 {
             self.start_task();
         } else if self.sign_task.protocol.round() < self.sign_task.protocol.last_round() {
@@ -109,14 +157,17 @@ impl SignPDFTask {
 
 impl Task for SignPDFTask {
     fn get_status(&self) -> TaskStatus {
-        self.sign_task.get_status()
+        match &self.result {
+            Some(Err(e)) => TaskStatus::Failed(e.clone()),
+            _ => self.sign_task.get_status(),
+        }
     }
 
     fn get_type(&self) -> TaskType {
         TaskType::SignPdf
     }
 
-    fn get_work(&self, device_id: Option<&[u8]>) -> Option<Vec<u8>> {
+    fn get_work(&self, device_id: Option<&[u8]>) -> Vec<Vec<u8>> {
         self.sign_task.get_work(device_id)
     }
 
@@ -128,11 +179,19 @@ impl Task for SignPDFTask {
         }
     }
 
+    fn get_active_devices(&self) -> Option<Vec<Vec<u8>>> {
+        self.sign_task.get_active_devices()
+    }
+
+    fn get_excluded_devices(&self) -> Vec<Vec<u8>> {
+        self.sign_task.get_excluded_devices()
+    }
+
     fn get_decisions(&self) -> (u32, u32) {
         self.sign_task.get_decisions()
     }
 
-    fn update(&mut self, device_id: &[u8], data: &[u8]) -> Result<bool, String> {
+    fn update(&mut self, device_id: &[u8], data: &[Vec<u8>]) -> Result<bool, String> {
         let result = self.sign_task.update_internal(device_id, data);
         if let Ok(true) = result {
             self.next_round();
@@ -141,22 +200,7 @@ impl Task for SignPDFTask {
     }
 
     fn restart(&mut self) -> Result<bool, String> {
-        self.sign_task.last_update = get_timestamp();
-        if self.result.is_some() {
-            return Ok(false);
-        }
-
-        if self.is_approved() {
-            if let Some(pdfhelper) = self.pdfhelper.as_mut() {
-                pdfhelper.kill().unwrap();
-                self.pdfhelper = None;
-            }
-            self.sign_task.attempts += 1;
-            self.start_task();
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+        self.restart_protocol(true)
     }
 
     fn last_update(&self) -> u64 {
@@ -187,6 +231,10 @@ impl Task for SignPDFTask {
         result
     }
 
+    fn device_decided(&self, device_id: &[u8]) -> bool {
+        self.sign_task.device_decided(device_id)
+    }
+
     fn acknowledge(&mut self, device_id: &[u8]) {
         self.sign_task.acknowledge(device_id);
     }
@@ -202,34 +250,31 @@ impl Task for SignPDFTask {
     fn get_attempts(&self) -> u32 {
         self.sign_task.get_attempts()
     }
-}
-
-fn request_hash(process: &mut Child, certificate: &[u8]) -> Vec<u8> {
-    let process_stdin = process.stdin.as_mut().unwrap();
-    let process_stdout = process.stdout.as_mut().unwrap();
-
-    process_stdin.write_all(certificate).unwrap();
-    process_stdin.flush().unwrap();
 
-    let mut in_buffer = [0u8; 65]; // \n
-    if let Ok(()) = process_stdout.read_exact(&mut in_buffer) {
-        hex::decode(String::from_utf8(Vec::from(&in_buffer[..64])).unwrap()).unwrap()
-    } else {
-        Vec::new()
+    fn get_transcript(&self) -> &Transcript {
+        self.sign_task.get_transcript()
     }
-}
 
-fn include_signature(process: &mut Child, signature: &[u8]) -> Vec<u8> {
-    let process_stdin = process.stdin.as_mut().unwrap();
-    let process_stdout = process.stdout.as_mut().unwrap();
+    fn created_at(&self) -> u64 {
+        self.sign_task.created_at()
+    }
 
-    let mut out_buffer = [0u8; 129];
-    out_buffer[..128].copy_from_slice(hex::encode(signature).as_bytes());
-    out_buffer[128] = b'\n';
+    fn approved_at(&self) -> Option<u64> {
+        self.sign_task.approved_at()
+    }
 
-    process_stdin.write_all(&out_buffer).unwrap();
+    fn terminate(&mut self, status: TaskStatus) -> bool {
+        if self.result.is_some() || !self.sign_task.terminate(status) {
+            return false;
+        }
+        self.prepared = None;
+        true
+    }
 
-    let mut result = Vec::new();
-    process_stdout.read_to_end(&mut result).unwrap();
-    hex::decode(&result).unwrap()
+    fn snapshot(&self) -> TaskSnapshot {
+        TaskSnapshot::SignPdf(SignPDFTaskSnapshot {
+            sign_task: self.sign_task.snapshot_internal(),
+            result: self.result.clone(),
+        })
+    }
 }
@@ -0,0 +1,134 @@
+use crate::proto;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+/// Misbehaviour of a device which hinders a protocol run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FaultKind {
+    /// Sent a message which could not be relayed
+    MalformedMessage,
+    /// Did not send its messages before the protocol run was restarted
+    Stalled,
+    /// Did not output the result of the protocol in the last round
+    MissingOutput,
+}
+
+impl FaultKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FaultKind::MalformedMessage => "malformed_message",
+            FaultKind::Stalled => "stalled",
+            FaultKind::MissingOutput => "missing_output",
+        }
+    }
+}
+
+/// Messages of a device received in a protocol round
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageRecord {
+    pub device_id: Vec<u8>,
+    pub attempt: u32,
+    pub round: u16,
+    /// Hash of the messages of all active shares of the device
+    pub sha256: Vec<u8>,
+}
+
+/// Problem in a protocol round, attributed to the device which caused it if known
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fault {
+    /// Device held responsible; `None` if the fault cannot be attributed to a device
+    pub device_id: Option<Vec<u8>>,
+    pub attempt: u32,
+    pub round: u16,
+    pub kind: FaultKind,
+}
+
+/// Record of the messages and faults of the devices in all protocol runs of a task
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Transcript {
+    messages: Vec<MessageRecord>,
+    faults: Vec<Fault>,
+}
+
+impl Transcript {
+    pub fn record_message(&mut self, device_id: &[u8], attempt: u32, round: u16, data: &[Vec<u8>]) {
+        let mut hasher = sha2::Sha256::new();
+        for message in data {
+            hasher.update(message);
+        }
+        self.messages.push(MessageRecord {
+            device_id: device_id.to_vec(),
+            attempt,
+            round,
+            sha256: hasher.finalize().to_vec(),
+        });
+    }
+
+    /// Attribute a fault to `device_id`; at most one fault of a device is recorded per round
+    pub fn record_fault(&mut self, device_id: &[u8], attempt: u32, round: u16, kind: FaultKind) {
+        if self.faults.iter().any(|fault| {
+            fault.device_id.as_deref() == Some(device_id)
+                && fault.attempt == attempt
+                && fault.round == round
+        }) {
+            return;
+        }
+        self.faults.push(Fault {
+            device_id: Some(device_id.to_vec()),
+            attempt,
+            round,
+            kind,
+        });
+    }
+
+    /// Record a fault which cannot be attributed to any device
+    pub fn record_unattributed_fault(&mut self, attempt: u32, round: u16, kind: FaultKind) {
+        self.faults.push(Fault {
+            device_id: None,
+            attempt,
+            round,
+            kind,
+        });
+    }
+
+    pub fn messages(&self) -> &[MessageRecord] {
+        &self.messages
+    }
+
+    /// Faults in the order in which they were recorded
+    pub fn faults(&self) -> &[Fault] {
+        &self.faults
+    }
+}
+
+impl From<FaultKind> for proto::FaultKind {
+    fn from(kind: FaultKind) -> Self {
+        match kind {
+            FaultKind::MalformedMessage => proto::FaultKind::MalformedMessage,
+            FaultKind::Stalled => proto::FaultKind::Stalled,
+            FaultKind::MissingOutput => proto::FaultKind::MissingOutput,
+        }
+    }
+}
+
+impl From<&Fault> for proto::Fault {
+    fn from(fault: &Fault) -> Self {
+        proto::Fault {
+            device_id: fault.device_id.clone().unwrap_or_default(),
+            attempt: fault.attempt,
+            round: fault.round.into(),
+            kind: proto::FaultKind::from(fault.kind).into(),
+        }
+    }
+}
+
+impl From<&MessageRecord> for proto::MessageRecord {
+    fn from(record: &MessageRecord) -> Self {
+        proto::MessageRecord {
+            device_id: record.device_id.clone(),
+            attempt: record.attempt,
+            round: record.round.into(),
+            sha256: record.sha256.clone(),
+        }
+    }
+}
@@ -0,0 +1,569 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request, Uri};
+use log::{error, info, warn};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use uuid::Uuid;
+
+use crate::interfaces::grpc::format_result;
+use crate::proto as msg;
+use crate::tasks::{Task, TaskStatus};
+use crate::{get_timestamp, utils};
+
+/// Time limit of a single delivery attempt
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Task lifecycle event reported to webhooks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EventKind {
+    #[serde(rename = "task_created")]
+    Created,
+    /// All required devices approved the task and the protocol started
+    #[serde(rename = "task_approved")]
+    Approved,
+    #[serde(rename = "task_finished")]
+    Finished,
+    /// Task failed, expired or was cancelled
+    #[serde(rename = "task_failed")]
+    Failed,
+}
+
+impl EventKind {
+    /// Event caused by the change of a task status from `previous` to `current`
+    pub fn from_transition(previous: &TaskStatus, current: &TaskStatus) -> Option<Self> {
+        if previous.has_ended() {
+            return None;
+        }
+        match current {
+            TaskStatus::Running(_) if *previous == TaskStatus::Created => Some(Self::Approved),
+            TaskStatus::Finished => Some(Self::Finished),
+            TaskStatus::Failed(_) | TaskStatus::Expired | TaskStatus::Cancelled => {
+                Some(Self::Failed)
+            }
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "task_created",
+            Self::Approved => "task_approved",
+            Self::Finished => "task_finished",
+            Self::Failed => "task_failed",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Webhook {
+    /// Endpoint receiving the events as HTTP POST requests
+    url: String,
+    /// Key of the HMAC-SHA256 signature sent in the `X-MeeSign-Signature` header,
+    /// computed over `<X-MeeSign-Timestamp>.<body>`
+    secret: String,
+    /// Events sent to the endpoint; all events if missing
+    events: Option<Vec<EventKind>>,
+}
+
+impl Webhook {
+    fn accepts(&self, event: EventKind) -> bool {
+        self.events
+            .as_ref()
+            .is_none_or(|events| events.contains(&event))
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_retry_delay() -> u64 {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct WebhookFile {
+    #[serde(default, rename = "webhook")]
+    webhooks: Vec<Webhook>,
+    /// JSON-lines file recording the outcome of every delivery
+    delivery_log: Option<PathBuf>,
+    #[serde(default = "default_max_attempts")]
+    max_attempts: u32,
+    /// Seconds before the first retry, doubled after each further attempt
+    #[serde(default = "default_retry_delay")]
+    retry_delay: u64,
+}
+
+/// Task data sent with an event; identifiers are hex-encoded
+///
+/// Plaintexts and signed PDFs are left out, the receiver can fetch them with `GetTaskResult`.
+#[derive(Serialize)]
+struct TaskPayload {
+    task_id: String,
+    task_type: &'static str,
+    state: &'static str,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Payload {
+    id: String,
+    event: EventKind,
+    timestamp: u64,
+    task: TaskPayload,
+}
+
+/// Event serialized for delivery to all webhooks accepting it
+#[derive(Debug)]
+struct Notification {
+    id: String,
+    event: EventKind,
+    task_id: Uuid,
+    body: Vec<u8>,
+}
+
+impl Notification {
+    fn new(event: EventKind, task_id: &Uuid, task: &dyn Task) -> Self {
+        use msg::task_result::Result as Outcome;
+
+        let result = format_result(task_id, task);
+        let state = result.state().as_str_name();
+        let (signature, error) = match result.result {
+            Some(Outcome::Signature(signature)) => (Some(hex::encode(signature)), None),
+            Some(Outcome::Error(error)) => (None, Some(error)),
+            _ => (None, None),
+        };
+        let id = Uuid::new_v4().to_string();
+        let payload = Payload {
+            id: id.clone(),
+            event,
+            timestamp: get_timestamp(),
+            task: TaskPayload {
+                task_id: hex::encode(task_id.as_bytes()),
+                task_type: task.get_type().as_str_name(),
+                state,
+                name: result.name,
+                group_id: result.group_id.map(hex::encode),
+                signature,
+                error,
+            },
+        };
+        Notification {
+            id,
+            event,
+            task_id: *task_id,
+            body: serde_json::to_vec(&payload).unwrap(),
+        }
+    }
+}
+
+/// Outcome of delivering a notification to a webhook, as recorded in the delivery log
+#[derive(Debug, Serialize)]
+struct Delivery<'a> {
+    delivery_id: &'a str,
+    timestamp: u64,
+    url: &'a str,
+    event: EventKind,
+    task_id: String,
+    attempts: u32,
+    delivered: bool,
+    /// HTTP status of the last response
+    status: Option<u16>,
+    /// Error of the last attempt
+    error: Option<String>,
+}
+
+/// Hex-encoded HMAC-SHA256 of `timestamp` and `body` joined by a dot
+///
+/// Binding the timestamp to the signature lets receivers reject replayed requests:
+/// they should accept only requests whose `X-MeeSign-Timestamp` is within 5 minutes
+/// of their clock. Each delivery attempt is signed with its own timestamp.
+fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let key = PKey::hmac(secret.as_bytes()).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(timestamp.to_string().as_bytes()).unwrap();
+    signer.update(b".").unwrap();
+    signer.update(body).unwrap();
+    hex::encode(signer.sign_to_vec().unwrap())
+}
+
+/// Configured webhooks and their HTTP client
+pub struct Webhooks {
+    webhooks: Vec<Webhook>,
+    delivery_log: Option<Mutex<File>>,
+    max_attempts: u32,
+    retry_delay: Duration,
+    client: Client<HttpConnector>,
+}
+
+impl Webhooks {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let webhooks = Self::parse(&content)
+            .map_err(|e| format!("Invalid webhooks {}: {}", path.display(), e))?;
+        info!("Loaded {} webhooks", webhooks.webhooks.len());
+        Ok(webhooks)
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let file: WebhookFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        for webhook in &file.webhooks {
+            let uri: Uri = webhook
+                .url
+                .parse()
+                .map_err(|_| format!("Webhook {} has an invalid URL", webhook.url))?;
+            // TLS is left to a reverse proxy in front of the receiver
+            if uri.scheme_str() != Some("http") {
+                return Err(format!("Webhook {} does not use http", webhook.url));
+            }
+            if webhook.secret.is_empty() {
+                return Err(format!("Webhook {} has an empty secret", webhook.url));
+            }
+        }
+        if file.max_attempts == 0 {
+            return Err(String::from("max-attempts must be positive"));
+        }
+        let delivery_log = match &file.delivery_log {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("Unable to open {}: {}", path.display(), e))?,
+            )),
+            None => None,
+        };
+        Ok(Webhooks {
+            webhooks: file.webhooks,
+            delivery_log,
+            max_attempts: file.max_attempts,
+            retry_delay: Duration::from_secs(file.retry_delay),
+            client: Client::new(),
+        })
+    }
+
+    /// Deliver `notification` to every webhook accepting its event, each in its own task
+    fn dispatch(self: &Arc<Self>, notification: Notification) {
+        let notification = Arc::new(notification);
+        for (index, webhook) in self.webhooks.iter().enumerate() {
+            if !webhook.accepts(notification.event) {
+                continue;
+            }
+            let webhooks = self.clone();
+            let notification = notification.clone();
+            tokio::spawn(async move {
+                webhooks
+                    .deliver(&webhooks.webhooks[index], &notification)
+                    .await
+            });
+        }
+    }
+
+    /// Send `notification` to `webhook` until it responds with a success status or
+    /// the attempts run out, and record the outcome to the delivery log
+    ///
+    /// # Returns
+    /// `true` if the notification has been delivered.
+    async fn deliver(&self, webhook: &Webhook, notification: &Notification) -> bool {
+        let mut delay = self.retry_delay;
+        let mut delivery = Delivery {
+            delivery_id: &notification.id,
+            timestamp: 0,
+            url: &webhook.url,
+            event: notification.event,
+            task_id: hex::encode(notification.task_id.as_bytes()),
+            attempts: 0,
+            delivered: false,
+            status: None,
+            error: None,
+        };
+        while delivery.attempts < self.max_attempts {
+            if delivery.attempts > 0 {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            delivery.attempts += 1;
+            let timestamp = get_timestamp();
+            let signature = format!(
+                "sha256={}",
+                sign(&webhook.secret, timestamp, &notification.body)
+            );
+            let request = Request::post(&webhook.url)
+                .header("content-type", "application/json")
+                .header("x-meesign-event", notification.event.as_str())
+                .header("x-meesign-delivery", &notification.id)
+                .header("x-meesign-timestamp", timestamp)
+                .header("x-meesign-signature", &signature)
+                .body(Body::from(notification.body.clone()))
+                .unwrap();
+            match tokio::time::timeout(ATTEMPT_TIMEOUT, self.client.request(request)).await {
+                Ok(Ok(response)) => {
+                    delivery.status = Some(response.status().as_u16());
+                    delivery.error = None;
+                    if response.status().is_success() {
+                        delivery.delivered = true;
+                        break;
+                    }
+                }
+                Ok(Err(e)) => {
+                    delivery.status = None;
+                    delivery.error = Some(e.to_string());
+                }
+                Err(_) => {
+                    delivery.status = None;
+                    delivery.error = Some(String::from("Request timed out"));
+                }
+            }
+        }
+        delivery.timestamp = get_timestamp();
+
+        if !delivery.delivered {
+            warn!(
+                "Webhook delivery failed url={} event={} task_id={} status={:?} error={:?}",
+                webhook.url,
+                notification.event.as_str(),
+                utils::hextrunc(notification.task_id.as_bytes()),
+                delivery.status,
+                delivery.error
+            );
+        }
+        self.record(&delivery);
+        delivery.delivered
+    }
+
+    fn record(&self, delivery: &Delivery) {
+        let Some(log) = &self.delivery_log else {
+            return;
+        };
+        let mut line = serde_json::to_string(delivery).unwrap();
+        line.push('\n');
+        if let Err(e) = log.lock().unwrap().write_all(line.as_bytes()) {
+            error!(
+                "Could not write webhook delivery delivery_id={} error={}",
+                delivery.delivery_id, e
+            );
+        }
+    }
+}
+
+static NOTIFICATIONS: OnceLock<UnboundedSender<Notification>> = OnceLock::new();
+
+/// Start delivering task events to the webhooks configured in `path`; must be called
+/// within the runtime
+pub fn init(path: &Path) -> Result<(), String> {
+    let webhooks = Arc::new(Webhooks::load(path)?);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    if NOTIFICATIONS.set(tx).is_err() {
+        panic!("Webhooks already initialized");
+    }
+    tokio::spawn(async move {
+        while let Some(notification) = rx.recv().await {
+            webhooks.dispatch(notification);
+        }
+    });
+    Ok(())
+}
+
+fn notify(event: EventKind, task_id: &Uuid, task: &dyn Task) {
+    if let Some(tx) = NOTIFICATIONS.get() {
+        let _ = tx.send(Notification::new(event, task_id, task));
+    }
+}
+
+/// Report a newly created task, if webhooks are configured
+pub fn task_created(task_id: &Uuid, task: &dyn Task) {
+    notify(EventKind::Created, task_id, task);
+}
+
+/// Report the change of `task` since it had `previous_status`, if webhooks are configured
+/// and the change is an event
+pub fn task_changed(task_id: &Uuid, task: &dyn Task, previous_status: &TaskStatus) {
+    if let Some(event) = EventKind::from_transition(previous_status, &task.get_status()) {
+        notify(event, task_id, task);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server, StatusCode};
+    use std::convert::Infallible;
+
+    #[test]
+    fn transitions() {
+        let event = EventKind::from_transition;
+        assert_eq!(
+            event(&TaskStatus::Created, &TaskStatus::Running(0)),
+            Some(EventKind::Approved)
+        );
+        assert_eq!(
+            event(&TaskStatus::Running(1), &TaskStatus::Running(2)),
+            None
+        );
+        assert_eq!(
+            event(&TaskStatus::Running(2), &TaskStatus::Finished),
+            Some(EventKind::Finished)
+        );
+        assert_eq!(
+            event(&TaskStatus::Created, &TaskStatus::Failed("declined".into())),
+            Some(EventKind::Failed)
+        );
+        assert_eq!(
+            event(&TaskStatus::Running(0), &TaskStatus::Cancelled),
+            Some(EventKind::Failed)
+        );
+        assert_eq!(event(&TaskStatus::Finished, &TaskStatus::Finished), None);
+    }
+
+    #[test]
+    fn parse() {
+        let webhooks = Webhooks::parse(
+            r#"
+            [[webhook]]
+            url = "http://127.0.0.1:8080/events"
+            secret = "secret"
+
+            [[webhook]]
+            url = "http://127.0.0.1:8081/"
+            secret = "secret"
+            events = ["task_finished", "task_failed"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(webhooks.max_attempts, 5);
+        assert!(webhooks.webhooks[0].accepts(EventKind::Created));
+        assert!(!webhooks.webhooks[1].accepts(EventKind::Approved));
+        assert!(webhooks.webhooks[1].accepts(EventKind::Failed));
+
+        let invalid = |webhook: &str| Webhooks::parse(&format!("[[webhook]]\n{}", webhook));
+        assert!(invalid("url = \"https://example.com\"\nsecret = \"secret\"").is_err());
+        assert!(invalid("url = \"http://example.com\"\nsecret = \"\"").is_err());
+        assert!(invalid("url = \"http://example.com\"\nsecret = \"s\"\nevents = [\"x\"]").is_err());
+    }
+
+    /// Stub endpoint failing the first request; returns the received timestamps,
+    /// signatures and bodies
+    async fn stub_endpoint() -> (String, Arc<Mutex<Vec<(u64, String, Vec<u8>)>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let requests = received.clone();
+        let service = make_service_fn(move |_| {
+            let requests = requests.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let requests = requests.clone();
+                    async move {
+                        let timestamp = request.headers()["x-meesign-timestamp"]
+                            .to_str()
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        let signature = request.headers()["x-meesign-signature"]
+                            .to_str()
+                            .unwrap()
+                            .to_string();
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let mut requests = requests.lock().unwrap();
+                        requests.push((timestamp, signature, body.to_vec()));
+                        let status = match requests.len() {
+                            1 => StatusCode::INTERNAL_SERVER_ERROR,
+                            _ => StatusCode::OK,
+                        };
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .body(Body::empty())
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let url = format!("http://{}/events", server.local_addr());
+        tokio::spawn(server);
+        (url, received)
+    }
+
+    fn notification() -> Notification {
+        Notification {
+            id: Uuid::new_v4().to_string(),
+            event: EventKind::Finished,
+            task_id: Uuid::nil(),
+            body: br#"{"event":"task_finished"}"#.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn delivery() {
+        let (url, received) = stub_endpoint().await;
+        let log = tempfile::NamedTempFile::new().unwrap();
+        let mut webhooks = Webhooks::parse(&format!(
+            "delivery-log = {:?}\nmax-attempts = 3\n[[webhook]]\nurl = {:?}\nsecret = \"secret\"",
+            log.path(),
+            url
+        ))
+        .unwrap();
+        webhooks.retry_delay = Duration::from_millis(10);
+
+        let notification = notification();
+        assert!(webhooks.deliver(&webhooks.webhooks[0], &notification).await);
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+        for (timestamp, signature, body) in &received {
+            assert_eq!(body, &notification.body);
+            assert!(get_timestamp().abs_diff(*timestamp) <= 300);
+            assert_eq!(
+                signature,
+                &format!("sha256={}", sign("secret", *timestamp, &notification.body))
+            );
+            // the signature does not verify with a different timestamp
+            assert_ne!(
+                signature,
+                &format!(
+                    "sha256={}",
+                    sign("secret", timestamp + 1, &notification.body)
+                )
+            );
+        }
+
+        // an unreachable endpoint is given up after the configured attempts
+        let unreachable = Webhook {
+            url: String::from("http://127.0.0.1:1/"),
+            secret: String::from("secret"),
+            events: None,
+        };
+        assert!(!webhooks.deliver(&unreachable, &notification).await);
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(log.path())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["attempts"], 2);
+        assert_eq!(records[0]["delivered"], true);
+        assert_eq!(records[0]["status"], 200);
+        assert_eq!(records[1]["attempts"], 3);
+        assert_eq!(records[1]["delivered"], false);
+        assert!(records[1]["error"].is_string());
+    }
+}